@@ -1,15 +1,15 @@
-use std::{any::{TypeId, type_name}, collections::{HashMap, LinkedList, VecDeque, HashSet}, sync::Arc};
+use std::{any::{TypeId, type_name}, collections::{HashMap, HashSet}, sync::Arc};
 
 // use crate::types::SystemId;
 
-use crossbeam::channel::bounded;
+use crossbeam::channel::{unbounded, Sender};
 use tokio::{runtime::Handle, task::JoinHandle, sync::{Mutex, RwLock}};
 
 // use crate::{types::{AddSystemResult, AddSystemError, ComponentId, ArchetypeType, BuildSystemResult, BuildSystemError}, data::{EcsDataManager, entity_data_accessor::ArchetypeDataAccessorBuilder}};
 
-use crate::{data::{EcsDataManager, entity_data_accessor::ChunkDataAccessor}, types::{ComponentId, BuildSystemError, BuildSystemResult}};
+use crate::{data::{EcsDataManager, entity_data_accessor::ChunkDataAccessor, commands::Commands, archetype::{MAX_CHANGE_AGE, check_tick}}, types::{BuildSystemError, BuildSystemResult, QueryResult, UpdateError}};
 
-use self::{system::{SystemType, IBlockingSystemHandler, IMultithreadSystemHandler}/* , job::Job */};
+use self::{system::{SystemType, IBlockingSystemHandler, IMultithreadSystemHandler, ResourcesAccess}/* , job::Job */, query::{ArchetypeQuery, ComponentsAccess}};
 
 pub mod system;
 pub mod job;
@@ -20,22 +20,43 @@ pub struct SystemInfo {
     system_type_id: TypeId,
     system: SystemType,
     disabled: bool,
+    // порядок регистрации, определяет порядок применения команд системы
+    order: usize,
+    resources_access: ResourcesAccess,
+    // такт предыдущего запуска, None до первого запуска
+    last_run_tick: Option<u32>,
 }
 
 impl SystemInfo {
-    pub fn new_blocking<TSystem: IBlockingSystemHandler + 'static>(system: TSystem) -> Self {
+    pub fn new_blocking<TSystem: IBlockingSystemHandler + 'static>(system: TSystem, order: usize) -> Self {
         Self {
             system_type_id: TypeId::of::<TSystem>(),
+            resources_access: system.resources_access(),
             system: SystemType::BlockingSystem(Box::new(system)),
             disabled: true,
+            order,
+            last_run_tick: None,
         }
     }
 
-    pub fn new_multithread<TSystem: IMultithreadSystemHandler + Sync + Send + 'static>(system: TSystem) -> Self {
+    pub fn new_multithread<TSystem: IMultithreadSystemHandler + Sync + Send + 'static>(system: TSystem, order: usize) -> Self {
         Self {
             system_type_id: TypeId::of::<TSystem>(),
+            resources_access: system.resources_access(),
             system: SystemType::MultithreadSystem(Arc::new(Mutex::new(system))),
             disabled: true,
+            order,
+            last_run_tick: None,
+        }
+    }
+}
+
+impl SystemInfo {
+    /// Многопоточная система захватывается только на время вызова
+    fn archetype_query(&self, rt_handle: &Handle) -> ArchetypeQuery {
+        match &self.system {
+            SystemType::BlockingSystem(system) => system.archetype_query(),
+            SystemType::MultithreadSystem(system) => rt_handle.block_on(system.lock()).archetype_query(),
         }
     }
 }
@@ -46,6 +67,7 @@ pub struct EcsBehaviorManager {
     next_systems_links: HashMap<TypeId, HashSet<TypeId>>,
     prev_systems_links: HashMap<TypeId, HashSet<TypeId>>,
     pure_systems: HashSet<TypeId>,
+    systems_order_count: usize,
 }
 
 // add cycle ref check
@@ -53,7 +75,7 @@ impl EcsBehaviorManager {
     pub fn check_disabled_systems(&mut self) {
         let disabled_systems_type_id = self.systems_info.keys()
             .filter_map(|system_type_id| {
-                if let Some(prev_systems_links) = self.prev_systems_links.get(&system_type_id) {
+                if let Some(prev_systems_links) = self.prev_systems_links.get(system_type_id) {
                     if prev_systems_links.iter().all(|prev_system_link| self.systems_info.contains_key(prev_system_link)) {
                         return None;
                     }
@@ -63,17 +85,13 @@ impl EcsBehaviorManager {
             }).collect::<Vec<_>>();
 
         self.systems_info.values_mut().for_each(|system_info| {
-            if disabled_systems_type_id.contains(&system_info.system_type_id) {
-                system_info.disabled = true;
-            } else {
-                system_info.disabled = false;
-            }
+            system_info.disabled = disabled_systems_type_id.contains(&system_info.system_type_id);
         })
     }
 
     pub fn remove_system<TSystem: 'static>(&mut self) {
         _ = self.systems_info.remove(&TypeId::of::<TSystem>());
-        _ = self.pure_systems.drain_filter(|item| *item == TypeId::of::<TSystem>());
+        _ = self.pure_systems.remove(&TypeId::of::<TSystem>());
         self.check_disabled_systems();
     }
 
@@ -93,126 +111,116 @@ impl EcsBehaviorManager {
             }
 
             system_prev_systems = system_prev_systems.into_iter().filter_map(|system_prev_system| -> _ {
-                    self.prev_systems_links.get(&system_prev_system)
+                    self.prev_systems_links.get(system_prev_system)
                 })
                 .flat_map(|system_prev_system| system_prev_system.iter())
                 .collect::<HashSet<_>>();
         }
 
-        return true;
+        true
     }
 
-    pub fn update(&mut self, ecs_data_manager: Arc<RwLock<EcsDataManager>>, rt_handle: Handle) {
+    /// Системы получают доступ к чанкам своих запросов и записывают структурные изменения в собственные буферы команд.
+    /// Буферы применяются после завершения всех систем, в порядке регистрации систем, независимо от порядка завершения.
+    ///
+    /// Каждый запуск системы получает свой такт, изменения колонок системой помечаются им. Фильтры `added`/`changed` запроса
+    /// сравниваются с тактом предыдущего запуска системы. Команды применяются в новом такте, после тактов всех систем.
+    ///
+    /// Системы, конфликтующие по ресурсам или компонентам запросов, не выполняются одновременно. Возвращает ошибки запросов пропущенных систем,
+    /// ошибки команд систем и команд хуков
+    pub fn update(&mut self, ecs_data_manager: Arc<RwLock<EcsDataManager>>, rt_handle: Handle) -> Vec<UpdateError> {
         if self.pure_systems.is_empty() {
-            return;
+            return Vec::new();
         }
 
-        let ecs_data_manager_write_lock = ecs_data_manager.blocking_write(); 
+        let mut ecs_data_manager_write_lock = ecs_data_manager.blocking_write(); 
+
+        let update_tick = ecs_data_manager_write_lock.begin_update();
+
+        // такты систем, не запускавшихся долго, прижимаются так же, как такты колонок
+        self.systems_info.values_mut()
+            .filter_map(|system_info| system_info.last_run_tick.as_mut())
+            .for_each(|last_run_tick| check_tick(last_run_tick, update_tick));
+
+        // события, отправленные системами, видны системам, запускаемым после отправителя, и всем системам следующего обновления
+        ecs_data_manager_write_lock.update_events();
 
         let mut system_requirements = self.prev_systems_links.clone();
 
-        let (end_job_sender, end_job_receiver) = bounded::<TypeId>(1);
+        // блокирующие системы отправляют результат из текущего потока, канал не должен ограничивать отправку
+        let (end_job_sender, end_job_receiver) = unbounded::<(TypeId, Commands)>();
 
         let mut join_handlers = Vec::with_capacity(self.systems_info.len());
 
-        let mut job_in_process_count = 0;
-
-        let mut components_used_as_read = HashSet::<ComponentId>::new();
-        let mut components_used_as_write = HashSet::<ComponentId>::new();
-        let mut pending_services = HashMap::<ComponentId, Vec<TypeId>>::new();
-
-        self.pure_systems.iter().for_each(|pure_system_type_id| {
-            let system_info = self.systems_info.get_mut(pure_system_type_id).unwrap();
-            
-            match &mut system_info.system {
-                SystemType::BlockingSystem(system) => {
-                    let query = system.archetype_query();
-
-                    ecs_data_manager_write_lock.archetype_map.values().for_each(|archetype| {
-                        if query.is_archetype_match(archetype.archetype_type()) {
-                            let archetype_data_accessor = ChunkDataAccessor::default();
-                            
-                            query.required
-
-                            archetype.get_chunks().for_each(|chunk| {
-                                archetype_data_accessor.fill_data_from_chunk(, chunk)
-                            });
-                            
-                        }
-                    });
-
-                    rt_handle.block_on(async {
-                        system.handle().await;
-                    })
-                },
-                SystemType::MultithreadSystem(system) => {
-                    let system_end_sender = end_job_sender.clone();
-                    let system_type_id = *pure_system_type_id;
-
-                    let system = system.clone();
-
-                    let join_handler = rt_handle.spawn(async move {
-                        system.lock().await.handle().await;
-    
-                        system_end_sender.send(system_type_id).unwrap();
-                    });
-
-                    join_handlers.push(join_handler);
-                
-                    job_in_process_count += 1;
-                },
+        let mut system_commands = Vec::<(usize, Commands)>::with_capacity(self.systems_info.len());
+
+        // системы, готовые к запуску, в порядке регистрации
+        let mut pending_systems = self.pure_systems.iter().copied().collect::<Vec<_>>();
+        pending_systems.sort_by_key(|system_type_id| self.systems_info[system_type_id].order);
+
+        // запросы ожидающих систем, запрашиваются у системы один раз за обновление
+        let mut pending_queries = HashMap::<TypeId, ArchetypeQuery>::new();
+
+        // доступ к ресурсам и компонентам выполняющихся систем
+        let mut running_systems = HashMap::<TypeId, (ResourcesAccess, ComponentsAccess)>::new();
+
+        let mut update_errors = Vec::new();
+
+        loop {
+            // система, конфликтующая по ресурсам или компонентам с выполняющимися, ждет их завершения
+            pending_systems.retain(|pending_system_type_id| {
+                let system_info = self.systems_info.get_mut(pending_system_type_id).unwrap();
+
+                let components_access = pending_queries.entry(*pending_system_type_id)
+                    .or_insert_with(|| system_info.archetype_query(&rt_handle))
+                    .components_access();
+
+                let is_conflicting = running_systems.values().any(|(resources_access, running_components_access)| {
+                    resources_access.is_conflicting(&system_info.resources_access) || running_components_access.is_conflicting(&components_access)
+                });
+
+                if is_conflicting {
+                    return true;
+                }
+
+                running_systems.insert(*pending_system_type_id, (system_info.resources_access.clone(), components_access));
+
+                let query = pending_queries.remove(pending_system_type_id).unwrap();
+                let change_tick = ecs_data_manager_write_lock.increment_change_tick();
+
+                match start_system(system_info, query, &ecs_data_manager_write_lock, change_tick, &rt_handle, &end_job_sender) {
+                    Ok(join_handler) => join_handlers.extend(join_handler),
+                    Err(error) => update_errors.push(UpdateError::Query { system_type_id: *pending_system_type_id, source: error }),
+                }
+
+                false
+            });
+
+            if running_systems.is_empty() {
+                break;
             }
-        });
 
-        while job_in_process_count != 0 {
-            job_in_process_count -= 1;
-            let finished_system_type_id = end_job_receiver.recv().unwrap();
+            let (finished_system_type_id, commands) = end_job_receiver.recv().unwrap();
+
+            running_systems.remove(&finished_system_type_id);
 
             let finished_system_info = self.systems_info.get(&finished_system_type_id).unwrap();
 
-            let systems_ready_to_start_opt = self.next_systems_links.get(&finished_system_type_id).map(|next_systems| {
-                next_systems.iter().filter_map(|next_system| {
+            system_commands.push((finished_system_info.order, commands));
+
+            if let Some(next_systems) = self.next_systems_links.get(&finished_system_type_id) {
+                next_systems.iter().for_each(|next_system| {
                     if let Some(next_system_requirements) = system_requirements.get_mut(next_system) {
                         next_system_requirements.remove(&finished_system_type_id);
-    
+
                         if next_system_requirements.is_empty() {
-                            return Some(*next_system);
+                            pending_systems.push(*next_system);
                         }
                     }
-    
-                    return None;
-                }).collect::<Vec<_>>()
-            });
-            
-            systems_ready_to_start_opt.map(|systems_ready_to_start| {
-                systems_ready_to_start.iter().for_each(|next_system| {
-                    let system_info = self.systems_info.get_mut(&next_system).unwrap();
-    
-                    match &mut system_info.system {
-                        SystemType::BlockingSystem(system) => {
-                            rt_handle.block_on(async {
-                                system.handle().await;
-                            })
-                        },
-                        SystemType::MultithreadSystem(system) => {
-                            let system_end_sender = end_job_sender.clone();
-                            let system_type_id = *next_system;
-        
-                            let system = system.clone();
+                });
 
-                            let join_handler = rt_handle.spawn(async move {
-                                system.lock().await.handle().await;
-            
-                                system_end_sender.send(system_type_id).unwrap();
-                            });
-        
-                            join_handlers.push(join_handler);
-                        
-                            job_in_process_count += 1;
-                        },
-                    }
-                })
-            });
+                pending_systems.sort_by_key(|system_type_id| self.systems_info[system_type_id].order);
+            }
         }
 
         rt_handle.block_on(async move {
@@ -220,6 +228,94 @@ impl EcsBehaviorManager {
                 system_job.await.unwrap();
             }
         });
+
+        // точка синхронизации: все системы завершены, доступ к данным чанков освобожден
+        system_commands.sort_by_key(|(order, _)| *order);
+
+        ecs_data_manager_write_lock.increment_change_tick();
+
+        system_commands.into_iter().for_each(|(_, mut commands)| {
+            let command_errors = commands.apply(&mut ecs_data_manager_write_lock);
+            update_errors.extend(command_errors.into_iter().map(UpdateError::from));
+        });
+
+        update_errors.extend(ecs_data_manager_write_lock.take_command_errors().into_iter().map(UpdateError::from));
+
+        update_errors
+    }
+}
+
+/// Доступ к каждому подходящему под запрос чанку
+fn chunk_data_accessors(ecs_data_manager: &EcsDataManager, query: ArchetypeQuery, resources_access: &ResourcesAccess, change_tick: u32, last_run_tick: u32) -> QueryResult<Vec<ChunkDataAccessor>> {
+    let query = query.with_last_run_tick(last_run_tick);
+
+    let select_components = query.selected_components();
+
+    let mut chunk_data_accessors = ecs_data_manager.query_chunk_rows(&query)?.into_iter().map(|(chunk, rows)| {
+        let mut chunk_data_accessor = ChunkDataAccessor::default();
+        chunk_data_accessor.fill_data_from_chunk(select_components.clone(), chunk, rows, ecs_data_manager, change_tick, last_run_tick);
+        chunk_data_accessor.fill_resources(&resources_access.resources, ecs_data_manager);
+        chunk_data_accessor
+    }).collect::<Vec<_>>();
+
+    // система без сущностей вызывается один раз, только с ресурсами
+    if query.resources_only {
+        let mut chunk_data_accessor = ChunkDataAccessor::default();
+        chunk_data_accessor.fill_ticks(change_tick, last_run_tick);
+        chunk_data_accessor.fill_resources(&resources_access.resources, ecs_data_manager);
+        chunk_data_accessors.push(chunk_data_accessor);
+    }
+
+    Ok(chunk_data_accessors)
+}
+
+/// Запуск системы по всем чанкам ее запроса, система с `ArchetypeQuery::resources_only` вызывается один раз. Блокирующая система выполняется сразу, многопоточная - в задаче рантайма.
+/// По завершении система отправляет в канал свой идентификатор и буфер команд. Система, запрос которой не выполнен, пропускается,
+/// но тоже отправляет идентификатор, чтобы зависимые системы были запущены.
+/// До первого запуска система видит добавленными и измененными все компоненты, кроме прижатых к MAX_CHANGE_AGE
+fn start_system(system_info: &mut SystemInfo, query: ArchetypeQuery, ecs_data_manager: &EcsDataManager, change_tick: u32, rt_handle: &Handle, end_job_sender: &Sender<(TypeId, Commands)>) -> QueryResult<Option<JoinHandle<()>>> {
+    let system_type_id = system_info.system_type_id;
+    let last_run_tick = system_info.last_run_tick.unwrap_or(change_tick.wrapping_sub(MAX_CHANGE_AGE));
+
+    let chunk_data_accessors = match chunk_data_accessors(ecs_data_manager, query, &system_info.resources_access, change_tick, last_run_tick) {
+        Ok(chunk_data_accessors) => chunk_data_accessors,
+        Err(error) => {
+            end_job_sender.send((system_type_id, Commands::new())).unwrap();
+            return Err(error);
+        },
+    };
+
+    system_info.last_run_tick = Some(change_tick);
+
+    match &mut system_info.system {
+        SystemType::BlockingSystem(system) => {
+            let mut commands = Commands::new();
+
+            rt_handle.block_on(async {
+                for chunk_data_accessor in chunk_data_accessors {
+                    system.handle(chunk_data_accessor, &mut commands).await;
+                }
+            });
+
+            end_job_sender.send((system_type_id, commands)).unwrap();
+
+            Ok(None)
+        },
+        SystemType::MultithreadSystem(system) => {
+            let system_end_sender = end_job_sender.clone();
+            let system = system.clone();
+
+            Ok(Some(rt_handle.spawn(async move {
+                let mut commands = Commands::new();
+                let mut system = system.lock().await;
+
+                for chunk_data_accessor in chunk_data_accessors {
+                    system.handle(chunk_data_accessor, &mut commands).await;
+                }
+
+                system_end_sender.send((system_type_id, commands)).unwrap();
+            })))
+        },
     }
 }
 
@@ -245,12 +341,12 @@ impl<'a> SystemBuilder<'a> {
     }
 
     pub fn result_for_system<TNextSystem: 'static>(&mut self) -> &mut Self {
-        self.prev_systems.insert(TypeId::of::<TNextSystem>());
+        self.next_systems.insert(TypeId::of::<TNextSystem>());
         self
     }
 
     pub fn build_with_sync_handler<TSystem: IBlockingSystemHandler + 'static>(self, system: TSystem) -> BuildSystemResult<()> {
-        if self.ecs_behavior_manager.check_links(&self.prev_systems, &self.next_systems) {
+        if !self.ecs_behavior_manager.check_links(&self.prev_systems, &self.next_systems) {
             return Err(BuildSystemError::CycledSystemLinks { system_name: type_name::<TSystem>().to_string(), system_type_id: TypeId::of::<TSystem>() })
         }
 
@@ -283,16 +379,18 @@ impl<'a> SystemBuilder<'a> {
         
         self.ecs_behavior_manager.systems_info.insert(
             TypeId::of::<TSystem>(),
-            SystemInfo::new_blocking::<TSystem>(system)
+            SystemInfo::new_blocking::<TSystem>(system, self.ecs_behavior_manager.systems_order_count)
         );
 
+        self.ecs_behavior_manager.systems_order_count += 1;
+
         self.ecs_behavior_manager.check_disabled_systems();
 
         Ok(())
     }
 
     pub fn build_with_muitithread_handler<TSystem: IMultithreadSystemHandler + Sync + Send + 'static>(self, system: TSystem) -> BuildSystemResult<()> {
-        if self.ecs_behavior_manager.check_links(&self.prev_systems, &self.next_systems) {
+        if !self.ecs_behavior_manager.check_links(&self.prev_systems, &self.next_systems) {
             return Err(BuildSystemError::CycledSystemLinks { system_name: type_name::<TSystem>().to_string(), system_type_id: TypeId::of::<TSystem>() })
         }
 
@@ -325,11 +423,239 @@ impl<'a> SystemBuilder<'a> {
         
         self.ecs_behavior_manager.systems_info.insert(
             TypeId::of::<TSystem>(),
-            SystemInfo::new_multithread::<TSystem>(system)
+            SystemInfo::new_multithread::<TSystem>(system, self.ecs_behavior_manager.systems_order_count)
         );
 
+        self.ecs_behavior_manager.systems_order_count += 1;
+
         self.ecs_behavior_manager.check_disabled_systems();
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, sync::{Arc, Mutex, atomic::{AtomicUsize, Ordering}}};
+
+    use tokio::sync::RwLock;
+
+    use crate::{data::{EcsDataManager, entity_data_accessor::ChunkDataAccessor, commands::Commands, test_fixtures::{TestComponentA, TestTickComponent, TestSharedComponent}}, types::{EntityId, ComponentId, EntityError, CommandError, UpdateError}};
+
+    use super::{EcsBehaviorManager, query::ArchetypeQuery, system::{IBlockingSystemHandler, ResourcesAccess}};
+
+    #[derive(Debug)]
+    struct TestAddedSystem {
+        seen_entities: Arc<Mutex<Vec<Vec<EntityId>>>>,
+        is_spawned: bool,
+    }
+
+    #[async_trait::async_trait(?Send)]
+    impl IBlockingSystemHandler for TestAddedSystem {
+        async fn handle(&mut self, chunk_data_accessor: ChunkDataAccessor, commands: &mut Commands) {
+            let entity_ids = chunk_data_accessor.rows().iter().map(|row| chunk_data_accessor.entity_ids()[*row]);
+            self.seen_entities.lock().unwrap().last_mut().unwrap().extend(entity_ids);
+
+            if !self.is_spawned {
+                self.is_spawned = true;
+                commands.spawn((TestTickComponent(0),));
+            }
+        }
+
+        fn archetype_query(&self) -> ArchetypeQuery {
+            ArchetypeQuery::new(Some(HashSet::from([ComponentId::from_type::<TestTickComponent>()])), None, None, None).added::<TestTickComponent>()
+        }
+    }
+
+    #[test]
+    fn test_system_added_filter_since_last_run() {
+        let mut ecs_data_manager = EcsDataManager::new();
+        ecs_data_manager.register_component::<TestTickComponent>();
+
+        let entity_id = ecs_data_manager.spawn((TestTickComponent(0),)).unwrap();
+        let ecs_data_manager = Arc::new(RwLock::new(ecs_data_manager));
+
+        let seen_entities = Arc::new(Mutex::new(Vec::new()));
+
+        let mut ecs_behavior_manager = EcsBehaviorManager::default();
+        ecs_behavior_manager.get_system_builder().unwrap().build_with_sync_handler(TestAddedSystem { seen_entities: seen_entities.clone(), is_spawned: false }).unwrap();
+
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+
+        for _ in 0..3 {
+            seen_entities.lock().unwrap().push(Vec::new());
+            assert!(ecs_behavior_manager.update(ecs_data_manager.clone(), runtime.handle().clone()).is_empty());
+        }
+
+        // сущность из команд системы создается после такта системы и видна ей в следующем обновлении
+        let spawned_entity_id = EntityId { id: entity_id.id + 1, version: entity_id.version };
+
+        assert_eq!(*seen_entities.lock().unwrap(), vec![vec![entity_id], vec![spawned_entity_id], vec![]]);
+    }
+
+    /// Система записывает создание сущности и удаление несуществующей
+    #[derive(Debug)]
+    struct TestCommandSystem<const VALUE: u32>;
+
+    #[async_trait::async_trait(?Send)]
+    impl<const VALUE: u32> IBlockingSystemHandler for TestCommandSystem<VALUE> {
+        async fn handle(&mut self, _: ChunkDataAccessor, commands: &mut Commands) {
+            commands.spawn((TestTickComponent(VALUE),)).despawn(EntityId::new(100));
+        }
+
+        fn archetype_query(&self) -> ArchetypeQuery {
+            ArchetypeQuery::new(Some(HashSet::from([ComponentId::from_type::<TestComponentA>()])), None, None, None)
+        }
+    }
+
+    #[test]
+    fn test_commands_applied_in_registration_order() {
+        let mut ecs_data_manager = EcsDataManager::new();
+        ecs_data_manager.register_component::<TestComponentA>();
+        let tick_component_id = ecs_data_manager.register_component::<TestTickComponent>();
+
+        ecs_data_manager.spawn((TestComponentA {},)).unwrap();
+        let ecs_data_manager = Arc::new(RwLock::new(ecs_data_manager));
+
+        // первая зарегистрированная система выполняется второй
+        let mut ecs_behavior_manager = EcsBehaviorManager::default();
+
+        let mut first_builder = ecs_behavior_manager.get_system_builder().unwrap();
+        first_builder.need_system_result::<TestCommandSystem<2>>();
+        first_builder.build_with_sync_handler(TestCommandSystem::<1>).unwrap();
+
+        ecs_behavior_manager.get_system_builder().unwrap().build_with_sync_handler(TestCommandSystem::<2>).unwrap();
+
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let update_errors = ecs_behavior_manager.update(ecs_data_manager.clone(), runtime.handle().clone());
+
+        // ошибки команд возвращаются из обновления и не прерывают применение остальных команд
+        assert_eq!(update_errors.len(), 2);
+        assert!(update_errors.iter().all(|update_error| matches!(update_error, UpdateError::Command(CommandError::Entity(EntityError::NoSuchEntity { .. })))));
+
+        // команды применяются в порядке регистрации систем, а не завершения
+        let ecs_data_manager = ecs_data_manager.blocking_read();
+        let query = ArchetypeQuery::new(Some(HashSet::from([tick_component_id])), None, None, None);
+
+        let values = ecs_data_manager.query_entities(&query).unwrap().into_iter()
+            .map(|entity_id| ecs_data_manager.get::<TestTickComponent>(entity_id).unwrap().0)
+            .collect::<Vec<_>>();
+
+        assert_eq!(values, vec![1, 2]);
+    }
+
+    #[derive(Debug)]
+    struct TestReadonlySystem {
+        resolved_accessors: Arc<Mutex<Vec<(bool, bool)>>>,
+    }
+
+    #[async_trait::async_trait(?Send)]
+    impl IBlockingSystemHandler for TestReadonlySystem {
+        async fn handle(&mut self, mut chunk_data_accessor: ChunkDataAccessor, _: &mut Commands) {
+            let is_rw_resolved = chunk_data_accessor.resolve_rw_components::<TestTickComponent>().is_some();
+            let ro_accessor = chunk_data_accessor.resolve_ro_components::<TestTickComponent>().await;
+
+            if let Some(ro_accessor) = ro_accessor.as_ref() {
+                assert_eq!(ro_accessor.read().await.len(), 2);
+            }
+
+            self.resolved_accessors.lock().unwrap().push((ro_accessor.is_some(), is_rw_resolved));
+        }
+
+        fn archetype_query(&self) -> ArchetypeQuery {
+            ArchetypeQuery::new(Some(HashSet::from([ComponentId::from_type::<TestTickComponent>()])), None, None, None).readonly::<TestTickComponent>()
+        }
+    }
+
+    #[test]
+    fn test_readonly_components() {
+        let mut ecs_data_manager = EcsDataManager::new();
+        ecs_data_manager.register_component::<TestTickComponent>();
+
+        ecs_data_manager.spawn((TestTickComponent(0),)).unwrap();
+        ecs_data_manager.spawn((TestTickComponent(1),)).unwrap();
+
+        let since_tick = ecs_data_manager.change_tick();
+        let ecs_data_manager = Arc::new(RwLock::new(ecs_data_manager));
+
+        let resolved_accessors = Arc::new(Mutex::new(Vec::new()));
+
+        let mut ecs_behavior_manager = EcsBehaviorManager::default();
+        ecs_behavior_manager.get_system_builder().unwrap().build_with_sync_handler(TestReadonlySystem { resolved_accessors: resolved_accessors.clone() }).unwrap();
+
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        assert!(ecs_behavior_manager.update(ecs_data_manager.clone(), runtime.handle().clone()).is_empty());
+
+        // компонент выдан только на чтение, колонка не помечена измененной
+        assert_eq!(*resolved_accessors.lock().unwrap(), vec![(true, false)]);
+
+        let changed_query = ArchetypeQuery::new(None, None, None, None).with_changed::<TestTickComponent>(since_tick);
+        assert!(ecs_data_manager.blocking_read().query_entities(&changed_query).unwrap().is_empty());
+    }
+
+    #[derive(Debug, Default)]
+    struct TestSystemCalls(Vec<usize>);
+
+    #[derive(Debug)]
+    struct TestResourceSystem;
+
+    #[async_trait::async_trait(?Send)]
+    impl IBlockingSystemHandler for TestResourceSystem {
+        async fn handle(&mut self, mut chunk_data_accessor: ChunkDataAccessor, _: &mut Commands) {
+            let system_calls = chunk_data_accessor.resolve_rw_resource::<TestSystemCalls>().unwrap();
+            system_calls.write().await.0.push(chunk_data_accessor.entity_ids().len());
+        }
+
+        fn archetype_query(&self) -> ArchetypeQuery {
+            ArchetypeQuery::resources_only()
+        }
+
+        fn resources_access(&self) -> ResourcesAccess {
+            ResourcesAccess::new().write::<TestSystemCalls>()
+        }
+    }
+
+    #[derive(Debug)]
+    struct TestEntitySystem(Arc<AtomicUsize>);
+
+    #[async_trait::async_trait(?Send)]
+    impl IBlockingSystemHandler for TestEntitySystem {
+        async fn handle(&mut self, _: ChunkDataAccessor, _: &mut Commands) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn archetype_query(&self) -> ArchetypeQuery {
+            ArchetypeQuery::new(Some(HashSet::from([ComponentId::from_type::<TestSharedComponent>()])), None, None, None)
+        }
+    }
+
+    #[test]
+    fn test_resources_only_system_called_once() {
+        let mut ecs_data_manager = EcsDataManager::new();
+        ecs_data_manager.register_component::<TestTickComponent>();
+        ecs_data_manager.register_component::<TestSharedComponent>();
+        ecs_data_manager.insert_resource(TestSystemCalls::default());
+
+        ecs_data_manager.spawn((TestTickComponent(0),)).unwrap();
+        ecs_data_manager.spawn((TestTickComponent(1),)).unwrap();
+
+        let ecs_data_manager = Arc::new(RwLock::new(ecs_data_manager));
+        let entity_system_calls = Arc::new(AtomicUsize::new(0));
+
+        let mut ecs_behavior_manager = EcsBehaviorManager::default();
+        ecs_behavior_manager.get_system_builder().unwrap().build_with_sync_handler(TestResourceSystem).unwrap();
+        ecs_behavior_manager.get_system_builder().unwrap().build_with_sync_handler(TestEntitySystem(entity_system_calls.clone())).unwrap();
+
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+
+        for _ in 0..2 {
+            ecs_behavior_manager.update(ecs_data_manager.clone(), runtime.handle().clone());
+        }
+
+        // запрос без сущностей: один вызов за обновление, без чанков
+        assert_eq!(ecs_data_manager.blocking_read().resource::<TestSystemCalls>().unwrap().0, vec![0, 0]);
+
+        // система сущностей без подходящих чанков не вызывается
+        assert_eq!(entity_system_calls.load(Ordering::SeqCst), 0);
+    }
+}
@@ -1,6 +1,6 @@
-use std::collections::HashSet;
+use std::{any::Any, collections::HashSet};
 
-use crate::{types::{ComponentId, ArchetypeType}, data::archetype::ArchetypeChunk};
+use crate::{types::{ComponentId, ComponentsBitSet}, data::{archetype::{ArchetypeChunk, SharedComponentsKey, ComponentTicks, is_tick_newer}, EcsDataManager, component::storage_type::StorageType, hierarchy::is_hierarchy_component}};
 
 
 pub struct ArchetypeQuery {
@@ -8,6 +8,16 @@ pub struct ArchetypeQuery {
     pub (crate) except: Option<HashSet<ComponentId>>,
     pub (crate) addition: Option<HashSet<ComponentId>>,
     pub (crate) updated: Option<u32>,
+    // компоненты, выдаваемые системе только на чтение
+    pub (crate) readonly: HashSet<ComponentId>,
+    // фильтр чанков по значениям общих компонентов
+    pub (crate) shared_values: Vec<(ComponentId, Box<dyn Any + Sync + Send>)>,
+    // фильтры сущностей по тактам добавления и изменения компонентов
+    pub (crate) ticks_filters: Vec<ComponentTicksFilter>,
+    // такт предыдущего запуска системы для относительных фильтров, задается планировщиком
+    pub (crate) last_run_tick: Option<u32>,
+    // запрос без сущностей, системе нужны только ресурсы
+    pub (crate) resources_only: bool,
 }
 
 impl ArchetypeQuery {
@@ -16,32 +26,323 @@ impl ArchetypeQuery {
             required,
             except,
             addition,
-            updated
+            updated,
+            readonly: Default::default(),
+            shared_values: Default::default(),
+            ticks_filters: Default::default(),
+            last_run_tick: None,
+            resources_only: false,
         }
     }
 
-    pub fn is_archetype_match(&self, archetype_type: &ArchetypeType) -> bool {
-        if let Some(required) = &self.required {
-            let all_required_exist = required.iter().all(|x| archetype_type.contains(x));
+    /// Запрос, которому не соответствует ни один чанк. Система вызывается один раз за обновление, только с ресурсами
+    pub fn resources_only() -> Self {
+        Self {
+            resources_only: true,
+            ..Self::new(None, None, None, None)
+        }
+    }
+
+    /// Только чанки, у которых значение общего компонента равно `value`
+    pub fn with_shared_component<TComponent: Sync + Send + 'static>(mut self, value: TComponent) -> Self {
+        self.shared_values.push((ComponentId::from_type::<TComponent>(), Box::new(value)));
+        self
+    }
+
+    /// Компонент выдается системе только на чтение: его колонка не помечается измененной, системы, читающие компонент, выполняются одновременно
+    pub fn readonly<TComponent: 'static>(mut self) -> Self {
+        self.readonly.insert(ComponentId::from_type::<TComponent>());
+        self
+    }
+
+    /// Added<T>: только сущности, получившие компонент после такта `since_tick`
+    pub fn with_added<TComponent: 'static>(mut self, since_tick: u32) -> Self {
+        self.ticks_filters.push(ComponentTicksFilter::Added { component_id: ComponentId::from_type::<TComponent>(), since_tick: Some(since_tick) });
+        self
+    }
+
+    /// Changed<T>: только сущности, компонент которых добавлен или изменен после такта `since_tick`
+    pub fn with_changed<TComponent: 'static>(mut self, since_tick: u32) -> Self {
+        self.ticks_filters.push(ComponentTicksFilter::Changed { component_id: ComponentId::from_type::<TComponent>(), since_tick: Some(since_tick) });
+        self
+    }
+
+    /// Added<T> относительно предыдущего запуска системы. Вне систем - относительно начала предыдущего обновления
+    pub fn added<TComponent: 'static>(mut self) -> Self {
+        self.ticks_filters.push(ComponentTicksFilter::Added { component_id: ComponentId::from_type::<TComponent>(), since_tick: None });
+        self
+    }
+
+    /// Changed<T> относительно предыдущего запуска системы. Вне систем - относительно начала предыдущего обновления
+    pub fn changed<TComponent: 'static>(mut self) -> Self {
+        self.ticks_filters.push(ComponentTicksFilter::Changed { component_id: ComponentId::from_type::<TComponent>(), since_tick: None });
+        self
+    }
+
+    pub (crate) fn with_last_run_tick(mut self, last_run_tick: u32) -> Self {
+        self.last_run_tick = Some(last_run_tick);
+        self
+    }
+
+    /// Запрошенные компоненты с признаком доступа только на чтение. Иерархия изменяется только через set_parent/remove_parent
+    pub (crate) fn selected_components(&self) -> Vec<(ComponentId, bool)> {
+        self.required.iter().flatten()
+            .chain(self.addition.iter().flatten())
+            .map(|component_id| (*component_id, self.readonly.contains(component_id) || is_hierarchy_component(component_id)))
+            .collect()
+    }
+
+    /// Доступ к компонентам для планировщика: запрошенные компоненты и компоненты фильтров тактов, такты которых читаются при выборе сущностей
+    pub (crate) fn components_access(&self) -> ComponentsAccess {
+        let components = self.selected_components().into_iter()
+            .chain(self.ticks_filters.iter().map(|ticks_filter| (ticks_filter.component_id(), true)))
+            .collect();
+
+        ComponentsAccess { components }
+    }
 
-            if !all_required_exist {
-                return false;
+    /// Компиляция запроса в битовые маски. None, если обязательный компонент не зарегистрирован и запросу не подходит ни один архетип.
+    /// Компоненты из разреженных множеств в сигнатуры архетипов не входят и проверяются отдельно, для каждой сущности
+    pub fn build_signature(&self, ecs_data_manager: &EcsDataManager) -> Option<ArchetypeQuerySignature> {
+        if self.resources_only {
+            return None;
+        }
+
+        let mut query_signature = ArchetypeQuerySignature {
+            last_run_tick: self.last_run_tick.unwrap_or(ecs_data_manager.previous_update_tick),
+            ..Default::default()
+        };
+
+        for component_id in self.required.iter().flatten() {
+            let component_info = ecs_data_manager.component_info(component_id)?;
+
+            match component_info.storage_type {
+                StorageType::Table | StorageType::Shared => query_signature.required.insert(component_info.index),
+                StorageType::SparseSet => query_signature.required_sparse.push(*component_id),
             }
         }
 
-        if let Some(except) = &self.except {
-            let any_except_exist = except.iter().any(|x| archetype_type.contains(x));
+        // незарегистрированный компонент не может быть у сущности, исключать его не нужно
+        self.except.iter().flatten()
+            .filter_map(|component_id| ecs_data_manager.component_info(component_id))
+            .for_each(|component_info| match component_info.storage_type {
+                StorageType::Table | StorageType::Shared => query_signature.except.insert(component_info.index),
+                StorageType::SparseSet => query_signature.except_sparse.push(component_info.component_id),
+            });
+
+        query_signature.addition = self.addition.iter().flatten()
+            .filter_map(|component_id| ecs_data_manager.component_info(component_id))
+            .filter(|component_info| component_info.storage_type == StorageType::Table)
+            .map(|component_info| component_info.index)
+            .collect::<ComponentsBitSet>();
+
+        // значение, которого нет ни в одном чанке, запросу не соответствует ничего
+        for (component_id, value) in self.shared_values.iter() {
+            let component_info = ecs_data_manager.component_info(component_id)?;
+            let value_index = ecs_data_manager.find_shared_value(component_id, value.as_ref())?;
 
-            if any_except_exist {
-                return false;
+            query_signature.required.insert(component_info.index);
+            query_signature.shared.push((*component_id, value_index));
+        }
+
+        // такты есть только у компонентов в колонках чанков, для остальных фильтр не выполняется никогда
+        for ticks_filter in self.ticks_filters.iter() {
+            let component_info = ecs_data_manager.component_info(&ticks_filter.component_id())?;
+
+            if component_info.storage_type != StorageType::Table || component_info.is_tag {
+                return None;
             }
+
+            query_signature.required.insert(component_info.index);
+            query_signature.ticks_filters.push(*ticks_filter);
         }
 
-        return true;
+        Some(query_signature)
     }
 
+    /// При заданном `updated` подходят только чанки, в которых колонки запрошенных компонентов изменялись после этого такта.
+    /// Такты сравниваются с учетом переполнения
     pub fn is_chunk_match(&self, archetype_chunk: &ArchetypeChunk) -> bool {
-        //add check updated
-        true
+        let Some(updated) = self.updated else {
+            return true;
+        };
+
+        let mut tracked_components = self.required.iter().flatten().chain(self.addition.iter().flatten()).peekable();
+
+        // компоненты не указаны - учитываются все колонки чанка
+        if tracked_components.peek().is_none() {
+            return archetype_chunk.is_changed_since(archetype_chunk.archetype_components_map.keys(), updated);
+        }
+
+        archetype_chunk.is_changed_since(tracked_components, updated)
+    }
+}
+
+/// Компоненты, к которым обращается система. Планировщик не запускает одновременно системы, одна из которых пишет в компонент, используемый другой
+#[derive(Debug, Default, Clone)]
+pub (crate) struct ComponentsAccess {
+    // идентификатор компонента и признак доступа только на чтение
+    pub (crate) components: Vec<(ComponentId, bool)>,
+}
+
+impl ComponentsAccess {
+    pub (crate) fn is_conflicting(&self, other: &ComponentsAccess) -> bool {
+        self.components.iter().any(|(component_id, readonly)| {
+            other.components.iter().any(|(other_component_id, other_readonly)| component_id == other_component_id && !(*readonly && *other_readonly))
+        })
+    }
+}
+
+/// Запрос, скомпилированный в битовые маски плотных индексов компонентов
+#[derive(Debug, Default, Clone)]
+pub struct ArchetypeQuerySignature {
+    pub (crate) required: ComponentsBitSet,
+    pub (crate) except: ComponentsBitSet,
+    pub (crate) addition: ComponentsBitSet,
+    pub (crate) required_sparse: Vec<ComponentId>,
+    pub (crate) except_sparse: Vec<ComponentId>,
+    pub (crate) shared: SharedComponentsKey,
+    pub (crate) ticks_filters: Vec<ComponentTicksFilter>,
+    // такт, с которым сравниваются относительные фильтры тактов
+    pub (crate) last_run_tick: u32,
+}
+
+impl ArchetypeQuerySignature {
+    pub fn is_match(&self, archetype_signature: &ComponentsBitSet) -> bool {
+        archetype_signature.contains_all(&self.required) && !archetype_signature.intersects(&self.except)
+    }
+
+    /// Значения общих компонентов чанка соответствуют фильтру запроса
+    pub fn is_shared_match(&self, shared_components_key: &SharedComponentsKey) -> bool {
+        self.shared.iter().all(|shared_value| shared_components_key.contains(shared_value))
+    }
+
+    /// Необязательные компоненты запроса, присутствующие в архетипе
+    pub fn addition_present(&self, archetype_signature: &ComponentsBitSet) -> ComponentsBitSet {
+        ComponentsBitSet { words: self.addition.words.iter().zip(archetype_signature.words.iter()).map(|(addition_word, word)| addition_word & word).collect() }
+    }
+}
+/// Фильтр сущности по тактам компонента. Без `since_tick` такт сравнивается с тактом предыдущего запуска системы
+#[derive(Debug, Clone, Copy)]
+pub (crate) enum ComponentTicksFilter {
+    Added { component_id: ComponentId, since_tick: Option<u32> },
+    Changed { component_id: ComponentId, since_tick: Option<u32> },
+}
+
+impl ComponentTicksFilter {
+    pub (crate) fn component_id(&self) -> ComponentId {
+        match self {
+            ComponentTicksFilter::Added { component_id, .. } | ComponentTicksFilter::Changed { component_id, .. } => *component_id,
+        }
+    }
+
+    pub (crate) fn is_match(&self, component_ticks: &ComponentTicks, last_run_tick: u32) -> bool {
+        match self {
+            ComponentTicksFilter::Added { since_tick, .. } => is_tick_newer(component_ticks.added, since_tick.unwrap_or(last_run_tick)),
+            ComponentTicksFilter::Changed { since_tick, .. } => is_tick_newer(component_ticks.changed, since_tick.unwrap_or(last_run_tick)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use crate::{types::ComponentId, data::{EcsDataManager, archetype::update_change_tick, hierarchy::Parent, test_fixtures::{TestComponentA, TestComponentB, TestComponentC, TestTickComponent, TestSharedComponent}}};
+
+    use super::ArchetypeQuery;
+
+    fn component_ids(component_ids: &[ComponentId]) -> Option<HashSet<ComponentId>> {
+        Some(component_ids.iter().copied().collect())
+    }
+
+    #[test]
+    fn test_query_signature_matching() {
+        let mut ecs_data_manager = EcsDataManager::new();
+        let component_a_id = ecs_data_manager.register_component::<TestComponentA>();
+        let component_b_id = ecs_data_manager.register_component::<TestComponentB>();
+        let component_c_id = ecs_data_manager.register_component::<TestComponentC>();
+
+        let entity_id = ecs_data_manager.spawn((TestComponentA {}, TestComponentB {})).unwrap();
+        let archetype_id = ecs_data_manager.entity_location(entity_id).unwrap().archetype_id();
+        let archetype_signature = ecs_data_manager.archetypes[*archetype_id].signature.clone();
+
+        let is_match = |required: &[ComponentId], except: &[ComponentId]| ArchetypeQuery::new(component_ids(required), component_ids(except), None, None)
+            .build_signature(&ecs_data_manager).unwrap()
+            .is_match(&archetype_signature);
+
+        assert!(is_match(&[], &[]));
+        assert!(is_match(&[component_a_id], &[component_c_id]));
+        assert!(is_match(&[component_a_id, component_b_id], &[]));
+        assert!(!is_match(&[component_a_id, component_c_id], &[]));
+        assert!(!is_match(&[component_a_id], &[component_b_id]));
+
+        // незарегистрированный обязательный компонент: запросу не подходит ни один архетип
+        let unregistered_id = ComponentId::from_type::<TestTickComponent>();
+        assert!(ArchetypeQuery::new(component_ids(&[unregistered_id]), None, None, None).build_signature(&ecs_data_manager).is_none());
+
+        // незарегистрированный исключенный компонент ничего не исключает
+        let except_query = ArchetypeQuery::new(component_ids(&[component_a_id]), component_ids(&[unregistered_id]), None, None);
+        assert!(except_query.build_signature(&ecs_data_manager).unwrap().is_match(&archetype_signature));
+        assert!(ArchetypeQuery::resources_only().build_signature(&ecs_data_manager).is_none());
+    }
+
+    #[test]
+    fn test_updated_chunk_filter() {
+        let mut ecs_data_manager = EcsDataManager::new();
+        let tick_component_id = ecs_data_manager.register_component::<TestTickComponent>();
+        let shared_component_id = ecs_data_manager.register_component::<TestSharedComponent>();
+
+        let entity_id = ecs_data_manager.spawn((TestTickComponent(0), TestSharedComponent(0))).unwrap();
+
+        let since_tick = ecs_data_manager.increment_change_tick();
+        let update_tick = ecs_data_manager.increment_change_tick();
+
+        let entity_location = ecs_data_manager.entity_location(entity_id).unwrap();
+        let archetype_chunk = &ecs_data_manager.archetypes[*entity_location.archetype_id()].chunks[entity_location.chunk_index()];
+
+        let updated_query = |required: &[ComponentId], updated| ArchetypeQuery::new(component_ids(required), None, None, Some(updated));
+
+        // без фильтра подходит любой чанк, колонки, в которые не писали системы, не изменены
+        assert!(ArchetypeQuery::new(None, None, None, None).is_chunk_match(archetype_chunk));
+        assert!(!updated_query(&[tick_component_id], since_tick).is_chunk_match(archetype_chunk));
+
+        update_change_tick(&archetype_chunk.get_components_array(&tick_component_id).unwrap().get_change_tick(), update_tick);
+
+        // учитываются только колонки запрошенных компонентов
+        assert!(updated_query(&[tick_component_id], since_tick).is_chunk_match(archetype_chunk));
+        assert!(!updated_query(&[shared_component_id], since_tick).is_chunk_match(archetype_chunk));
+        assert!(updated_query(&[tick_component_id, shared_component_id], since_tick).is_chunk_match(archetype_chunk));
+        assert!(!updated_query(&[tick_component_id], update_tick).is_chunk_match(archetype_chunk));
+
+        // компоненты не указаны - учитываются все колонки чанка
+        assert!(updated_query(&[], since_tick).is_chunk_match(archetype_chunk));
+        assert!(ArchetypeQuery::new(None, None, None, Some(since_tick)).is_chunk_match(archetype_chunk));
+        assert!(!ArchetypeQuery::new(None, None, None, Some(update_tick)).is_chunk_match(archetype_chunk));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_components_access_conflicts() {
+        let tick_component_id = ComponentId::from_type::<TestTickComponent>();
+
+        let write_access = ArchetypeQuery::new(component_ids(&[tick_component_id]), None, None, None).components_access();
+        let read_access = ArchetypeQuery::new(component_ids(&[tick_component_id]), None, None, None).readonly::<TestTickComponent>().components_access();
+        let other_access = ArchetypeQuery::new(None, None, component_ids(&[ComponentId::from_type::<TestSharedComponent>()]), None).components_access();
+
+        assert!(write_access.is_conflicting(&write_access));
+        assert!(write_access.is_conflicting(&read_access));
+        assert!(read_access.is_conflicting(&write_access));
+        assert!(!read_access.is_conflicting(&read_access));
+        assert!(!write_access.is_conflicting(&other_access));
+
+        // такты фильтра читаются при выборе сущностей
+        let filter_access = ArchetypeQuery::new(None, None, None, None).changed::<TestTickComponent>().components_access();
+        assert!(filter_access.is_conflicting(&write_access));
+        assert!(!filter_access.is_conflicting(&read_access));
+
+        // иерархия выдается системам только на чтение
+        let hierarchy_query = ArchetypeQuery::new(component_ids(&[ComponentId::from_type::<Parent>()]), None, None, None);
+        assert_eq!(hierarchy_query.selected_components(), vec![(ComponentId::from_type::<Parent>(), true)]);
+        assert!(!hierarchy_query.components_access().is_conflicting(&hierarchy_query.components_access()));
+    }
+}
@@ -1,8 +1,8 @@
-use std::{fmt::Debug, any::TypeId, marker::PhantomData, sync::{Arc}, cell::RefCell};
+use std::{fmt::Debug, any::TypeId, sync::{Arc}};
 
-use tokio::sync::{RwLock, Mutex};
+use tokio::sync::Mutex;
 
-use crate::{data::{EcsDataManager, entity_data_accessor::ChunkDataAccessor}, types::ArchetypeType};
+use crate::data::{entity_data_accessor::ChunkDataAccessor, commands::Commands};
 
 use super::{/* entity_data_accessor::ArchetypeDataAccessor, */ /* job::{Job, JobType} ,*/ query::ArchetypeQuery};
 
@@ -22,6 +22,36 @@ use super::{/* entity_data_accessor::ArchetypeDataAccessor, */ /* job::{Job, Job
 
 // impl ISystem for System {}
 
+/// Ресурсы, к которым обращается система: `ResourcesAccess::new().read::<Time>().write::<InputState>()`.
+/// Планировщик не запускает одновременно системы, одна из которых пишет в ресурс, используемый другой
+#[derive(Debug, Default, Clone)]
+pub struct ResourcesAccess {
+    // идентификатор ресурса и признак доступа только на чтение
+    pub (crate) resources: Vec<(TypeId, bool)>,
+}
+
+impl ResourcesAccess {
+    pub fn new() -> Self {
+        Self { ..Default::default() }
+    }
+
+    pub fn read<TResource: 'static>(mut self) -> Self {
+        self.resources.push((TypeId::of::<TResource>(), true));
+        self
+    }
+
+    pub fn write<TResource: 'static>(mut self) -> Self {
+        self.resources.push((TypeId::of::<TResource>(), false));
+        self
+    }
+
+    pub (crate) fn is_conflicting(&self, other: &ResourcesAccess) -> bool {
+        self.resources.iter().any(|(resource_type_id, readonly)| {
+            other.resources.iter().any(|(other_resource_type_id, other_readonly)| resource_type_id == other_resource_type_id && !(*readonly && *other_readonly))
+        })
+    }
+}
+
 #[derive(Debug)]
 pub enum SystemType {
     BlockingSystem(Box<dyn IBlockingSystemHandler>),
@@ -44,8 +74,14 @@ impl ISystemType for dyn IBlockingSystemHandler {
 
 #[async_trait::async_trait(?Send)]
 pub trait IBlockingSystemHandler: Debug {
-    async fn handle(&mut self, archetype_data_accessor: ChunkDataAccessor);
+    /// Вызывается для каждого подходящего чанка. Система с запросом `ArchetypeQuery::resources_only` вызывается один раз, с пустым набором сущностей.
+    /// Структурные изменения записываются в `commands` и применяются после завершения всех систем
+    async fn handle(&mut self, archetype_data_accessor: ChunkDataAccessor, commands: &mut Commands);
     fn archetype_query(&self) -> ArchetypeQuery;
+
+    fn resources_access(&self) -> ResourcesAccess {
+        ResourcesAccess::default()
+    }
 }
 
 impl ISystemType for dyn IMultithreadSystemHandler {
@@ -56,6 +92,12 @@ impl ISystemType for dyn IMultithreadSystemHandler {
 
 #[async_trait::async_trait]
 pub trait IMultithreadSystemHandler: Debug {
-    async fn handle(&mut self, archetype_data_accessor: ChunkDataAccessor);
+    /// Вызывается для каждого подходящего чанка. Система с запросом `ArchetypeQuery::resources_only` вызывается один раз, с пустым набором сущностей.
+    /// Структурные изменения записываются в `commands` и применяются после завершения всех систем
+    async fn handle(&mut self, archetype_data_accessor: ChunkDataAccessor, commands: &mut Commands);
     fn archetype_query(&self) -> ArchetypeQuery;
+
+    fn resources_access(&self) -> ResourcesAccess {
+        ResourcesAccess::default()
+    }
 }
\ No newline at end of file
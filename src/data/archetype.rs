@@ -1,46 +1,157 @@
-use std::{any::Any, fmt::Debug, collections::HashMap, iter::Enumerate, ops::Add, sync::Arc, slice::Iter};
+use std::{any::Any, fmt::Debug, collections::HashMap, sync::{Arc, atomic::{AtomicU32, Ordering}}, slice::Iter};
 
-use tokio::sync::{RwLock, RwLockWriteGuard, RwLockReadGuard, Mutex};
-use vec_map::VecMap;
+use tokio::sync::{RwLock, TryLockError};
 
-use crate::types::{ArchetypeType, EntityId, ComponentId};
+use crate::{types::{ArchetypeType, EntityId, ComponentId, ArchetypeId, EntityLocation, ComponentsBitSet, QueryResult, QueryError}, behavior::query::ComponentTicksFilter};
 
-use super::entity_data::EntityData;
+use super::{entity_data::EntityData, new_entity_components_info::{INewEntityComponentsInfo, NewEntityComponentsWriter, NewEntitiesBatchWriter}, component::{sparse_set::IComponentSparseSet, dynamic_component::DynamicComponentsArray}};
 
-const CHUNK_ELEMENTS_COUNT: usize = 64;
+/// Пары (компонент, индекс значения) общих компонентов чанка, отсортированы по компоненту
+pub (crate) type SharedComponentsKey = Vec<(ComponentId, usize)>;
+
+/// Такты добавления и последнего изменения компонента сущности
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub (crate) struct ComponentTicks {
+    pub (crate) added: u32,
+    pub (crate) changed: u32,
+}
+
+impl ComponentTicks {
+    pub (crate) fn new(change_tick: u32) -> Self {
+        Self { added: change_tick, changed: change_tick }
+    }
+
+    pub (crate) fn check_ticks(&mut self, change_tick: u32) {
+        check_tick(&mut self.added, change_tick);
+        check_tick(&mut self.changed, change_tick);
+    }
+}
+
+/// Такты сравниваются с учетом переполнения счетчика, разница сравниваемых тактов должна быть меньше 2^31.
+/// Раз в CHECK_TICK_THRESHOLD тактов такты старше MAX_CHANGE_AGE прижимаются к этому возрасту
+pub (crate) const CHECK_TICK_THRESHOLD: u32 = 1 << 29;
+pub (crate) const MAX_CHANGE_AGE: u32 = 1 << 30;
+
+/// `tick` новее `since_tick`
+pub (crate) fn is_tick_newer(tick: u32, since_tick: u32) -> bool {
+    (tick.wrapping_sub(since_tick) as i32) > 0
+}
+
+pub (crate) fn check_tick(tick: &mut u32, change_tick: u32) {
+    if change_tick.wrapping_sub(*tick) > MAX_CHANGE_AGE {
+        *tick = change_tick.wrapping_sub(MAX_CHANGE_AGE);
+    }
+}
+
+/// Такт изменения колонки только растет: системы с разными тактами захватывают колонку в любом порядке
+pub (crate) fn update_change_tick(column_change_tick: &AtomicU32, change_tick: u32) {
+    _ = column_change_tick.fetch_update(Ordering::AcqRel, Ordering::Acquire, |column_change_tick| is_tick_newer(change_tick, column_change_tick).then_some(change_tick));
+}
 
 pub (crate) trait IComponentsArray where Self: Sync + Send + Debug {
-    fn set_component(&mut self, component: Box<dyn Any + Sync + Send>);
-    fn remove_component(&mut self, position: usize) -> Box<dyn Any + Sync + Send>;
+    fn set_component(&mut self, component: Box<dyn Any + Sync + Send>, component_ticks: ComponentTicks);
+    fn remove_component(&mut self, position: usize) -> (Box<dyn Any + Sync + Send>, ComponentTicks);
     fn get_array(&self) -> Arc<dyn Any + Sync + Send>;
+    fn get_components_ticks(&self) -> Arc<RwLock<Vec<ComponentTicks>>>;
+    fn get_change_tick(&self) -> Arc<AtomicU32>;
+    fn change_tick(&self) -> u32;
+    fn check_change_ticks(&mut self, change_tick: u32);
+    // колонку удерживает ChunkDataAccessor, структурные изменения ее не затрагивают
+    fn is_shared(&self) -> bool;
+    // обход значений колонки по строкам, для снимка. Колонка, захваченная системой на запись, не читается
+    fn for_each_component(&self, f: &mut dyn FnMut(&dyn Any)) -> Result<(), TryLockError>;
 }
 
 #[derive(Debug)]
 pub struct ComponentsArray<TComponent: Debug + Sync + Send + 'static> {
-    components_collection: Arc<RwLock<Vec<TComponent>>>,
+    pub (crate) components_collection: Arc<RwLock<Vec<TComponent>>>,
+    // такт последнего изменения колонки, выставляется при доступе на запись через RwComponentDataAccessor
+    pub (crate) change_tick: Arc<AtomicU32>,
+    // такты каждой строки колонки, для фильтров Added/Changed
+    pub (crate) components_ticks: Arc<RwLock<Vec<ComponentTicks>>>,
 }
 
 impl<TComponent: Debug + Sync + Send + 'static> ComponentsArray<TComponent> {
-    pub (crate) fn new() -> Self {
+    pub (crate) fn new(capacity: usize) -> Self {
         Self {
-            components_collection: Arc::new(RwLock::new(Vec::with_capacity(CHUNK_ELEMENTS_COUNT))),
+            components_collection: Arc::new(RwLock::new(Vec::with_capacity(capacity))),
+            change_tick: Default::default(),
+            components_ticks: Arc::new(RwLock::new(Vec::with_capacity(capacity))),
         }
     }
+
+    pub (crate) fn push(&mut self, component: TComponent, component_ticks: ComponentTicks) {
+        write_exclusive(&mut self.components_collection, |components| components.push(component));
+        write_exclusive(&mut self.components_ticks, |components_ticks| components_ticks.push(component_ticks));
+    }
+
+    pub (crate) fn extend(&mut self, components: impl ExactSizeIterator<Item = TComponent>, component_ticks: ComponentTicks) {
+        let count = components.len();
+
+        write_exclusive(&mut self.components_collection, |components_collection| components_collection.extend(components));
+        write_exclusive(&mut self.components_ticks, |components_ticks| components_ticks.extend(std::iter::repeat_n(component_ticks, count)));
+    }
+}
+
+/// Структурные изменения идут при монопольном доступе к менеджеру. Колонки, которые удерживает ChunkDataAccessor, менеджер
+/// проверяет до начала изменения (ошибка ComponentLocked), поэтому остальные колонки изменяются без блокировки
+pub (crate) fn write_exclusive<T, TResult>(lock: &mut Arc<RwLock<T>>, write: impl FnOnce(&mut T) -> TResult) -> TResult {
+    write(Arc::get_mut(lock).expect("held columns are checked before structural changes").get_mut())
+}
+
+/// Прижатие старых тактов строк и такта изменения колонки. Такты строк, захваченные выполняющейся системой, прижимаются
+/// при следующей проверке: до переполнения сравнения остается еще MAX_CHANGE_AGE тактов
+pub (crate) fn check_column_ticks(components_ticks: &RwLock<Vec<ComponentTicks>>, column_change_tick: &AtomicU32, change_tick: u32) {
+    if let Ok(mut components_ticks) = components_ticks.try_write() {
+        components_ticks.iter_mut().for_each(|component_ticks| component_ticks.check_ticks(change_tick));
+    }
+
+    let mut tick = column_change_tick.load(Ordering::Acquire);
+    check_tick(&mut tick, change_tick);
+    column_change_tick.store(tick, Ordering::Release);
 }
 
 impl<TComponent: Debug + Sync + Send + 'static> IComponentsArray for ComponentsArray<TComponent> {
-    fn set_component(&mut self, component: Box<dyn Any + Sync + Send>) {
+    fn set_component(&mut self, component: Box<dyn Any + Sync + Send>, component_ticks: ComponentTicks) {
         let component = unsafe { *component.downcast_unchecked::<TComponent>() };
-        self.components_collection.blocking_write().push(component);
+        self.push(component, component_ticks);
     }
 
-    fn remove_component(&mut self, position: usize) -> Box<dyn Any + Sync + Send> {
-        Box::new(self.components_collection.blocking_write().swap_remove(position))
+    fn remove_component(&mut self, position: usize) -> (Box<dyn Any + Sync + Send>, ComponentTicks) {
+        (
+            Box::new(write_exclusive(&mut self.components_collection, |components| components.swap_remove(position))),
+            write_exclusive(&mut self.components_ticks, |components_ticks| components_ticks.swap_remove(position))
+        )
     }
 
     fn get_array(&self) -> Arc<dyn Any + Sync + Send> {
         self.components_collection.clone()
     }
+
+    fn get_components_ticks(&self) -> Arc<RwLock<Vec<ComponentTicks>>> {
+        self.components_ticks.clone()
+    }
+
+    fn get_change_tick(&self) -> Arc<AtomicU32> {
+        self.change_tick.clone()
+    }
+
+    fn change_tick(&self) -> u32 {
+        self.change_tick.load(Ordering::Acquire)
+    }
+
+    fn check_change_ticks(&mut self, change_tick: u32) {
+        check_column_ticks(&self.components_ticks, &self.change_tick, change_tick);
+    }
+
+    fn is_shared(&self) -> bool {
+        Arc::strong_count(&self.components_collection) > 1 || Arc::strong_count(&self.components_ticks) > 1 || Arc::strong_count(&self.change_tick) > 1
+    }
+
+    fn for_each_component(&self, f: &mut dyn FnMut(&dyn Any)) -> Result<(), TryLockError> {
+        self.components_collection.try_read()?.iter().for_each(|component| f(component));
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
@@ -49,18 +160,25 @@ pub struct ArchetypeChunk {
     pub (crate) archetype_components_map: HashMap<ComponentId, Box<dyn IComponentsArray>>,
     pub (crate) chunk_size: usize,
     pub (crate) components_count: usize,
+    // индексы значений общих компонентов чанка, одинаковы для всех сущностей чанка
+    pub (crate) shared_components_key: SharedComponentsKey,
 }
 
 impl ArchetypeChunk {
-    pub (crate) fn new(archetype_components_map: HashMap<ComponentId, Box<dyn IComponentsArray>>) -> Self {
+    pub (crate) fn new(archetype_components_map: HashMap<ComponentId, Box<dyn IComponentsArray>>, chunk_size: usize) -> Self {
         Self {
-            entity_ids: Vec::with_capacity(CHUNK_ELEMENTS_COUNT),
+            entity_ids: Vec::with_capacity(chunk_size),
             archetype_components_map,
-            chunk_size: CHUNK_ELEMENTS_COUNT,
+            chunk_size,
             components_count: 0,
+            shared_components_key: Default::default(),
         }
     }
 
+    pub fn entity_ids(&self) -> &[EntityId] {
+        &self.entity_ids
+    }
+
     pub (crate) fn is_filled(&self) -> bool {
         self.chunk_size == self.components_count
     }
@@ -69,47 +187,140 @@ impl ArchetypeChunk {
         self.components_count == 0
     }
 
-    pub (crate) fn set_data(&mut self, mut entity_data: EntityData) {
+    /// Компоненты без тактов (новые для сущности) считаются добавленными в такте `change_tick`
+    pub (crate) fn set_data(&mut self, mut entity_data: EntityData, change_tick: u32) {
+        debug_assert_eq!(self.archetype_components_map.len(), entity_data.entity_components.len());
+
         self.components_count += 1;
 
         self.entity_ids.push(entity_data.entity_id);
 
         self.archetype_components_map.iter_mut().for_each(|(component_id, archetype_component_array)| {
-            archetype_component_array.set_component(entity_data.entity_components.remove(component_id).unwrap())
+            let component_ticks = entity_data.components_ticks.remove(component_id).unwrap_or(ComponentTicks::new(change_tick));
+            archetype_component_array.set_component(entity_data.entity_components.remove(component_id).unwrap(), component_ticks)
         });
     }
 
+    pub (crate) fn set_components<TComponents: INewEntityComponentsInfo>(&mut self, entity_id: EntityId, components: TComponents, sparse_sets: &mut HashMap<ComponentId, Box<dyn IComponentSparseSet>>, change_tick: u32) {
+        self.components_count += 1;
+
+        self.entity_ids.push(entity_id);
+
+        components.set_data(&mut NewEntityComponentsWriter { entity_id, archetype_chunk: self, sparse_sets, change_tick });
+    }
+
+    pub (crate) fn set_component<TComponent: Debug + Sync + Send + 'static>(&mut self, component: TComponent, component_ticks: ComponentTicks) {
+        let archetype_component_array = self.archetype_components_map.get_mut(&ComponentId::from_type::<TComponent>()).unwrap();
+        let components_array = unsafe { &mut *(archetype_component_array.as_mut() as *mut dyn IComponentsArray as *mut ComponentsArray<TComponent>) };
+
+        components_array.push(component, component_ticks);
+    }
+
+    /// Значения колонки для пачки новых сущностей, такты у всех одинаковые
+    pub (crate) fn extend_components<TComponent: Debug + Sync + Send + 'static>(&mut self, components: impl ExactSizeIterator<Item = TComponent>, component_ticks: ComponentTicks) {
+        let archetype_component_array = self.archetype_components_map.get_mut(&ComponentId::from_type::<TComponent>()).unwrap();
+        let components_array = unsafe { &mut *(archetype_component_array.as_mut() as *mut dyn IComponentsArray as *mut ComponentsArray<TComponent>) };
+
+        components_array.extend(components, component_ticks);
+    }
+
     pub (crate) fn remove_data(&mut self, position: usize) -> EntityData {
         self.components_count -= 1;
 
-        let entity_components = self.archetype_components_map.iter_mut().map(|(component_id, archetype_component_array)| -> _ {
-            (*component_id, archetype_component_array.remove_component(position))
-        }).collect::<HashMap<_,_>>();
+        let mut entity_components = HashMap::with_capacity(self.archetype_components_map.len());
+        let mut components_ticks = HashMap::with_capacity(self.archetype_components_map.len());
+
+        self.archetype_components_map.iter_mut().for_each(|(component_id, archetype_component_array)| {
+            let (component, component_ticks) = archetype_component_array.remove_component(position);
+
+            entity_components.insert(*component_id, component);
+            components_ticks.insert(*component_id, component_ticks);
+        });
 
         let entity_id = self.entity_ids.swap_remove(position);
 
-        EntityData::new(entity_id, entity_components)
+        let mut entity_data = EntityData::new(entity_id, entity_components);
+        entity_data.components_ticks = components_ticks;
+
+        entity_data
     }
 
-    pub (crate) fn contain_entity(&self, entity_id: &EntityId) -> bool {
-        self.entity_ids.contains(entity_id)
+    /// Хотя бы одна из колонок изменена после такта `tick`. Компоненты без колонок не учитываются
+    pub (crate) fn is_changed_since<'a>(&self, mut component_ids: impl Iterator<Item = &'a ComponentId>, tick: u32) -> bool {
+        component_ids.any(|component_id| {
+            self.archetype_components_map.get(component_id).is_some_and(|components_array| is_tick_newer(components_array.change_tick(), tick))
+        })
     }
 
-    pub (crate) fn entity_position(&self, entity_id: &EntityId) -> usize {
-        self.entity_ids.iter().position(|x| *x == *entity_id).unwrap()
+    pub (crate) fn check_change_ticks(&mut self, change_tick: u32) {
+        self.archetype_components_map.values_mut().for_each(|components_array| components_array.check_change_ticks(change_tick));
     }
 
-    pub (crate) fn get_components_array(&self, component_id: &ComponentId) -> Option<&Box<dyn IComponentsArray>> {
-        self.archetype_components_map.get(&component_id)
+    /// Строки чанка, прошедшие фильтры тактов. Колонки компонентов фильтров должны быть в чанке.
+    /// Относительные фильтры сравниваются с `last_run_tick`. Такты, захваченные системой на запись, не ожидаются: ошибка ComponentLocked
+    pub (crate) fn filter_rows(&self, ticks_filters: &[ComponentTicksFilter], last_run_tick: u32) -> QueryResult<Vec<usize>> {
+        if ticks_filters.is_empty() {
+            return Ok((0..self.entity_ids.len()).collect());
+        }
+
+        let components_ticks = ticks_filters.iter()
+            .map(|ticks_filter| self.archetype_components_map[&ticks_filter.component_id()].get_components_ticks())
+            .collect::<Vec<_>>();
+
+        let components_ticks_read_locks = components_ticks.iter().zip(ticks_filters.iter())
+            .map(|(components_ticks, ticks_filter)| components_ticks.try_read().map_err(|_| QueryError::ComponentLocked { component_id: ticks_filter.component_id() }))
+            .collect::<QueryResult<Vec<_>>>()?;
+
+        Ok((0..self.entity_ids.len())
+            .filter(|row| {
+                ticks_filters.iter().zip(components_ticks_read_locks.iter()).all(|(ticks_filter, components_ticks)| ticks_filter.is_match(&components_ticks[*row], last_run_tick))
+            })
+            .collect())
+    }
+
+    /// Колонка чанка, которую удерживает ChunkDataAccessor
+    pub (crate) fn locked_component(&self) -> Option<ComponentId> {
+        self.archetype_components_map.iter().find(|(_, components_array)| components_array.is_shared()).map(|(component_id, _)| *component_id)
+    }
+
+    /// Типизированная колонка, тип проверяется по ComponentId
+    pub (crate) fn get_typed_components_array<TComponent: Debug + Sync + Send + 'static>(&self) -> Option<&ComponentsArray<TComponent>> {
+        self.archetype_components_map.get(&ComponentId::from_type::<TComponent>())
+            .map(|components_array| unsafe { &*(components_array.as_ref() as *const dyn IComponentsArray as *const ComponentsArray<TComponent>) })
+    }
+
+    /// Колонка динамического компонента, вызывающий проверяет, что компонент динамический
+    pub (crate) fn get_dynamic_components_array(&self, component_id: &ComponentId) -> Option<&DynamicComponentsArray> {
+        self.archetype_components_map.get(component_id)
+            .map(|components_array| unsafe { &*(components_array.as_ref() as *const dyn IComponentsArray as *const DynamicComponentsArray) })
+    }
+
+    pub (crate) fn get_components_array(&self, component_id: &ComponentId) -> Option<&dyn IComponentsArray> {
+        self.archetype_components_map.get(component_id).map(|components_array| components_array.as_ref())
     }
 }
 
 pub trait ArchetypeChunkFabricClosure = Fn() -> ArchetypeChunk;
 
 pub struct Archetype where Self: Sync + Send{
+    pub (crate) archetype_id: ArchetypeId,
     pub (crate) archetype_type: ArchetypeType,
+    // битовая сигнатура по плотным индексам компонентов, для быстрого сопоставления с запросами
+    pub (crate) signature: ComponentsBitSet,
     pub (crate) chunks: Vec<ArchetypeChunk>,
+    // чанки, сгруппированные по значениям общих компонентов. Все чанки группы кроме последнего полностью заняты
+    pub (crate) chunk_groups: HashMap<SharedComponentsKey, Vec<usize>>,
     pub (crate) archetype_chunk_fabric: Box<dyn ArchetypeChunkFabricClosure + Sync + Send>,
+    // заранее созданные пустые чанки, используются до создания новых
+    pub (crate) reserved_chunks: Vec<ArchetypeChunk>,
+    pub (crate) chunk_capacity: usize,
+    // метки архетипа: значения не хранятся и при извлечении сущности не создаются
+    pub (crate) tag_component_ids: Vec<ComponentId>,
+    // общие компоненты архетипа, значения хранятся в чанках
+    pub (crate) shared_component_ids: Vec<ComponentId>,
+    // граф переходов между архетипами: добавление/удаление компонента -> целевой архетип
+    pub (crate) add_component_edges: HashMap<ComponentId, ArchetypeId>,
+    pub (crate) remove_component_edges: HashMap<ComponentId, ArchetypeId>,
 }
 
 impl Debug for Archetype
@@ -117,19 +328,44 @@ where Self: Sync + Send
 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Archetype")
+            .field("archetype_id", &self.archetype_id)
             .field("archetype_type", &self.archetype_type)
+            .field("signature", &self.signature)
             .field("chunks", &self.chunks)
+            .field("chunk_groups", &self.chunk_groups)
             .field("archetype_chunk_fabric", &"closure")
+            .field("reserved_chunks", &self.reserved_chunks.len())
+            .field("chunk_capacity", &self.chunk_capacity)
+            .field("shared_component_ids", &self.shared_component_ids)
+            .field("add_component_edges", &self.add_component_edges)
+            .field("remove_component_edges", &self.remove_component_edges)
             .finish()
     }
 }
 
 impl Archetype where Self: Sync + Send {
-    pub (crate) fn new(archetype_type: ArchetypeType, archetype_chunk_fabric: Box<dyn ArchetypeChunkFabricClosure + Sync + Send>) -> Self {
+    pub (crate) fn new(
+        archetype_id: ArchetypeId,
+        archetype_type: ArchetypeType,
+        signature: ComponentsBitSet,
+        archetype_chunk_fabric: Box<dyn ArchetypeChunkFabricClosure + Sync + Send>,
+        chunk_capacity: usize,
+        tag_component_ids: Vec<ComponentId>,
+        shared_component_ids: Vec<ComponentId>,
+    ) -> Self {
         Self {
+            archetype_id,
             archetype_type,
+            signature,
             chunks: Default::default(),
+            chunk_groups: Default::default(),
             archetype_chunk_fabric,
+            reserved_chunks: Default::default(),
+            chunk_capacity,
+            tag_component_ids,
+            shared_component_ids,
+            add_component_edges: Default::default(),
+            remove_component_edges: Default::default(),
         }
     }
 
@@ -137,61 +373,331 @@ impl Archetype where Self: Sync + Send {
         &self.archetype_type
     }
 
-    pub (crate) fn add_entity(&mut self, entity_data: EntityData) {
-        assert_eq!(self.archetype_type.components_count(), entity_data.entity_components.len());
+    /// Переданные значения меток удаляются (drop): метка есть только в сигнатуре архетипа
+    pub (crate) fn add_entity(&mut self, mut entity_data: EntityData, shared_components_key: &SharedComponentsKey, change_tick: u32) -> EntityLocation {
+        self.tag_component_ids.iter().for_each(|component_id| {
+            entity_data.remove_component(component_id);
+        });
+
+        assert_eq!(self.archetype_type.components_count(), entity_data.entity_components.len() + shared_components_key.len() + self.tag_component_ids.len());
+
+        let chunk_index = self.get_free_chunk(shared_components_key);
+
+        self.chunks[chunk_index].set_data(entity_data, change_tick);
+
+        self.last_entity_location(chunk_index)
+    }
+
+    /// Только для архетипов без общих компонентов
+    pub (crate) fn add_components<TComponents: INewEntityComponentsInfo>(&mut self, entity_id: EntityId, components: TComponents, sparse_sets: &mut HashMap<ComponentId, Box<dyn IComponentSparseSet>>, change_tick: u32) -> EntityLocation {
+        let chunk_index = self.get_free_chunk(&SharedComponentsKey::new());
+
+        self.chunks[chunk_index].set_components(entity_id, components, sparse_sets, change_tick);
+
+        self.last_entity_location(chunk_index)
+    }
+
+    /// Пачка сущностей архетипа без общих компонентов в свободные строки чанка (не больше `free_chunk_rows`), значения записывает `write`.
+    /// Возвращает положение первой сущности пачки
+    pub (crate) fn add_components_batch(
+        &mut self,
+        entity_ids: &[EntityId],
+        sparse_sets: &mut HashMap<ComponentId, Box<dyn IComponentSparseSet>>,
+        change_tick: u32,
+        write: impl FnOnce(&mut NewEntitiesBatchWriter)
+    ) -> EntityLocation {
+        let chunk_index = self.get_free_chunk(&Default::default());
+        let archetype_chunk = &mut self.chunks[chunk_index];
+        let first_row = archetype_chunk.components_count;
+
+        debug_assert!(first_row + entity_ids.len() <= archetype_chunk.chunk_size);
+
+        archetype_chunk.components_count += entity_ids.len();
+        archetype_chunk.entity_ids.extend_from_slice(entity_ids);
+
+        write(&mut NewEntitiesBatchWriter { entity_ids, archetype_chunk, sparse_sets, change_tick });
+
+        EntityLocation::new(self.archetype_id, chunk_index, first_row)
+    }
+
+    /// Удерживаемая ChunkDataAccessor колонка чанков, которые изменит удаление сущности: ее чанк и последний чанк ее группы
+    pub (crate) fn locked_component_on_remove(&self, chunk_index: usize) -> Option<ComponentId> {
+        let last_chunk_index = *self.chunk_groups[&self.chunks[chunk_index].shared_components_key].last().unwrap();
+
+        self.chunks[chunk_index].locked_component().or_else(|| self.chunks[last_chunk_index].locked_component())
+    }
 
-        if let Some(last_chunk) = self.chunks.last_mut() {
-            if !last_chunk.is_filled() {
-                last_chunk.set_data(entity_data);
+    /// Удерживаемая ChunkDataAccessor колонка чанка, в который попадет следующая сущность группы
+    pub (crate) fn locked_component_on_add(&self, shared_components_key: &SharedComponentsKey) -> Option<ComponentId> {
+        self.chunk_groups.get(shared_components_key)
+            .and_then(|chunk_group| chunk_group.last())
+            .filter(|chunk_index| !self.chunks[**chunk_index].is_filled())
+            .and_then(|chunk_index| self.chunks[*chunk_index].locked_component())
+    }
+
+    /// Свободные строки чанка, в который попадет следующая сущность группы. Новый чанк не создается
+    pub (crate) fn free_chunk_rows(&self, shared_components_key: &SharedComponentsKey) -> usize {
+        self.chunk_groups.get(shared_components_key)
+            .and_then(|chunk_group| chunk_group.last())
+            .map(|chunk_index| self.chunk_capacity - self.chunks[*chunk_index].components_count)
+            .filter(|free_rows| *free_rows != 0)
+            .unwrap_or(self.chunk_capacity)
+    }
+
+    /// положение последней добавленной в чанк сущности
+    fn last_entity_location(&self, chunk_index: usize) -> EntityLocation {
+        EntityLocation::new(self.archetype_id, chunk_index, self.chunks[chunk_index].components_count - 1)
+    }
 
-                return;
+    /// последний чанк группы, если в нем есть место, иначе новый чанк группы
+    fn get_free_chunk(&mut self, shared_components_key: &SharedComponentsKey) -> usize {
+        if let Some(chunk_index) = self.chunk_groups.get(shared_components_key).and_then(|chunk_group| chunk_group.last()) {
+            if !self.chunks[*chunk_index].is_filled() {
+                return *chunk_index;
             }
         }
 
-        let mut new_archetype_chunk = (self.archetype_chunk_fabric)();
-        new_archetype_chunk.set_data(entity_data);
-        self.chunks.push(new_archetype_chunk);
+        let chunk_index = self.push_chunk(shared_components_key.clone());
+        self.chunk_groups.entry(shared_components_key.clone()).or_default().push(chunk_index);
+
+        chunk_index
+    }
+
+    /// Новый чанк в конце архетипа. В группу чанков его добавляет вызывающий
+    pub (crate) fn push_chunk(&mut self, shared_components_key: SharedComponentsKey) -> usize {
+        let mut archetype_chunk = self.reserved_chunks.pop().unwrap_or_else(|| (self.archetype_chunk_fabric)());
+        archetype_chunk.shared_components_key = shared_components_key;
+
+        self.chunks.push(archetype_chunk);
+
+        self.chunks.len() - 1
     }
 
-    pub (crate) fn remove_entity(&mut self, entity_id: EntityId) -> EntityData {
-        let chunk_number = self.chunks.iter().position(|x| x.contain_entity(&entity_id)).unwrap();
+    /// Создание заранее чанков, достаточных для добавления `entities_count` сущностей в группу чанков
+    pub (crate) fn reserve_chunks(&mut self, shared_components_key: &SharedComponentsKey, entities_count: usize) {
+        let free_space = self.chunk_groups.get(shared_components_key)
+            .and_then(|chunk_group| chunk_group.last())
+            .map_or(0, |chunk_index| self.chunk_capacity - self.chunks[*chunk_index].components_count);
 
-        let entity_position = self.chunks[chunk_number].entity_position(&entity_id);
+        let chunks_count = entities_count.saturating_sub(free_space).div_ceil(self.chunk_capacity).saturating_sub(self.reserved_chunks.len());
+
+        self.chunks.reserve(chunks_count);
+        self.reserved_chunks.extend((0..chunks_count).map(|_| (self.archetype_chunk_fabric)()));
+    }
+
+    /// Удаление сущности по ее положению. Помимо данных сущности возвращает ключ общих компонентов ее чанка
+    /// и новые положения сущностей, перемещенных при уплотнении чанков
+    pub (crate) fn remove_entity(&mut self, entity_location: EntityLocation) -> (EntityData, SharedComponentsKey, Vec<(EntityId, EntityLocation)>) {
+        let chunk_number = entity_location.chunk_index;
+        let entity_position = entity_location.row;
+
+        let mut moved_entities = Vec::new();
 
         let entity_data = self.chunks[chunk_number].remove_data(entity_position);
+        let shared_components_key = self.chunks[chunk_number].shared_components_key.clone();
+
+        // на место удаленной сущности переставляется последняя сущность чанка
+        if let Some(moved_entity_id) = self.chunks[chunk_number].entity_ids.get(entity_position) {
+            moved_entities.push((*moved_entity_id, EntityLocation::new(self.archetype_id, chunk_number, entity_position)));
+        }
+
+        let last_chunk_number = *self.chunk_groups[&shared_components_key].last().unwrap();
+
+        // если чанк не последний в группе, для более плотной упаковки перемещаем компоненты из последнего чанка группы в освободившееся место
+        if chunk_number != last_chunk_number {
+            let last_chunk_last_entity_position = self.chunks[last_chunk_number].components_count - 1;
+            let last_chunk_last_entity_data = self.chunks[last_chunk_number].remove_data(last_chunk_last_entity_position);
 
-        // если чанк после удаления компонентов пуст, значит он последний, т.к. все чанки кроме последнего должны быть полностью заняты
-        if self.chunks[chunk_number].is_empty() {
-            self.chunks.pop();
+            let moved_entity_id = last_chunk_last_entity_data.entity_id;
 
-            return entity_data;
+            // такты перемещаемой сущности сохраняются
+            self.chunks[chunk_number].set_data(last_chunk_last_entity_data, 0);
+
+            moved_entities.push((moved_entity_id, EntityLocation::new(self.archetype_id, chunk_number, self.chunks[chunk_number].components_count - 1)));
         }
 
-        let is_tail_chunk = self.chunks.len() == chunk_number + 1;
+        // пустым может оказаться только последний чанк группы
+        if self.chunks[last_chunk_number].is_empty() {
+            self.remove_chunk(last_chunk_number, &mut moved_entities);
+        }
 
-        // если чанк не последний, для более плотной упаковки перемещаем компоненты из последнего чанка в освободившееся место
-        if !is_tail_chunk {
-            let lust_chunk_number = self.chunks.len() - 1;
+        (entity_data, shared_components_key, moved_entities)
+    }
 
-            let last_chunk_last_entity_position = self.chunks[lust_chunk_number].components_count - 1;
-            let last_chunk_last_entity_data = self.chunks[lust_chunk_number].remove_data(last_chunk_last_entity_position);
+    /// Удаление пустого чанка. На его место переставляется последний чанк архетипа, положения его сущностей меняются.
+    /// Пустой чанк возвращается в запас и занимается следующим новым чанком
+    fn remove_chunk(&mut self, chunk_number: usize, moved_entities: &mut Vec<(EntityId, EntityLocation)>) {
+        let mut removed_chunk = self.chunks.swap_remove(chunk_number);
 
-            self.chunks[chunk_number].set_data(last_chunk_last_entity_data);
+        let chunk_group = self.chunk_groups.get_mut(&removed_chunk.shared_components_key).unwrap();
+        chunk_group.pop();
 
-            // если последний чанк пустой, удаляем его
-            if self.chunks[lust_chunk_number].is_empty() {
-                self.chunks.pop();
-            }
+        if chunk_group.is_empty() {
+            self.chunk_groups.remove(&removed_chunk.shared_components_key);
         }
 
-        entity_data
+        // колонки, оставшиеся у ChunkDataAccessor, новым сущностям не выдаются
+        if removed_chunk.locked_component().is_none() {
+            removed_chunk.shared_components_key = Default::default();
+            self.reserved_chunks.push(removed_chunk);
+        }
+
+        let Some(moved_chunk) = self.chunks.get(chunk_number) else {
+            return;
+        };
+
+        let moved_chunk_index = self.chunks.len();
+
+        self.chunk_groups.get_mut(&moved_chunk.shared_components_key).unwrap().iter_mut()
+            .filter(|chunk_index| **chunk_index == moved_chunk_index)
+            .for_each(|chunk_index| *chunk_index = chunk_number);
+
+        // сущность могла быть перемещена в этот чанк при уплотнении, ее положение перезаписывается
+        moved_entities.retain(|(_, entity_location)| entity_location.chunk_index != moved_chunk_index);
+
+        moved_chunk.entity_ids.iter().enumerate().for_each(|(row, entity_id)| {
+            moved_entities.push((*entity_id, EntityLocation::new(self.archetype_id, chunk_number, row)));
+        });
     }
 
     pub (crate) fn is_empty(&self) -> bool {
         self.chunks.is_empty()
     }
 
-    pub (crate) fn get_chunks(&self) -> Iter<ArchetypeChunk> {
+    pub (crate) fn get_chunks(&self) -> Iter<'_, ArchetypeChunk> {
         self.chunks.iter()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use crate::{behavior::query::ArchetypeQuery, types::{ComponentId, EntityError, AddEntityError, QueryError}};
+
+    use super::{CHECK_TICK_THRESHOLD, MAX_CHANGE_AGE, super::{EcsDataManager, entity_data_accessor::ChunkDataAccessor, test_fixtures::{TestTickComponent, TestSharedComponent, register_shared}}};
+
+    #[test]
+    fn test_entity_locations_after_removal() {
+        let mut ecs_data_manager = EcsDataManager::with_chunk_size(64);
+        ecs_data_manager.register_component::<TestTickComponent>();
+        register_shared::<TestSharedComponent>(&mut ecs_data_manager);
+
+        // две группы чанков по несколько чанков в каждой, чанки групп чередуются
+        let mut entity_ids = (0..24).map(|value| ecs_data_manager.spawn((TestTickComponent(value), TestSharedComponent(value % 2))).unwrap()).collect::<Vec<_>>();
+
+        let archetype_id = ecs_data_manager.entity_location(entity_ids[0]).unwrap().archetype_id();
+        let chunks_count = ecs_data_manager.archetypes[*archetype_id].chunks.len();
+        assert!(chunks_count > 4);
+
+        // удаление из середины, из последних чанков групп и из первых, пока архетип не опустеет
+        let mut position = 7;
+
+        while !entity_ids.is_empty() {
+            position = (position * 5 + 3) % entity_ids.len();
+            ecs_data_manager.remove_entity(entity_ids.swap_remove(position)).unwrap();
+
+            let archetype = &ecs_data_manager.archetypes[*archetype_id];
+
+            // положение ведет в строку этой же сущности, значения переехали вместе с ней
+            for entity_id in entity_ids.iter() {
+                let entity_location = ecs_data_manager.entity_location(*entity_id).unwrap();
+                assert_eq!(archetype.chunks[entity_location.chunk_index()].entity_ids[entity_location.row()], *entity_id);
+
+                let value = ecs_data_manager.get::<TestTickComponent>(*entity_id).unwrap().0;
+                assert_eq!(value as usize, entity_id.id());
+                assert_eq!(ecs_data_manager.get::<TestSharedComponent>(*entity_id).unwrap().0, value % 2);
+            }
+
+            // пустых чанков нет, все чанки группы кроме последнего заполнены
+            assert!(archetype.chunks.iter().all(|archetype_chunk| archetype_chunk.components_count != 0));
+
+            for chunk_group in archetype.chunk_groups.values() {
+                assert!(chunk_group[..chunk_group.len() - 1].iter().all(|chunk_index| archetype.chunks[*chunk_index].is_filled()));
+            }
+
+            assert_eq!(archetype.chunk_groups.values().map(|chunk_group| chunk_group.len()).sum::<usize>(), archetype.chunks.len());
+        }
+
+        // опустевшие чанки возвращаются в резерв и используются повторно
+        assert!(ecs_data_manager.archetypes[*archetype_id].chunks.is_empty());
+        assert_eq!(ecs_data_manager.archetypes[*archetype_id].reserved_chunks.len(), chunks_count);
+
+        let entity_id = ecs_data_manager.spawn((TestTickComponent(0), TestSharedComponent(5))).unwrap();
+        assert_eq!(ecs_data_manager.archetypes[*archetype_id].reserved_chunks.len(), chunks_count - 1);
+        assert_eq!(ecs_data_manager.get::<TestSharedComponent>(entity_id).unwrap().0, 5);
+    }
+
+    #[test]
+    fn test_change_ticks_wrap_around() {
+        let mut ecs_data_manager = EcsDataManager::new();
+        let component_id = ecs_data_manager.register_component::<TestTickComponent>();
+
+        ecs_data_manager.change_tick = u32::MAX - 1;
+        let entity_old = ecs_data_manager.spawn((TestTickComponent(0),)).unwrap();
+
+        ecs_data_manager.increment_change_tick();
+        ecs_data_manager.increment_change_tick();
+        assert_eq!(ecs_data_manager.change_tick(), 0);
+
+        let entity_new = ecs_data_manager.spawn((TestTickComponent(0),)).unwrap();
+
+        let query = |since_tick| ArchetypeQuery::new(Some(HashSet::from([component_id])), None, None, None).with_added::<TestTickComponent>(since_tick);
+
+        assert_eq!(ecs_data_manager.query_entities(&query(u32::MAX - 2)).unwrap(), vec![entity_old, entity_new]);
+        assert_eq!(ecs_data_manager.query_entities(&query(u32::MAX - 1)).unwrap(), vec![entity_new]);
+        assert!(ecs_data_manager.query_entities(&query(0)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_old_change_ticks_are_clamped() {
+        let mut ecs_data_manager = EcsDataManager::new();
+        ecs_data_manager.register_component::<TestTickComponent>();
+
+        let entity_id = ecs_data_manager.spawn((TestTickComponent(0),)).unwrap();
+
+        ecs_data_manager.change_tick = 3 * CHECK_TICK_THRESHOLD - 1;
+        ecs_data_manager.increment_change_tick();
+
+        let entity_location = ecs_data_manager.entity_location(entity_id).unwrap();
+        let components_array = ecs_data_manager.archetypes[*entity_location.archetype_id].chunks[entity_location.chunk_index].get_components_array(&ComponentId::from_type::<TestTickComponent>()).unwrap();
+
+        let component_ticks = components_array.get_components_ticks().try_read().unwrap()[entity_location.row];
+
+        assert_eq!(component_ticks.added, 3 * CHECK_TICK_THRESHOLD - MAX_CHANGE_AGE);
+        assert_eq!(component_ticks.changed, 3 * CHECK_TICK_THRESHOLD - MAX_CHANGE_AGE);
+    }
+
+    #[test]
+    fn test_held_columns_return_errors() {
+        let mut ecs_data_manager = EcsDataManager::new();
+        let component_id = ecs_data_manager.register_component::<TestTickComponent>();
+
+        ecs_data_manager.increment_change_tick();
+        let entity_id = ecs_data_manager.spawn((TestTickComponent(0),)).unwrap();
+
+        let query = ArchetypeQuery::new(Some(HashSet::from([component_id])), None, None, None).with_changed::<TestTickComponent>(0);
+        let (chunk, rows) = ecs_data_manager.query_chunk_rows(&query).unwrap().pop().unwrap();
+
+        let mut chunk_data_accessor = ChunkDataAccessor::default();
+        chunk_data_accessor.fill_data_from_chunk(vec![(component_id, false)], chunk, rows, &ecs_data_manager, 0, 0);
+        let components_accessor = chunk_data_accessor.resolve_rw_components::<TestTickComponent>().unwrap();
+
+        tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(async {
+            let _components = components_accessor.write().await;
+
+            // такты строк захвачены системой: запрос не ждет и не паникует
+            assert!(matches!(ecs_data_manager.query_entities(&query), Err(QueryError::ComponentLocked { .. })));
+        });
+
+        // колонку удерживает доступ системы, чанк не перестраивается
+        assert!(matches!(ecs_data_manager.remove_entity(entity_id), Err(EntityError::ComponentLocked { .. })));
+        assert!(matches!(ecs_data_manager.spawn((TestTickComponent(1),)), Err(AddEntityError::ComponentLocked { .. })));
+
+        drop(components_accessor);
+        drop(chunk_data_accessor);
+
+        assert_eq!(ecs_data_manager.query_entities(&query).unwrap(), vec![entity_id]);
+        ecs_data_manager.remove_entity(entity_id).unwrap();
+    }
+}
@@ -0,0 +1,68 @@
+use std::fmt::Debug;
+
+use crate::types::{EntityId, CommandResult, CommandError};
+
+use super::{EcsDataManager, new_entity_components_info::INewEntityComponentsInfo};
+
+pub trait CommandClosure = FnOnce(&mut EcsDataManager) -> CommandResult<()> + Sync + Send;
+
+/// Отложенные структурные изменения. Записываются там, где EcsDataManager недоступен на запись, применяются позже по порядку записи
+#[derive(Default)]
+pub struct Commands {
+    commands: Vec<Box<dyn CommandClosure>>,
+}
+
+impl Debug for Commands {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Commands")
+            .field("commands_count", &self.commands.len())
+            .finish()
+    }
+}
+
+impl Commands {
+    pub fn new() -> Self {
+        Self { ..Default::default() }
+    }
+
+    pub fn add(&mut self, command: impl CommandClosure + 'static) -> &mut Self {
+        self.commands.push(Box::new(command));
+        self
+    }
+
+    pub fn spawn<TComponents: INewEntityComponentsInfo>(&mut self, components: TComponents) -> &mut Self {
+        self.add(move |ecs_data_manager| {
+            ecs_data_manager.spawn(components)?;
+            Ok(())
+        })
+    }
+
+    /// Сущность, удаленная до применения команды, дает ошибку NoSuchEntity
+    pub fn despawn(&mut self, entity_id: EntityId) -> &mut Self {
+        self.add(move |ecs_data_manager| Ok(ecs_data_manager.remove_entity(entity_id)?))
+    }
+
+    pub fn insert_component<TComponent: Debug + Sync + Send + 'static>(&mut self, entity_id: EntityId, component: TComponent) -> &mut Self {
+        self.add(move |ecs_data_manager| Ok(ecs_data_manager.insert_component(entity_id, component)?))
+    }
+
+    pub fn remove_component<TComponent: Debug + Sync + Send + 'static>(&mut self, entity_id: EntityId) -> &mut Self {
+        self.add(move |ecs_data_manager| {
+            ecs_data_manager.remove_component::<TComponent>(entity_id)?;
+            Ok(())
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    /// Применение команд в порядке записи, буфер очищается. Ошибка команды не прерывает применение остальных
+    pub fn apply(&mut self, ecs_data_manager: &mut EcsDataManager) -> Vec<CommandError> {
+        self.commands.drain(..).filter_map(|command| command(ecs_data_manager).err()).collect()
+    }
+}
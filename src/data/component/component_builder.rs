@@ -0,0 +1,209 @@
+use std::{fmt::Debug, marker::PhantomData, sync::Arc};
+
+use crate::{types::{ComponentId, StableComponentId, RegisterComponentResult, RegisterComponentError}, data::EcsDataManager};
+
+use crate::data::{snapshot::snapshot_io::ISnapshotComponent, text::text_io::ITextComponent};
+
+use super::{storage_type::StorageType, component_info::{ComponentInfo, ComponentHookClosure, SnapshotWriteFn, SnapshotReadFn, ComponentCloneFn, ComponentCloneIntoFn, TagDefaultFn, component_clone, component_clone_into, tag_default, snapshot_write, snapshot_read, TextWriteFn, TextReadFn, text_write, text_read}, sparse_set::ComponentSparseSet};
+
+pub struct ComponentBuilder<'a, TComponent: Debug + Sync + Send + 'static> {
+    ecs_data_manager: &'a mut EcsDataManager,
+
+    storage_type: StorageType,
+    tag_default: Option<TagDefaultFn>,
+    // для общих компонентов, сравнение и клонирование значений доступны только при дополнительных ограничениях типа
+    shared_component_info_fabric: Option<fn(usize) -> ComponentInfo>,
+
+    on_add: Option<Arc<dyn ComponentHookClosure + Sync + Send>>,
+    on_insert: Option<Arc<dyn ComponentHookClosure + Sync + Send>>,
+    on_remove: Option<Arc<dyn ComponentHookClosure + Sync + Send>>,
+
+    stable_id: Option<StableComponentId>,
+    // клонирование в упакованное значение и сразу в хранилище пачки
+    clone: Option<(ComponentCloneFn, ComponentCloneIntoFn)>,
+    // запись в снимок доступна только при дополнительном ограничении типа
+    snapshot_hooks: Option<(SnapshotWriteFn, SnapshotReadFn)>,
+    text_hooks: Option<(TextWriteFn, TextReadFn)>,
+
+    _component: PhantomData<TComponent>,
+}
+
+impl<'a, TComponent: Debug + Sync + Send + 'static> ComponentBuilder<'a, TComponent> {
+    pub (crate) fn new(ecs_data_manager: &'a mut EcsDataManager) -> Self {
+        Self {
+            ecs_data_manager,
+            storage_type: Default::default(),
+            tag_default: None,
+            shared_component_info_fabric: None,
+            on_add: None,
+            on_insert: None,
+            on_remove: None,
+            stable_id: None,
+            clone: None,
+            snapshot_hooks: None,
+            text_hooks: None,
+            _component: PhantomData,
+        }
+    }
+
+    pub fn storage_type(&mut self, storage_type: StorageType) -> &mut Self {
+        self.storage_type = storage_type;
+        self
+    }
+
+    /// Общий компонент: одно значение на чанк, чанки архетипа группируются по значению
+    pub fn shared(&mut self) -> &mut Self where TComponent: PartialEq + Clone {
+        self.storage_type = StorageType::Shared;
+        self.shared_component_info_fabric = Some(ComponentInfo::new_shared::<TComponent>);
+        self
+    }
+
+    /// Значение метки не хранится, `remove_component` метки возвращает `TComponent::default()`. Без этого - None
+    pub fn tag_default(&mut self) -> &mut Self where TComponent: Default {
+        self.tag_default = Some(tag_default::<TComponent>);
+        self
+    }
+
+    /// Хук появления компонента у сущности: создание сущности или добавление отсутствовавшего компонента
+    pub fn on_add(&mut self, hook: impl ComponentHookClosure + Sync + Send + 'static) -> &mut Self {
+        self.on_add = Some(Arc::new(hook));
+        self
+    }
+
+    /// Хук записи значения компонента, вызывается после on_add и при замене значения
+    pub fn on_insert(&mut self, hook: impl ComponentHookClosure + Sync + Send + 'static) -> &mut Self {
+        self.on_insert = Some(Arc::new(hook));
+        self
+    }
+
+    /// Хук удаления компонента, вызывается до удаления, в том числе при удалении сущности: значение компонента еще доступно
+    pub fn on_remove(&mut self, hook: impl ComponentHookClosure + Sync + Send + 'static) -> &mut Self {
+        self.on_remove = Some(Arc::new(hook));
+        self
+    }
+
+    /// Стабильное имя или номер компонента, по которому компонент находится в сохранениях и сетевых сообщениях.
+    /// Имя вида `#123` отклоняется при регистрации: в тексте оно читается как номер
+    pub fn stable_id(&mut self, stable_id: impl Into<StableComponentId>) -> &mut Self {
+        self.stable_id = Some(stable_id.into());
+        self
+    }
+
+    /// Значения компонента клонируются, это нужно для создания сущностей из шаблонов (Prefab)
+    pub fn cloneable(&mut self) -> &mut Self where TComponent: Clone {
+        self.clone = Some((component_clone::<TComponent>, component_clone_into::<TComponent>));
+        self
+    }
+
+    /// Значения компонента сохраняются в снимок EcsDataManager. Для снимка компоненту нужен и стабильный идентификатор
+    pub fn snapshot(&mut self) -> &mut Self where TComponent: ISnapshotComponent {
+        self.snapshot_hooks = Some((snapshot_write::<TComponent>, snapshot_read::<TComponent>));
+        self
+    }
+
+    /// Значения компонента пишутся в текстовый формат сцены под стабильным идентификатором компонента
+    pub fn text(&mut self) -> &mut Self where TComponent: ITextComponent {
+        self.text_hooks = Some((text_write::<TComponent>, text_read::<TComponent>));
+        self
+    }
+
+    /// Повторная регистрация компонента игнорируется, если не запрошен другой стабильный идентификатор
+    pub fn build(self) -> RegisterComponentResult<ComponentId> {
+        let component_id = ComponentId::from_type::<TComponent>();
+
+        if let Some(component_info) = self.ecs_data_manager.components_info.get(&component_id) {
+            return match self.stable_id {
+                Some(stable_id) if component_info.stable_id.as_ref() != Some(&stable_id) => {
+                    Err(RegisterComponentError::StableIdMismatch { component_id, registered: component_info.stable_id.clone(), requested: stable_id })
+                },
+                _ => Ok(component_id),
+            };
+        }
+
+        if let Some(stable_id) = &self.stable_id {
+            self.ecs_data_manager.check_stable_id(stable_id)?;
+        }
+
+        // сравнение и клонирование значений задает только `shared`
+        let shared_component_info_fabric = match (self.storage_type, self.shared_component_info_fabric) {
+            (StorageType::Shared, None) => return Err(RegisterComponentError::SharedStorageRequiresShared { component_id }),
+            (_, shared_component_info_fabric) => shared_component_info_fabric,
+        };
+
+        if self.storage_type == StorageType::SparseSet {
+            self.ecs_data_manager.sparse_sets.insert(component_id, Box::new(ComponentSparseSet::<TComponent>::new()));
+        }
+
+        let component_index = self.ecs_data_manager.components_info.len();
+
+        let mut component_info = match self.storage_type {
+            StorageType::Shared => (shared_component_info_fabric.unwrap())(component_index),
+            storage_type => ComponentInfo::new::<TComponent>(component_index, storage_type),
+        };
+
+        component_info.tag_default = self.tag_default.filter(|_| component_info.is_tag);
+        component_info.on_add = self.on_add;
+        component_info.on_insert = self.on_insert;
+        component_info.on_remove = self.on_remove;
+        // у общего компонента клонирование уже задано
+        component_info.clone = self.clone.map(|(clone, _)| clone).or(component_info.clone);
+        component_info.clone_into = self.clone.map(|(_, clone_into)| clone_into).or(component_info.clone_into);
+
+        component_info.snapshot_write = self.snapshot_hooks.map(|(snapshot_write, _)| snapshot_write);
+        component_info.snapshot_read = self.snapshot_hooks.map(|(_, snapshot_read)| snapshot_read);
+        component_info.text_write = self.text_hooks.map(|(text_write, _)| text_write);
+        component_info.text_read = self.text_hooks.map(|(_, text_read)| text_read);
+
+        self.ecs_data_manager.insert_component_info(component_id, component_info, self.stable_id);
+
+        Ok(component_id)
+    }
+}
+#[cfg(test)]
+mod test {
+    use crate::types::{StableComponentId, RegisterComponentError};
+
+    use super::super::super::{EcsDataManager, component::dynamic_component::DynamicComponentDescriptor, test_fixtures::{TestComponentA, TestComponentB, TestTickComponent, register_with}};
+
+    #[test]
+    fn test_stable_ids() {
+        let mut ecs_data_manager = EcsDataManager::new();
+
+        let tick_component_id = register_with::<TestTickComponent>(&mut ecs_data_manager, |component_builder| {
+            component_builder.stable_id("test::TestTickComponent");
+        });
+
+        let numbered_component_id = register_with::<TestComponentA>(&mut ecs_data_manager, |component_builder| {
+            component_builder.stable_id(5u64);
+        });
+
+        assert_eq!(ecs_data_manager.component_id_by_stable_id(&"test::TestTickComponent".into()), Some(tick_component_id));
+        assert_eq!(ecs_data_manager.component_id_by_stable_id(&5u64.into()), Some(numbered_component_id));
+        assert_eq!(ecs_data_manager.stable_component_id(&tick_component_id), Some(&StableComponentId::from("test::TestTickComponent")));
+        assert_eq!(ecs_data_manager.stable_component_id(&numbered_component_id).unwrap().to_string().parse(), Ok(StableComponentId::Id(5)));
+
+        // идентификатор занят другим компонентом
+        let mut component_builder = ecs_data_manager.get_component_builder::<TestComponentB>();
+        component_builder.stable_id("test::TestTickComponent");
+        assert!(matches!(component_builder.build(), Err(RegisterComponentError::DuplicateStableId { component_id, .. }) if component_id == tick_component_id));
+
+        // имя, которое в тексте читается как номер, не пережило бы сохранение
+        for stable_name in ["#7", "#07", "#+7"] {
+            let mut component_builder = ecs_data_manager.get_component_builder::<TestComponentB>();
+            component_builder.stable_id(stable_name);
+            assert!(matches!(component_builder.build(), Err(RegisterComponentError::InvalidStableName { .. })), "{stable_name}");
+        }
+
+        let result = ecs_data_manager.register_dynamic_component(DynamicComponentDescriptor::new("#7", 4, 4));
+        assert!(matches!(result, Err(RegisterComponentError::InvalidStableName { .. })));
+
+        // неудачная регистрация идентификатор не занимает
+        assert_eq!(ecs_data_manager.component_id_by_stable_id(&7u64.into()), None);
+
+        let component_b_id = register_with::<TestComponentB>(&mut ecs_data_manager, |component_builder| {
+            component_builder.stable_id("#name");
+        });
+
+        assert_eq!(ecs_data_manager.component_id_by_stable_id(&"#name".parse().unwrap()), Some(component_b_id));
+    }
+}
@@ -1,32 +1,216 @@
-use crate::types::ComponentId;
+use crate::types::{ComponentId, EntityId, StableComponentId};
+use crate::data::{EcsDataManager, commands::Commands};
 
 use crate::data::archetype::{IComponentsArray, ComponentsArray};
+use crate::data::new_entity_components_info::NewEntitiesBatchWriter;
+use crate::data::snapshot::snapshot_io::{ISnapshotComponent, SnapshotWriter, SnapshotReader};
+use crate::data::text::text_io::{ITextComponent, TextValue, TextContext};
+use crate::types::{SnapshotResult, TextResult};
 
+use super::{storage_type::StorageType, dynamic_component::{DynamicComponentDescriptor, DynamicComponentsArray, DynamicComponent}};
+
+use std::any::Any;
 use std::fmt::Debug;
 use std::sync::Arc;
 
 trait ComponentArrayBuildClosure = Fn(usize) -> Box<dyn IComponentsArray>;
+/// Хук видит менеджер целиком, в том числе значение компонента, изменения записываются командами
+pub trait ComponentHookClosure = Fn(EntityId, &EcsDataManager, &mut Commands);
+pub (crate) type ComponentCloneFn = fn(&dyn Any) -> Box<dyn Any + Sync + Send>;
+pub (crate) type ComponentCloneIntoFn = fn(&dyn Any, &mut NewEntitiesBatchWriter);
+pub (crate) type SharedValueEqFn = fn(&dyn Any, &dyn Any) -> bool;
+pub (crate) type TagDefaultFn = fn() -> Box<dyn Any + Sync + Send>;
+pub (crate) type SnapshotWriteFn = fn(&dyn Any, &mut SnapshotWriter);
+pub (crate) type SnapshotReadFn = fn(&mut SnapshotReader) -> SnapshotResult<Box<dyn Any + Sync + Send>>;
+pub (crate) type TextWriteFn = fn(&dyn Any, &TextContext) -> TextValue;
+pub (crate) type TextReadFn = fn(&TextValue, &TextContext) -> TextResult<Box<dyn Any + Sync + Send>>;
 
 pub struct ComponentInfo {
     pub (crate) component_id: ComponentId,
+    // имя типа или динамического компонента, для сообщений об ошибках
+    pub (crate) name: String,
+    // плотный индекс компонента, номер бита в сигнатуре архетипа
+    pub (crate) index: usize,
+    pub (crate) storage_type: StorageType,
     pub (crate) component_array_fabric_cloure: Arc<dyn ComponentArrayBuildClosure + Sync + Send>,
     pub (crate) size: usize,
+    pub (crate) align: usize,
+    // компонент нулевого размера в колонках (метка): участвует только в сигнатуре архетипа, колонка в чанке не создается
+    pub (crate) is_tag: bool,
+    // значение метки, возвращаемое при удалении (ComponentBuilder::tag_default)
+    pub (crate) tag_default: Option<TagDefaultFn>,
+    // сравнение и клонирование значений общего компонента (StorageType::Shared)
+    pub (crate) shared_value_eq: Option<SharedValueEqFn>,
+    pub (crate) shared_value_clone: Option<ComponentCloneFn>,
+    // клонирование значения, например при создании сущностей из шаблона (Prefab)
+    pub (crate) clone: Option<ComponentCloneFn>,
+    // клонирование значения сразу в хранилище для каждой сущности пачки
+    pub (crate) clone_into: Option<ComponentCloneIntoFn>,
+    // хуки жизненного цикла: компонент появился у сущности, записано значение (в том числе замена), компонент удаляется
+    pub (crate) on_add: Option<Arc<dyn ComponentHookClosure + Sync + Send>>,
+    pub (crate) on_insert: Option<Arc<dyn ComponentHookClosure + Sync + Send>>,
+    pub (crate) on_remove: Option<Arc<dyn ComponentHookClosure + Sync + Send>>,
+    // описание компонента, заданного во время выполнения
+    pub (crate) dynamic_descriptor: Option<DynamicComponentDescriptor>,
+    // идентификатор, не зависящий от сборки
+    pub (crate) stable_id: Option<StableComponentId>,
+    // запись и чтение значения в снимке EcsDataManager
+    pub (crate) snapshot_write: Option<SnapshotWriteFn>,
+    pub (crate) snapshot_read: Option<SnapshotReadFn>,
+    // запись и чтение значения в текстовом формате сцены
+    pub (crate) text_write: Option<TextWriteFn>,
+    pub (crate) text_read: Option<TextReadFn>,
 }
 
 impl Debug for ComponentInfo {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("ComponentInfo")
             .field("component_id", &self.component_id)
-            .field("component_array_fabric_cloure", &"closure").finish()
+            .field("name", &self.name)
+            .field("index", &self.index)
+            .field("storage_type", &self.storage_type)
+            .field("component_array_fabric_cloure", &"closure")
+            .field("size", &self.size)
+            .field("align", &self.align)
+            .field("is_tag", &self.is_tag)
+            .field("tag_default", &self.tag_default.map(|_| "fn"))
+            .field("shared_value_eq", &self.shared_value_eq.map(|_| "fn"))
+            .field("shared_value_clone", &self.shared_value_clone.map(|_| "fn"))
+            .field("clone", &self.clone.map(|_| "fn"))
+            .field("clone_into", &self.clone_into.map(|_| "fn"))
+            .field("on_add", &self.on_add.as_ref().map(|_| "closure"))
+            .field("on_insert", &self.on_insert.as_ref().map(|_| "closure"))
+            .field("on_remove", &self.on_remove.as_ref().map(|_| "closure"))
+            .field("dynamic_descriptor", &self.dynamic_descriptor)
+            .field("stable_id", &self.stable_id)
+            .field("snapshot_write", &self.snapshot_write.map(|_| "fn"))
+            .field("snapshot_read", &self.snapshot_read.map(|_| "fn"))
+            .field("text_write", &self.text_write.map(|_| "fn"))
+            .field("text_read", &self.text_read.map(|_| "fn")).finish()
     }
 }
 
 impl ComponentInfo {
-    pub fn new<TComponent: Debug + Sync + Send + 'static>() -> Self {
+    pub fn new<TComponent: Debug + Sync + Send + 'static>(index: usize, storage_type: StorageType) -> Self {
         Self {
             component_id: ComponentId::from_type::<TComponent>(),
-            component_array_fabric_cloure: Arc::new(|size: usize| Box::new(ComponentsArray::<TComponent>::new()) as Box<dyn IComponentsArray>),
+            name: std::any::type_name::<TComponent>().to_string(),
+            index,
+            storage_type,
+            component_array_fabric_cloure: Arc::new(|capacity: usize| Box::new(ComponentsArray::<TComponent>::new(capacity)) as Box<dyn IComponentsArray>),
             size: std::mem::size_of::<TComponent>(),
+            align: std::mem::align_of::<TComponent>(),
+            is_tag: std::mem::size_of::<TComponent>() == 0 && storage_type == StorageType::Table,
+            tag_default: None,
+            shared_value_eq: None,
+            shared_value_clone: None,
+            clone: None,
+            clone_into: None,
+            on_add: None,
+            on_insert: None,
+            on_remove: None,
+            dynamic_descriptor: None,
+            stable_id: None,
+            snapshot_write: None,
+            snapshot_read: None,
+            text_write: None,
+            text_read: None,
+        }
+    }
+
+    /// Динамический компонент всегда хранится в колонках чанков, даже при нулевом размере.
+    /// В снимок и текст значение пишется байтами, а клонируется копированием байтов, если у компонента нет `drop` (значение не владеет ресурсами)
+    pub (crate) fn new_dynamic(component_id: ComponentId, index: usize, dynamic_descriptor: DynamicComponentDescriptor) -> Self {
+        let layout = dynamic_descriptor.layout();
+        let fabric_descriptor = dynamic_descriptor.clone();
+
+        Self {
+            component_id,
+            name: dynamic_descriptor.name.clone(),
+            index,
+            storage_type: StorageType::Table,
+            component_array_fabric_cloure: Arc::new(move |capacity: usize| Box::new(DynamicComponentsArray::new(component_id, &fabric_descriptor, capacity)) as Box<dyn IComponentsArray>),
+            size: layout.size(),
+            align: layout.align(),
+            is_tag: false,
+            tag_default: None,
+            shared_value_eq: None,
+            shared_value_clone: None,
+            clone: dynamic_descriptor.drop.is_none().then_some(dynamic_clone as ComponentCloneFn),
+            clone_into: dynamic_descriptor.drop.is_none().then_some(dynamic_clone_into as ComponentCloneIntoFn),
+            on_add: None,
+            on_insert: None,
+            on_remove: None,
+            snapshot_write: dynamic_descriptor.drop.is_none().then_some(dynamic_snapshot_write as SnapshotWriteFn),
+            snapshot_read: None,
+            text_write: dynamic_descriptor.drop.is_none().then_some(dynamic_text_write as TextWriteFn),
+            text_read: None,
+            dynamic_descriptor: Some(dynamic_descriptor),
+            stable_id: None,
+        }
+    }
+
+    /// Общий компонент хранится одним значением на чанк, меткой он не считается даже при нулевом размере
+    pub (crate) fn new_shared<TComponent: Debug + PartialEq + Clone + Sync + Send + 'static>(index: usize) -> Self {
+        Self {
+            is_tag: false,
+            tag_default: None,
+            shared_value_eq: Some(shared_value_eq::<TComponent>),
+            shared_value_clone: Some(component_clone::<TComponent>),
+            clone: Some(component_clone::<TComponent>),
+            clone_into: Some(component_clone_into::<TComponent>),
+            ..Self::new::<TComponent>(index, StorageType::Shared)
         }
     }
-}
\ No newline at end of file
+}
+
+fn shared_value_eq<TComponent: PartialEq + 'static>(left: &dyn Any, right: &dyn Any) -> bool {
+    unsafe { *(left as *const dyn Any as *const TComponent) == *(right as *const dyn Any as *const TComponent) }
+}
+
+pub (crate) fn component_clone<TComponent: Clone + Sync + Send + 'static>(value: &dyn Any) -> Box<dyn Any + Sync + Send> {
+    Box::new(unsafe { &*(value as *const dyn Any as *const TComponent) }.clone())
+}
+
+pub (crate) fn component_clone_into<TComponent: Clone + Debug + Sync + Send + 'static>(value: &dyn Any, batch_writer: &mut NewEntitiesBatchWriter) {
+    batch_writer.write_cloned(unsafe { &*(value as *const dyn Any as *const TComponent) })
+}
+
+fn dynamic_clone(value: &dyn Any) -> Box<dyn Any + Sync + Send> {
+    Box::new(value.downcast_ref::<DynamicComponent>().unwrap().copy_bytes())
+}
+
+fn dynamic_clone_into(value: &dyn Any, batch_writer: &mut NewEntitiesBatchWriter) {
+    batch_writer.write_dynamic_cloned(value.downcast_ref::<DynamicComponent>().unwrap())
+}
+
+pub (crate) fn snapshot_write<TComponent: ISnapshotComponent + 'static>(value: &dyn Any, writer: &mut SnapshotWriter) {
+    unsafe { &*(value as *const dyn Any as *const TComponent) }.write_snapshot(writer)
+}
+
+pub (crate) fn snapshot_read<TComponent: ISnapshotComponent + Sync + Send + 'static>(reader: &mut SnapshotReader) -> SnapshotResult<Box<dyn Any + Sync + Send>> {
+    Ok(Box::new(TComponent::read_snapshot(reader)?))
+}
+
+/// Значение строки динамической колонки передается байтами (Vec<u8>), см. DynamicComponentsArray::for_each_component.
+/// Читается значение в EcsDataManager, которому известно описание компонента
+fn dynamic_snapshot_write(value: &dyn Any, writer: &mut SnapshotWriter) {
+    writer.write_bytes(value.downcast_ref::<Vec<u8>>().unwrap())
+}
+
+pub (crate) fn text_write<TComponent: ITextComponent + 'static>(value: &dyn Any, context: &TextContext) -> TextValue {
+    unsafe { &*(value as *const dyn Any as *const TComponent) }.write_text(context)
+}
+
+pub (crate) fn text_read<TComponent: ITextComponent + Sync + Send + 'static>(value: &TextValue, context: &TextContext) -> TextResult<Box<dyn Any + Sync + Send>> {
+    Ok(Box::new(TComponent::read_text(value, context)?))
+}
+
+/// Байты динамического компонента пишутся массивом чисел
+fn dynamic_text_write(value: &dyn Any, _context: &TextContext) -> TextValue {
+    TextValue::array(value.downcast_ref::<Vec<u8>>().unwrap().iter().map(|byte| TextValue::integer(*byte)))
+}
+
+pub (crate) fn tag_default<TComponent: Default + Sync + Send + 'static>() -> Box<dyn Any + Sync + Send> {
+    Box::new(TComponent::default())
+}
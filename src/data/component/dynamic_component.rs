@@ -0,0 +1,325 @@
+use std::{alloc::Layout, any::Any, fmt::Debug, ptr::NonNull, sync::{Arc, atomic::{AtomicU32, Ordering}}};
+
+use tokio::sync::{RwLock, TryLockError};
+
+use crate::{types::ComponentId, data::archetype::{IComponentsArray, ComponentTicks, write_exclusive, check_column_ticks}};
+
+pub type DynamicComponentDropFn = unsafe fn(*mut u8);
+
+/// Компонент, описанный во время выполнения (скрипты, моды). Значение - байты заданного размера и выравнивания,
+/// `drop` вызывается для значения при его уничтожении.
+///
+/// Выравнивание - степень двойки, размер кратен выравниванию: значения лежат в колонке подряд, без дополнительного заполнения.
+/// Компонент без `drop` - простые байты: значения копируются побайтно (снимки, текст, префабы) и не должны владеть ресурсами
+#[derive(Debug, Clone)]
+pub struct DynamicComponentDescriptor {
+    pub name: String,
+    pub size: usize,
+    pub align: usize,
+    pub drop: Option<DynamicComponentDropFn>,
+}
+
+impl DynamicComponentDescriptor {
+    pub fn new(name: impl Into<String>, size: usize, align: usize) -> Self {
+        Self {
+            name: name.into(),
+            size,
+            align,
+            drop: None,
+        }
+    }
+
+    pub fn with_drop(mut self, drop: DynamicComponentDropFn) -> Self {
+        self.drop = Some(drop);
+        self
+    }
+
+    /// Размер кратен выравниванию, как у типов Rust
+    pub (crate) fn is_valid_layout(&self) -> bool {
+        Layout::from_size_align(self.size, self.align).is_ok_and(|layout| layout.size() == layout.pad_to_align().size())
+    }
+
+    pub (crate) fn layout(&self) -> Layout {
+        Layout::from_size_align(self.size, self.align).expect("dynamic component layout is checked on registration")
+    }
+}
+
+/// Начало буфера без выделения памяти, выровненное для значений нулевого размера
+fn dangling(layout: Layout) -> NonNull<u8> {
+    NonNull::new(layout.align() as *mut u8).unwrap()
+}
+
+/// Значение динамического компонента вне чанка: при создании сущности, при переносе между архетипами и после удаления
+pub struct DynamicComponent {
+    component_id: ComponentId,
+    data: NonNull<u8>,
+    layout: Layout,
+    drop: Option<DynamicComponentDropFn>,
+}
+
+// значение передается между потоками и читается из нескольких потоков одновременно,
+// это гарантирует вызывающий EcsDataManager::new_dynamic_component
+unsafe impl Send for DynamicComponent {}
+unsafe impl Sync for DynamicComponent {}
+
+impl Debug for DynamicComponent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DynamicComponent")
+            .field("component_id", &self.component_id)
+            .field("data", &self.as_bytes())
+            .finish()
+    }
+}
+
+impl DynamicComponent {
+    /// Байты значения копируются, значение переходит во владение компонента.
+    /// Safety: `bytes` - корректное значение компонента размера `descriptor.size`, исходное значение больше не уничтожается
+    pub (crate) unsafe fn from_bytes(component_id: ComponentId, descriptor: &DynamicComponentDescriptor, bytes: &[u8]) -> Self {
+        debug_assert_eq!(bytes.len(), descriptor.size);
+
+        Self::from_bytes_with_layout(component_id, descriptor.layout(), descriptor.drop, bytes)
+    }
+
+    unsafe fn from_bytes_with_layout(component_id: ComponentId, layout: Layout, drop: Option<DynamicComponentDropFn>, bytes: &[u8]) -> Self {
+        let dynamic_component = Self::alloc(component_id, layout, drop);
+        std::ptr::copy_nonoverlapping(bytes.as_ptr(), dynamic_component.data.as_ptr(), bytes.len());
+        dynamic_component
+    }
+
+    fn alloc(component_id: ComponentId, layout: Layout, drop: Option<DynamicComponentDropFn>) -> Self {
+        let data = match layout.size() {
+            0 => dangling(layout),
+            _ => NonNull::new(unsafe { std::alloc::alloc(layout) }).unwrap_or_else(|| std::alloc::handle_alloc_error(layout)),
+        };
+
+        Self { component_id, data, layout, drop }
+    }
+
+    pub fn component_id(&self) -> ComponentId {
+        self.component_id
+    }
+
+    /// Копия байтов значения. Корректна только для компонента без `drop`, значение которого не владеет ресурсами
+    pub (crate) fn copy_bytes(&self) -> Self {
+        unsafe { Self::from_bytes_with_layout(self.component_id, self.layout, self.drop, self.as_bytes()) }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.data.as_ptr(), self.layout.size()) }
+    }
+
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.data.as_ptr(), self.layout.size()) }
+    }
+}
+
+impl Drop for DynamicComponent {
+    fn drop(&mut self) {
+        if let Some(drop) = self.drop {
+            unsafe { drop(self.data.as_ptr()) };
+        }
+
+        if self.layout.size() != 0 {
+            unsafe { std::alloc::dealloc(self.data.as_ptr(), self.layout) };
+        }
+    }
+}
+
+/// Колонка динамического компонента: значения подряд, с шагом в размер компонента
+pub struct DynamicComponentsColumn {
+    data: NonNull<u8>,
+    len: usize,
+    capacity: usize,
+    layout: Layout,
+    drop: Option<DynamicComponentDropFn>,
+}
+
+// значения колонки - значения DynamicComponent, условия те же
+unsafe impl Send for DynamicComponentsColumn {}
+unsafe impl Sync for DynamicComponentsColumn {}
+
+impl Debug for DynamicComponentsColumn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DynamicComponentsColumn")
+            .field("len", &self.len)
+            .field("capacity", &self.capacity)
+            .field("layout", &self.layout)
+            .finish()
+    }
+}
+
+impl DynamicComponentsColumn {
+    fn new(layout: Layout, drop: Option<DynamicComponentDropFn>, capacity: usize) -> Self {
+        let mut dynamic_components_column = Self { data: dangling(layout), len: 0, capacity: 0, layout, drop };
+        dynamic_components_column.grow(capacity);
+        dynamic_components_column
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Размер одного значения в байтах
+    pub fn item_size(&self) -> usize {
+        self.layout.size()
+    }
+
+    /// Байты всех значений колонки, значение строки `row` начинается со смещения `row * item_size()`
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.data.as_ptr(), self.len * self.layout.size()) }
+    }
+
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.data.as_ptr(), self.len * self.layout.size()) }
+    }
+
+    pub fn get(&self, row: usize) -> Option<&[u8]> {
+        (row < self.len).then(|| unsafe { std::slice::from_raw_parts(self.item_ptr(row), self.layout.size()) })
+    }
+
+    pub fn get_mut(&mut self, row: usize) -> Option<&mut [u8]> {
+        (row < self.len).then(|| unsafe { std::slice::from_raw_parts_mut(self.item_ptr(row), self.layout.size()) })
+    }
+
+    fn item_ptr(&self, row: usize) -> *mut u8 {
+        unsafe { self.data.as_ptr().add(row * self.layout.size()) }
+    }
+
+    fn grow(&mut self, capacity: usize) {
+        if capacity <= self.capacity || self.layout.size() == 0 {
+            self.capacity = self.capacity.max(capacity);
+            return;
+        }
+
+        let new_layout = Layout::from_size_align(capacity * self.layout.size(), self.layout.align()).unwrap();
+
+        let data = match self.capacity {
+            0 => unsafe { std::alloc::alloc(new_layout) },
+            _ => unsafe { std::alloc::realloc(self.data.as_ptr(), Layout::from_size_align_unchecked(self.capacity * self.layout.size(), self.layout.align()), new_layout.size()) },
+        };
+
+        self.data = NonNull::new(data).unwrap_or_else(|| std::alloc::handle_alloc_error(new_layout));
+        self.capacity = capacity;
+    }
+
+    /// Значение перемещается в колонку, у исходного значения drop не вызывается
+    fn push(&mut self, mut dynamic_component: DynamicComponent) {
+        if self.len == self.capacity {
+            self.grow((self.capacity * 2).max(1));
+        }
+
+        unsafe { std::ptr::copy_nonoverlapping(dynamic_component.data.as_ptr(), self.item_ptr(self.len), self.layout.size()) };
+        dynamic_component.drop = None;
+
+        self.len += 1;
+    }
+
+    /// На место строки переносится последнее значение колонки
+    fn swap_remove(&mut self, row: usize, component_id: ComponentId) -> DynamicComponent {
+        assert!(row < self.len, "row out of bounds");
+
+        let dynamic_component = DynamicComponent::alloc(component_id, self.layout, self.drop);
+
+        unsafe {
+            std::ptr::copy_nonoverlapping(self.item_ptr(row), dynamic_component.data.as_ptr(), self.layout.size());
+            std::ptr::copy(self.item_ptr(self.len - 1), self.item_ptr(row), self.layout.size());
+        }
+
+        self.len -= 1;
+
+        dynamic_component
+    }
+}
+
+impl Drop for DynamicComponentsColumn {
+    fn drop(&mut self) {
+        if let Some(drop) = self.drop {
+            (0..self.len).for_each(|row| unsafe { drop(self.item_ptr(row)) });
+        }
+
+        if self.capacity != 0 && self.layout.size() != 0 {
+            unsafe { std::alloc::dealloc(self.data.as_ptr(), Layout::from_size_align_unchecked(self.capacity * self.layout.size(), self.layout.align())) };
+        }
+    }
+}
+
+/// Колонка чанка для динамического компонента, аналог ComponentsArray
+#[derive(Debug)]
+pub struct DynamicComponentsArray {
+    pub (crate) component_id: ComponentId,
+    pub (crate) components_collection: Arc<RwLock<DynamicComponentsColumn>>,
+    pub (crate) change_tick: Arc<AtomicU32>,
+    pub (crate) components_ticks: Arc<RwLock<Vec<ComponentTicks>>>,
+}
+
+impl DynamicComponentsArray {
+    pub (crate) fn new(component_id: ComponentId, descriptor: &DynamicComponentDescriptor, capacity: usize) -> Self {
+        Self {
+            component_id,
+            components_collection: Arc::new(RwLock::new(DynamicComponentsColumn::new(descriptor.layout(), descriptor.drop, capacity))),
+            change_tick: Default::default(),
+            components_ticks: Arc::new(RwLock::new(Vec::with_capacity(capacity))),
+        }
+    }
+}
+
+impl IComponentsArray for DynamicComponentsArray {
+    fn set_component(&mut self, component: Box<dyn Any + Sync + Send>, component_ticks: ComponentTicks) {
+        let component = unsafe { *component.downcast_unchecked::<DynamicComponent>() };
+
+        write_exclusive(&mut self.components_collection, |components| components.push(component));
+        write_exclusive(&mut self.components_ticks, |components_ticks| components_ticks.push(component_ticks));
+    }
+
+    fn remove_component(&mut self, position: usize) -> (Box<dyn Any + Sync + Send>, ComponentTicks) {
+        let component_id = self.component_id;
+
+        (
+            Box::new(write_exclusive(&mut self.components_collection, |components| components.swap_remove(position, component_id))),
+            write_exclusive(&mut self.components_ticks, |components_ticks| components_ticks.swap_remove(position))
+        )
+    }
+
+    fn get_array(&self) -> Arc<dyn Any + Sync + Send> {
+        self.components_collection.clone()
+    }
+
+    fn get_components_ticks(&self) -> Arc<RwLock<Vec<ComponentTicks>>> {
+        self.components_ticks.clone()
+    }
+
+    fn get_change_tick(&self) -> Arc<AtomicU32> {
+        self.change_tick.clone()
+    }
+
+    fn change_tick(&self) -> u32 {
+        self.change_tick.load(Ordering::Acquire)
+    }
+
+    fn check_change_ticks(&mut self, change_tick: u32) {
+        check_column_ticks(&self.components_ticks, &self.change_tick, change_tick);
+    }
+
+    fn is_shared(&self) -> bool {
+        Arc::strong_count(&self.components_collection) > 1 || Arc::strong_count(&self.components_ticks) > 1 || Arc::strong_count(&self.change_tick) > 1
+    }
+
+    /// Значения передаются копиями байтов (Vec<u8>)
+    fn for_each_component(&self, f: &mut dyn FnMut(&dyn Any)) -> Result<(), TryLockError> {
+        let components = self.components_collection.try_read()?;
+        (0..components.len()).for_each(|row| f(&components.get(row).unwrap().to_vec()));
+        Ok(())
+    }
+}
+
+/// Идентификатор упакованного компонента: у динамического компонента он хранится в значении
+pub (crate) fn boxed_component_id(component: &(dyn Any + Send + Sync)) -> ComponentId {
+    match component.downcast_ref::<DynamicComponent>() {
+        Some(dynamic_component) => dynamic_component.component_id,
+        None => component.type_id().into(),
+    }
+}
@@ -1,2 +1,6 @@
 pub mod component_info;
-pub mod boxed_component;
\ No newline at end of file
+pub mod boxed_component;
+pub mod storage_type;
+pub mod sparse_set;
+pub mod component_builder;
+pub mod dynamic_component;
\ No newline at end of file
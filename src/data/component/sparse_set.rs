@@ -0,0 +1,134 @@
+use std::{any::Any, fmt::Debug, sync::Arc};
+
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError};
+use vec_map::VecMap;
+
+use crate::{types::EntityId, data::{archetype::write_exclusive, component_access::ComponentMut}};
+
+pub (crate) trait IComponentSparseSet where Self: Sync + Send + Debug {
+    fn insert_component(&mut self, entity_id: EntityId, component: Box<dyn Any + Sync + Send>);
+    fn remove_component(&mut self, entity_id: EntityId) -> Option<Box<dyn Any + Sync + Send>>;
+    fn contains(&self, entity_id: EntityId) -> bool;
+    // позиция значения сущности в плотном массиве
+    fn position(&self, entity_id: EntityId) -> Option<usize>;
+    fn len(&self) -> usize;
+    // плотный массив значений, для доступа систем
+    fn get_array(&self) -> Arc<dyn Any + Sync + Send>;
+    // значения удерживает ChunkDataAccessor, вставка и удаление их не затрагивают
+    fn is_shared(&self) -> bool;
+    // обход значений в порядке плотного массива, для снимка. Множество, захваченное системой на запись, не читается
+    fn for_each_component(&self, f: &mut dyn FnMut(EntityId, &dyn Any)) -> Result<(), TryLockError>;
+}
+
+/// Компоненты, хранимые вне архетипов. Ключ - номер слота сущности, поколение проверяет EcsDataManager
+#[derive(Debug)]
+pub struct ComponentSparseSet<TComponent: Debug + Sync + Send + 'static> {
+    // номер слота сущности -> позиция в плотных массивах
+    sparse: VecMap<usize>,
+    entity_ids: Vec<EntityId>,
+    // значения выдаются системам так же, как колонки чанков
+    components: Arc<RwLock<Vec<TComponent>>>,
+}
+
+impl<TComponent: Debug + Sync + Send + 'static> ComponentSparseSet<TComponent> {
+    pub (crate) fn new() -> Self {
+        Self {
+            sparse: Default::default(),
+            entity_ids: Default::default(),
+            components: Default::default(),
+        }
+    }
+
+    /// Возвращает предыдущее значение, если компонент у сущности уже был
+    pub (crate) fn insert(&mut self, entity_id: EntityId, component: TComponent) -> Option<TComponent> {
+        if let Some(position) = self.sparse.get(*entity_id).copied() {
+            return Some(write_exclusive(&mut self.components, |components| std::mem::replace(&mut components[position], component)));
+        }
+
+        self.sparse.insert(*entity_id, self.entity_ids.len());
+        self.entity_ids.push(entity_id);
+        write_exclusive(&mut self.components, |components| components.push(component));
+
+        None
+    }
+
+    /// Значение захватывается на чтение без ожидания, Err - значения захвачены системой на запись
+    pub (crate) fn get(&self, entity_id: EntityId) -> Option<Result<RwLockReadGuard<'_, TComponent>, TryLockError>> {
+        let position = *self.sparse.get(*entity_id)?;
+
+        Some(self.components.try_read().map(|components| RwLockReadGuard::map(components, |components| &components[position])))
+    }
+
+    pub (crate) fn get_mut(&mut self, entity_id: EntityId) -> Option<Result<ComponentMut<'_, TComponent>, TryLockError>> {
+        let position = *self.sparse.get(*entity_id)?;
+
+        // значения не удерживает ни один ChunkDataAccessor - блокировка не нужна
+        if Arc::get_mut(&mut self.components).is_some() {
+            return Some(Ok(ComponentMut::Borrowed(&mut Arc::get_mut(&mut self.components).unwrap().get_mut()[position])));
+        }
+
+        Some(self.components.try_write().map(|components| ComponentMut::Locked(RwLockWriteGuard::map(components, |components| &mut components[position]))))
+    }
+
+    pub (crate) fn remove(&mut self, entity_id: EntityId) -> Option<TComponent> {
+        let position = self.sparse.remove(*entity_id)?;
+
+        self.entity_ids.swap_remove(position);
+        let component = write_exclusive(&mut self.components, |components| components.swap_remove(position));
+
+        // на освободившееся место переставлен последний элемент
+        if let Some(moved_entity_id) = self.entity_ids.get(position) {
+            self.sparse.insert(**moved_entity_id, position);
+        }
+
+        Some(component)
+    }
+}
+
+impl<TComponent: Debug + Sync + Send + 'static> IComponentSparseSet for ComponentSparseSet<TComponent> {
+    fn insert_component(&mut self, entity_id: EntityId, component: Box<dyn Any + Sync + Send>) {
+        let component = unsafe { *component.downcast_unchecked::<TComponent>() };
+        self.insert(entity_id, component);
+    }
+
+    fn remove_component(&mut self, entity_id: EntityId) -> Option<Box<dyn Any + Sync + Send>> {
+        self.remove(entity_id).map(|component| Box::new(component) as Box<dyn Any + Sync + Send>)
+    }
+
+    fn contains(&self, entity_id: EntityId) -> bool {
+        self.sparse.contains_key(*entity_id)
+    }
+
+    fn position(&self, entity_id: EntityId) -> Option<usize> {
+        self.sparse.get(*entity_id).copied()
+    }
+
+    fn len(&self) -> usize {
+        self.entity_ids.len()
+    }
+
+    fn get_array(&self) -> Arc<dyn Any + Sync + Send> {
+        self.components.clone()
+    }
+
+    fn is_shared(&self) -> bool {
+        Arc::strong_count(&self.components) > 1
+    }
+
+    fn for_each_component(&self, f: &mut dyn FnMut(EntityId, &dyn Any)) -> Result<(), TryLockError> {
+        self.entity_ids.iter().zip(self.components.try_read()?.iter()).for_each(|(entity_id, component)| f(*entity_id, component));
+        Ok(())
+    }
+}
+
+impl dyn IComponentSparseSet {
+    /// Типизированный доступ, тип проверяется по ComponentId на стороне вызывающего
+    pub (crate) unsafe fn as_typed<TComponent: Debug + Sync + Send + 'static>(&self) -> &ComponentSparseSet<TComponent> {
+        &*(self as *const dyn IComponentSparseSet as *const ComponentSparseSet<TComponent>)
+    }
+
+    /// Типизированный доступ, тип проверяется по ComponentId на стороне вызывающего
+    pub (crate) unsafe fn as_typed_mut<TComponent: Debug + Sync + Send + 'static>(&mut self) -> &mut ComponentSparseSet<TComponent> {
+        &mut *(self as *mut dyn IComponentSparseSet as *mut ComponentSparseSet<TComponent>)
+    }
+}
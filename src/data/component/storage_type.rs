@@ -0,0 +1,12 @@
+/// Способ хранения компонента
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StorageType {
+    /// Колонки чанков архетипа, компонент входит в сигнатуру архетипа
+    #[default]
+    Table,
+    /// Разреженное множество вне архетипа, добавление и удаление компонента не перемещает сущность между архетипами
+    SparseSet,
+    /// Одно значение на чанк архетипа, чанки архетипа группируются по значениям общих компонентов.
+    /// Задается через `ComponentBuilder::shared`, т.к. требует сравнения и клонирования значений
+    Shared,
+}
\ No newline at end of file
@@ -0,0 +1,321 @@
+use std::{fmt::Debug, ops::{Deref, DerefMut}, collections::HashMap, sync::atomic::AtomicU32};
+
+use tokio::sync::{RwLockReadGuard, RwLockWriteGuard, RwLockMappedWriteGuard, TryLockError};
+
+use crate::types::{EntityId, ComponentId, EntityResult, EntityError};
+
+use super::{EcsDataManager, archetype::{Archetype, ArchetypeChunk, ComponentTicks, update_change_tick}, hierarchy::is_hierarchy_component, component::{component_info::ComponentInfo, sparse_set::IComponentSparseSet, storage_type::StorageType}};
+
+/// Компонент сущности, доступный на чтение. Колонка чанка остается захваченной на чтение, пока жива ссылка
+pub enum ComponentRef<'a, TComponent: ?Sized> {
+    Locked(RwLockReadGuard<'a, TComponent>),
+    Borrowed(&'a TComponent),
+}
+
+impl<'a, TComponent: ?Sized> Deref for ComponentRef<'a, TComponent> {
+    type Target = TComponent;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            ComponentRef::Locked(component) => component,
+            ComponentRef::Borrowed(component) => component,
+        }
+    }
+}
+
+/// Компонент сущности, доступный на запись. Колонка чанка остается захваченной на запись, пока жива ссылка
+pub enum ComponentMut<'a, TComponent: ?Sized> {
+    Locked(RwLockMappedWriteGuard<'a, TComponent>),
+    Borrowed(&'a mut TComponent),
+}
+
+impl<'a, TComponent: ?Sized> Deref for ComponentMut<'a, TComponent> {
+    type Target = TComponent;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            ComponentMut::Locked(component) => component,
+            ComponentMut::Borrowed(component) => component,
+        }
+    }
+}
+
+impl<'a, TComponent: ?Sized> DerefMut for ComponentMut<'a, TComponent> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match self {
+            ComponentMut::Locked(component) => component,
+            ComponentMut::Borrowed(component) => component,
+        }
+    }
+}
+
+/// Несколько компонентов одной сущности на чтение: `get_many::<(Position, Velocity)>(entity_id)`
+pub trait IEntityComponentsRef<'a> {
+    type Item;
+
+    fn fetch_ref(ecs_data_manager: &'a EcsDataManager, entity_id: EntityId) -> EntityResult<Self::Item>;
+}
+
+/// Несколько компонентов одной сущности на запись: `get_many_mut::<(Position, Velocity)>(entity_id)`
+pub trait IEntityComponentsMut<'a> {
+    type Item;
+
+    fn fetch_mut(components_fetcher: &mut EntityComponentsFetcher<'a>) -> EntityResult<Self::Item>;
+}
+
+/// Выдает компоненты одной сущности на запись, каждый компонент не более одного раза
+pub struct EntityComponentsFetcher<'a> {
+    entity_id: EntityId,
+    archetype: &'a Archetype,
+    archetype_chunk: &'a ArchetypeChunk,
+    row: usize,
+    components_info: &'a HashMap<ComponentId, ComponentInfo>,
+    // изменяемые ссылки на разные множества не пересекаются, выданное множество удаляется из коллекции
+    sparse_sets: HashMap<ComponentId, &'a mut Box<dyn IComponentSparseSet>>,
+    change_tick: u32,
+    fetched_component_ids: Vec<ComponentId>,
+    // такты захваченных колонок, строка помечается измененной только после выдачи всех компонентов набора
+    pending_ticks: Vec<(RwLockWriteGuard<'a, Vec<ComponentTicks>>, &'a AtomicU32)>,
+}
+
+impl<'a> EntityComponentsFetcher<'a> {
+    pub fn fetch_mut<TComponent: Debug + Sync + Send + 'static>(&mut self) -> EntityResult<ComponentMut<'a, TComponent>> {
+        let entity_id = self.entity_id;
+        let component_id = ComponentId::from_type::<TComponent>();
+
+        let component_info = self.components_info.get(&component_id).ok_or(EntityError::ComponentNotRegistered { component_id })?;
+
+        if self.fetched_component_ids.contains(&component_id) {
+            return Err(EntityError::ComponentDuplicated { component_id });
+        }
+
+        // изменение Parent/Children на месте нарушило бы связь с другой стороны
+        if is_hierarchy_component(&component_id) {
+            return Err(EntityError::HierarchyComponent { component_id });
+        }
+
+        self.fetched_component_ids.push(component_id);
+
+        match component_info.storage_type {
+            StorageType::SparseSet => {
+                let sparse_set = self.sparse_sets.remove(&component_id).unwrap();
+
+                unsafe { sparse_set.as_typed_mut::<TComponent>() }.get_mut(entity_id)
+                    .ok_or(EntityError::MissingComponent { entity_id, component_id })?
+                    .map_err(|_| EntityError::ComponentLocked { entity_id, component_id })
+            },
+            _ if !self.archetype.archetype_type().contains(&component_id) => Err(EntityError::MissingComponent { entity_id, component_id }),
+            StorageType::Shared => Err(EntityError::SharedComponentReadOnly { component_id }),
+            // значение метки не хранится
+            StorageType::Table if component_info.is_tag => Err(EntityError::TagComponent { component_id }),
+            StorageType::Table => {
+                let components_array = self.archetype_chunk.get_typed_components_array::<TComponent>().unwrap();
+
+                let components_write_lock = components_array.components_collection.try_write().map_err(|_| EntityError::ComponentLocked { entity_id, component_id })?;
+                let components_ticks_write_lock = components_array.components_ticks.try_write().map_err(|_| EntityError::ComponentLocked { entity_id, component_id })?;
+
+                self.pending_ticks.push((components_ticks_write_lock, &components_array.change_tick));
+
+                let row = self.row;
+
+                Ok(ComponentMut::Locked(RwLockWriteGuard::map(components_write_lock, |components| &mut components[row])))
+            },
+        }
+    }
+}
+
+impl<'a> EntityComponentsFetcher<'a> {
+    /// Все компоненты набора выданы, строки захваченных колонок помечаются измененными
+    fn mark_changed(self) {
+        self.pending_ticks.into_iter().for_each(|(mut components_ticks, column_change_tick)| {
+            components_ticks[self.row].changed = self.change_tick;
+            update_change_tick(column_change_tick, self.change_tick);
+        });
+    }
+}
+
+impl EcsDataManager {
+    /// Компонент сущности на чтение. Колонка захватывается без ожидания, занятая колонка - ошибка ComponentLocked
+    pub fn get<TComponent: Debug + Sync + Send + 'static>(&self, entity_id: EntityId) -> EntityResult<ComponentRef<'_, TComponent>> {
+        let component_id = ComponentId::from_type::<TComponent>();
+
+        let component_info = self.components_info.get(&component_id).ok_or(EntityError::ComponentNotRegistered { component_id })?;
+        let entity_location = self.entity_location(entity_id).ok_or(EntityError::NoSuchEntity { entity_id })?;
+
+        let archetype = &self.archetypes[*entity_location.archetype_id];
+        let archetype_chunk = &archetype.chunks[entity_location.chunk_index];
+
+        match component_info.storage_type {
+            StorageType::SparseSet => self.sparse_component::<TComponent>(entity_id)
+                .ok_or(EntityError::MissingComponent { entity_id, component_id })?
+                .map(ComponentRef::Locked)
+                .map_err(|_| EntityError::ComponentLocked { entity_id, component_id }),
+            _ if !archetype.archetype_type().contains(&component_id) => Err(EntityError::MissingComponent { entity_id, component_id }),
+            StorageType::Shared => Ok(ComponentRef::Borrowed(self.shared_component::<TComponent>(archetype_chunk).unwrap())),
+            StorageType::Table if component_info.is_tag => Err(EntityError::TagComponent { component_id }),
+            StorageType::Table => {
+                let components_array = archetype_chunk.get_typed_components_array::<TComponent>().unwrap();
+                let components_read_lock = components_array.components_collection.try_read().map_err(|_| EntityError::ComponentLocked { entity_id, component_id })?;
+
+                Ok(ComponentRef::Locked(RwLockReadGuard::map(components_read_lock, |components| &components[entity_location.row])))
+            },
+        }
+    }
+
+    /// Наличие компонента у сущности, в том числе метки
+    pub fn has_component<TComponent: 'static>(&self, entity_id: EntityId) -> bool {
+        let component_id = ComponentId::from_type::<TComponent>();
+
+        let Some(entity_location) = self.entity_location(entity_id) else {
+            return false;
+        };
+
+        match self.sparse_sets.get(&component_id) {
+            Some(sparse_set) => sparse_set.contains(entity_id),
+            None => self.archetypes[*entity_location.archetype_id].archetype_type().contains(&component_id),
+        }
+    }
+
+    /// Компонент сущности на запись, строка помечается измененной в текущем такте
+    pub fn get_mut<TComponent: Debug + Sync + Send + 'static>(&mut self, entity_id: EntityId) -> EntityResult<ComponentMut<'_, TComponent>> {
+        self.get_many_mut::<(TComponent,)>(entity_id).map(|(component,)| component)
+    }
+
+    pub fn get_many<'a, TComponents: IEntityComponentsRef<'a>>(&'a self, entity_id: EntityId) -> EntityResult<TComponents::Item> {
+        TComponents::fetch_ref(self, entity_id)
+    }
+
+    /// Один и тот же компонент в наборе - ошибка ComponentDuplicated. Строки помечаются измененными, только если выданы все компоненты набора
+    pub fn get_many_mut<'a, TComponents: IEntityComponentsMut<'a>>(&'a mut self, entity_id: EntityId) -> EntityResult<TComponents::Item> {
+        let entity_location = self.entity_location(entity_id).ok_or(EntityError::NoSuchEntity { entity_id })?;
+
+        let archetype = &self.archetypes[*entity_location.archetype_id];
+
+        let mut components_fetcher = EntityComponentsFetcher {
+            entity_id,
+            archetype,
+            archetype_chunk: &archetype.chunks[entity_location.chunk_index],
+            row: entity_location.row,
+            components_info: &self.components_info,
+            sparse_sets: self.sparse_sets.iter_mut().map(|(component_id, sparse_set)| (*component_id, sparse_set)).collect(),
+            change_tick: self.change_tick,
+            fetched_component_ids: Vec::new(),
+            pending_ticks: Vec::new(),
+        };
+
+        // при ошибке захваченные колонки освобождаются без пометки изменения
+        let components = TComponents::fetch_mut(&mut components_fetcher)?;
+        components_fetcher.mark_changed();
+
+        Ok(components)
+    }
+
+    /// Значение из разреженного множества, Err - значения захвачены системой на запись
+    pub (crate) fn sparse_component<TComponent: Debug + Sync + Send + 'static>(&self, entity_id: EntityId) -> Option<Result<RwLockReadGuard<'_, TComponent>, TryLockError>> {
+        self.sparse_sets.get(&ComponentId::from_type::<TComponent>()).and_then(|sparse_set| unsafe { sparse_set.as_typed::<TComponent>() }.get(entity_id))
+    }
+
+    pub (crate) fn sparse_component_mut<TComponent: Debug + Sync + Send + 'static>(&mut self, entity_id: EntityId) -> Option<Result<ComponentMut<'_, TComponent>, TryLockError>> {
+        self.sparse_sets.get_mut(&ComponentId::from_type::<TComponent>()).and_then(|sparse_set| unsafe { sparse_set.as_typed_mut::<TComponent>() }.get_mut(entity_id))
+    }
+}
+
+macro_rules! component_tuple_into_entity_components_access {
+    ( $( $name:ident ),+ ) => {
+        impl<'a, $($name: Debug + Sync + Send + 'static),+> IEntityComponentsRef<'a> for ($($name,)+) {
+            type Item = ($(ComponentRef<'a, $name>,)+);
+
+            fn fetch_ref(ecs_data_manager: &'a EcsDataManager, entity_id: EntityId) -> EntityResult<Self::Item> {
+                Ok(($(ecs_data_manager.get::<$name>(entity_id)?,)+))
+            }
+        }
+
+        impl<'a, $($name: Debug + Sync + Send + 'static),+> IEntityComponentsMut<'a> for ($($name,)+) {
+            type Item = ($(ComponentMut<'a, $name>,)+);
+
+            fn fetch_mut(components_fetcher: &mut EntityComponentsFetcher<'a>) -> EntityResult<Self::Item> {
+                Ok(($(components_fetcher.fetch_mut::<$name>()?,)+))
+            }
+        }
+    };
+}
+
+component_tuple_into_entity_components_access!(T0);
+component_tuple_into_entity_components_access!(T0, T1);
+component_tuple_into_entity_components_access!(T0, T1, T2);
+component_tuple_into_entity_components_access!(T0, T1, T2, T3);
+component_tuple_into_entity_components_access!(T0, T1, T2, T3, T4);
+component_tuple_into_entity_components_access!(T0, T1, T2, T3, T4, T5);
+component_tuple_into_entity_components_access!(T0, T1, T2, T3, T4, T5, T6);
+component_tuple_into_entity_components_access!(T0, T1, T2, T3, T4, T5, T6, T7);
+component_tuple_into_entity_components_access!(T0, T1, T2, T3, T4, T5, T6, T7, T8);
+component_tuple_into_entity_components_access!(T0, T1, T2, T3, T4, T5, T6, T7, T8, T9);
+component_tuple_into_entity_components_access!(T0, T1, T2, T3, T4, T5, T6, T7, T8, T9, T10);
+component_tuple_into_entity_components_access!(T0, T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11);
+component_tuple_into_entity_components_access!(T0, T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12);
+component_tuple_into_entity_components_access!(T0, T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13);
+component_tuple_into_entity_components_access!(T0, T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14);
+component_tuple_into_entity_components_access!(T0, T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14, T15);
+
+#[cfg(test)]
+mod test {
+    use crate::{behavior::query::ArchetypeQuery, types::EntityError};
+
+    use super::super::{EcsDataManager, test_fixtures::{TestComponentA, TestComponentC, TestTickComponent, TestSharedComponent, TestNameComponent, register_sparse, register_shared, register_tag}};
+
+    #[test]
+    fn test_get_many_mut_conflicts() {
+        let mut ecs_data_manager = EcsDataManager::new();
+        ecs_data_manager.register_component::<TestTickComponent>();
+        ecs_data_manager.register_component::<TestComponentA>();
+        register_sparse::<TestNameComponent>(&mut ecs_data_manager);
+        register_shared::<TestSharedComponent>(&mut ecs_data_manager);
+        register_tag::<TestComponentC>(&mut ecs_data_manager);
+
+        let entity_id = ecs_data_manager.spawn((TestTickComponent(1), TestNameComponent("name".to_string()), TestSharedComponent(2), TestComponentC {})).unwrap();
+
+        // каждый компонент выдается на запись не более одного раза
+        assert!(matches!(ecs_data_manager.get_many_mut::<(TestTickComponent, TestTickComponent)>(entity_id), Err(EntityError::ComponentDuplicated { .. })));
+        assert!(matches!(ecs_data_manager.get_many_mut::<(TestNameComponent, TestTickComponent, TestNameComponent)>(entity_id), Err(EntityError::ComponentDuplicated { .. })));
+
+        assert!(matches!(ecs_data_manager.get_many_mut::<(TestTickComponent, TestComponentA)>(entity_id), Err(EntityError::MissingComponent { .. })));
+        assert!(matches!(ecs_data_manager.get_many_mut::<(TestTickComponent, TestSharedComponent)>(entity_id), Err(EntityError::SharedComponentReadOnly { .. })));
+        assert!(matches!(ecs_data_manager.get_many_mut::<(TestComponentC, TestTickComponent)>(entity_id), Err(EntityError::TagComponent { .. })));
+
+        // разные колонки и разреженные множества выдаются вместе
+        {
+            let (mut tick_component, mut name_component) = ecs_data_manager.get_many_mut::<(TestTickComponent, TestNameComponent)>(entity_id).unwrap();
+            tick_component.0 = 10;
+            name_component.0.push('!');
+        }
+
+        assert_eq!(*ecs_data_manager.get::<TestTickComponent>(entity_id).unwrap(), TestTickComponent(10));
+        assert_eq!(*ecs_data_manager.get::<TestNameComponent>(entity_id).unwrap(), TestNameComponent("name!".to_string()));
+
+        // неудачный запрос не оставляет захваченных колонок
+        assert!(ecs_data_manager.get_mut::<TestTickComponent>(entity_id).is_ok());
+    }
+
+    #[test]
+    fn test_failed_get_many_mut_keeps_ticks() {
+        let mut ecs_data_manager = EcsDataManager::new();
+        ecs_data_manager.register_component::<TestTickComponent>();
+        ecs_data_manager.register_component::<TestComponentA>();
+        register_tag::<TestComponentC>(&mut ecs_data_manager);
+
+        let entity_id = ecs_data_manager.spawn((TestTickComponent(1), TestComponentC {})).unwrap();
+
+        let since_tick = ecs_data_manager.increment_change_tick();
+        ecs_data_manager.increment_change_tick();
+
+        let changed_query = ArchetypeQuery::new(None, None, None, None).with_changed::<TestTickComponent>(since_tick);
+
+        // такты первых компонентов не помечаются, если следующий компонент не выдан
+        assert!(ecs_data_manager.get_many_mut::<(TestTickComponent, TestComponentA)>(entity_id).is_err());
+        assert!(ecs_data_manager.get_many_mut::<(TestTickComponent, TestComponentC)>(entity_id).is_err());
+        assert!(ecs_data_manager.query_entities(&changed_query).unwrap().is_empty());
+
+        ecs_data_manager.get_mut::<TestTickComponent>(entity_id).unwrap().0 = 2;
+        assert_eq!(ecs_data_manager.query_entities(&changed_query).unwrap(), vec![entity_id]);
+    }
+}
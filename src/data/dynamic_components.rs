@@ -0,0 +1,194 @@
+use tokio::sync::{RwLockReadGuard, RwLockWriteGuard};
+
+use crate::types::{ComponentId, EntityId, EntityResult, EntityError, StableComponentId, RegisterComponentResult, RegisterComponentError};
+
+use super::{
+    EcsDataManager,
+    archetype::update_change_tick,
+    component::{component_info::ComponentInfo, dynamic_component::{DynamicComponentDescriptor, DynamicComponent}},
+    component_access::{ComponentRef, ComponentMut},
+};
+
+impl EcsDataManager {
+    /// Регистрация компонента, описанного во время выполнения. Имя компонента становится его стабильным идентификатором.
+    /// Выравнивание должно быть степенью двойки, размер - кратным выравниванию
+    pub fn register_dynamic_component(&mut self, dynamic_descriptor: DynamicComponentDescriptor) -> RegisterComponentResult<ComponentId> {
+        if !dynamic_descriptor.is_valid_layout() {
+            return Err(RegisterComponentError::InvalidDynamicLayout { name: dynamic_descriptor.name, size: dynamic_descriptor.size, align: dynamic_descriptor.align });
+        }
+
+        let stable_id = StableComponentId::Name(dynamic_descriptor.name.clone());
+
+        self.check_stable_id(&stable_id)?;
+
+        let component_id = ComponentId::new_dynamic(self.dynamic_components_count);
+        self.dynamic_components_count += 1;
+
+        let component_index = self.components_info.len();
+
+        self.insert_component_info(component_id, ComponentInfo::new_dynamic(component_id, component_index, dynamic_descriptor), Some(stable_id));
+
+        Ok(component_id)
+    }
+
+    pub fn dynamic_component_descriptor(&self, component_id: &ComponentId) -> Option<&DynamicComponentDescriptor> {
+        self.components_info.get(component_id).and_then(|component_info| component_info.dynamic_descriptor.as_ref())
+    }
+
+    /// Значение динамического компонента из байтов, значение переходит во владение ECS.
+    ///
+    /// # Safety
+    /// `bytes` - корректное значение компонента. Если у компонента есть `drop`, исходное значение больше не должно уничтожаться.
+    /// Значение должно допускать передачу в другой поток и одновременное чтение из нескольких потоков (как `Send + Sync`).
+    /// Значение компонента без `drop` должно оставаться корректным после побайтного копирования
+    pub unsafe fn new_dynamic_component(&self, component_id: ComponentId, bytes: &[u8]) -> EntityResult<DynamicComponent> {
+        let dynamic_descriptor = self.checked_dynamic_descriptor(component_id)?;
+
+        if bytes.len() != dynamic_descriptor.size {
+            return Err(EntityError::DynamicComponentSizeMismatch { component_id, expected: dynamic_descriptor.size, actual: bytes.len() });
+        }
+
+        Ok(DynamicComponent::from_bytes(component_id, dynamic_descriptor, bytes))
+    }
+
+    /// Добавление (или замена) динамического компонента, сущность переносится в архетип с компонентом
+    pub fn insert_dynamic_component(&mut self, entity_id: EntityId, dynamic_component: DynamicComponent) -> EntityResult<()> {
+        let component_id = dynamic_component.component_id();
+
+        self.checked_dynamic_descriptor(component_id)?;
+
+        let entity_location = self.entity_location(entity_id).ok_or(EntityError::NoSuchEntity { entity_id })?;
+
+        self.insert_boxed_component(entity_id, entity_location, component_id, Box::new(dynamic_component))
+    }
+
+    /// Ok(None), если компонента у сущности нет
+    pub fn remove_dynamic_component(&mut self, entity_id: EntityId, component_id: ComponentId) -> EntityResult<Option<DynamicComponent>> {
+        self.checked_dynamic_descriptor(component_id)?;
+
+        let entity_location = self.entity_location(entity_id).ok_or(EntityError::NoSuchEntity { entity_id })?;
+
+        Ok(self.remove_boxed_component(entity_id, entity_location, component_id)?.map(|component| unsafe { *component.downcast_unchecked::<DynamicComponent>() }))
+    }
+
+    /// Байты значения динамического компонента на чтение, колонка захватывается без ожидания
+    pub fn get_dynamic(&self, entity_id: EntityId, component_id: ComponentId) -> EntityResult<ComponentRef<'_, [u8]>> {
+        self.checked_dynamic_descriptor(component_id)?;
+
+        let entity_location = self.entity_location(entity_id).ok_or(EntityError::NoSuchEntity { entity_id })?;
+
+        let components_array = self.archetypes[*entity_location.archetype_id].chunks[entity_location.chunk_index]
+            .get_dynamic_components_array(&component_id)
+            .ok_or(EntityError::MissingComponent { entity_id, component_id })?;
+
+        let components_read_lock = components_array.components_collection.try_read().map_err(|_| EntityError::ComponentLocked { entity_id, component_id })?;
+
+        Ok(ComponentRef::Locked(RwLockReadGuard::map(components_read_lock, |components| components.get(entity_location.row).unwrap())))
+    }
+
+    /// Байты значения динамического компонента на запись, строка помечается измененной в текущем такте
+    pub fn get_dynamic_mut(&mut self, entity_id: EntityId, component_id: ComponentId) -> EntityResult<ComponentMut<'_, [u8]>> {
+        self.checked_dynamic_descriptor(component_id)?;
+
+        let entity_location = self.entity_location(entity_id).ok_or(EntityError::NoSuchEntity { entity_id })?;
+
+        let components_array = self.archetypes[*entity_location.archetype_id].chunks[entity_location.chunk_index]
+            .get_dynamic_components_array(&component_id)
+            .ok_or(EntityError::MissingComponent { entity_id, component_id })?;
+
+        let components_write_lock = components_array.components_collection.try_write().map_err(|_| EntityError::ComponentLocked { entity_id, component_id })?;
+        let mut components_ticks_write_lock = components_array.components_ticks.try_write().map_err(|_| EntityError::ComponentLocked { entity_id, component_id })?;
+
+        components_ticks_write_lock[entity_location.row].changed = self.change_tick;
+        update_change_tick(&components_array.change_tick, self.change_tick);
+
+        Ok(ComponentMut::Locked(RwLockWriteGuard::map(components_write_lock, |components| components.get_mut(entity_location.row).unwrap())))
+    }
+
+    fn checked_dynamic_descriptor(&self, component_id: ComponentId) -> EntityResult<&DynamicComponentDescriptor> {
+        let component_info = self.components_info.get(&component_id).ok_or(EntityError::ComponentNotRegistered { component_id })?;
+
+        component_info.dynamic_descriptor.as_ref().ok_or(EntityError::NotDynamicComponent { component_id })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::types::{EntityError, RegisterComponentError};
+
+    use super::super::{EcsDataManager, component::dynamic_component::DynamicComponentDescriptor, test_fixtures::TestTickComponent};
+
+    // значение с заполнением между полями, как у типов Rust
+    #[repr(C)]
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct TestPaddedValue {
+        flag: u8,
+        value: u32,
+    }
+
+    impl TestPaddedValue {
+        // байты заполнения инициализируются нулями
+        fn to_bytes(self) -> [u8; 8] {
+            let mut bytes = [0; 8];
+            bytes[0] = self.flag;
+            bytes[4..].copy_from_slice(&self.value.to_ne_bytes());
+            bytes
+        }
+
+        fn from_bytes(bytes: &[u8]) -> Self {
+            unsafe { std::ptr::read_unaligned(bytes.as_ptr() as *const Self) }
+        }
+    }
+
+    static DROPPED_DYNAMIC_VALUES: AtomicUsize = AtomicUsize::new(0);
+
+    unsafe fn test_dynamic_drop(_: *mut u8) {
+        DROPPED_DYNAMIC_VALUES.fetch_add(1, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn test_dynamic_padded_columns() {
+        let mut ecs_data_manager = EcsDataManager::new();
+        ecs_data_manager.register_component::<TestTickComponent>();
+
+        for (size, align) in [(8, 3), (8, 0), (6, 4), (4, 8)] {
+            let result = ecs_data_manager.register_dynamic_component(DynamicComponentDescriptor::new(format!("invalid_{size}_{align}"), size, align));
+            assert!(matches!(result, Err(RegisterComponentError::InvalidDynamicLayout { .. })));
+        }
+
+        let descriptor = DynamicComponentDescriptor::new("padded", std::mem::size_of::<TestPaddedValue>(), std::mem::align_of::<TestPaddedValue>()).with_drop(test_dynamic_drop);
+        let component_id = ecs_data_manager.register_dynamic_component(descriptor).unwrap();
+
+        assert!(matches!(unsafe { ecs_data_manager.new_dynamic_component(component_id, &[0; 5]) }, Err(EntityError::DynamicComponentSizeMismatch { .. })));
+
+        let values = (0..5).map(|value| TestPaddedValue { flag: value as u8, value: value * 1000 }).collect::<Vec<_>>();
+
+        let entity_ids = values.iter().map(|value| {
+            let dynamic_component = unsafe { ecs_data_manager.new_dynamic_component(component_id, &value.to_bytes()) }.unwrap();
+            ecs_data_manager.add_entity(vec![Box::new(TestTickComponent(value.value)), Box::new(dynamic_component)]).unwrap()
+        }).collect::<Vec<_>>();
+
+        // на место удаленной строки переносится последнее значение колонки
+        ecs_data_manager.remove_entity(entity_ids[1]).unwrap();
+        assert_eq!(DROPPED_DYNAMIC_VALUES.load(Ordering::SeqCst), 1);
+
+        let removed_value = ecs_data_manager.remove_dynamic_component(entity_ids[3], component_id).unwrap().unwrap();
+        assert_eq!(TestPaddedValue::from_bytes(removed_value.as_bytes()), values[3]);
+        drop(removed_value);
+        assert_eq!(DROPPED_DYNAMIC_VALUES.load(Ordering::SeqCst), 2);
+
+        for index in [0, 2, 4] {
+            let bytes = ecs_data_manager.get_dynamic(entity_ids[index], component_id).unwrap();
+            assert_eq!(TestPaddedValue::from_bytes(&bytes), values[index]);
+            assert_eq!(ecs_data_manager.get::<TestTickComponent>(entity_ids[index]).unwrap().0, values[index].value);
+        }
+
+        ecs_data_manager.get_dynamic_mut(entity_ids[4], component_id).unwrap().copy_from_slice(&values[1].to_bytes());
+        assert_eq!(TestPaddedValue::from_bytes(&ecs_data_manager.get_dynamic(entity_ids[4], component_id).unwrap()), values[1]);
+
+        drop(ecs_data_manager);
+        assert_eq!(DROPPED_DYNAMIC_VALUES.load(Ordering::SeqCst), 5);
+    }
+}
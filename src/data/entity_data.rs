@@ -1,12 +1,16 @@
 use std::{collections::HashMap, any::Any};
 
-use crate::types::{EntityId, ComponentId, ArchetypeType};
+use crate::types::{EntityId, ComponentId};
+
+use super::archetype::ComponentTicks;
 
 
 
 pub struct EntityData {
     pub (crate) entity_id: EntityId,
-    pub (crate) entity_components: HashMap<ComponentId, Box<dyn Any + Send + Sync>>
+    pub (crate) entity_components: HashMap<ComponentId, Box<dyn Any + Send + Sync>>,
+    // такты компонентов, извлеченных из колонок чанка. Сохраняются при переносе сущности между архетипами
+    pub (crate) components_ticks: HashMap<ComponentId, ComponentTicks>,
 }
 
 impl EntityData {
@@ -15,34 +19,20 @@ impl EntityData {
         Self {
             entity_id,
             entity_components,
+            components_ticks: Default::default(),
         }
     }
 
-    #[inline(always)]
-    pub (crate) fn add_component_with_type<TComponent: Sync + Send + 'static>(&mut self, component: TComponent) -> &mut Self {
-        self.add_component(ComponentId::from_type::<TComponent>(), Box::new(component))
-    }
-
     #[inline(always)]
     pub (crate) fn add_component(&mut self, component_id: ComponentId, component: Box<dyn Any + Send + Sync>) -> &mut Self {
-        self.entity_components.insert(component_id, Box::new(component));
-        self
-    }
-
-    #[inline(always)]
-    pub (crate) fn remove_component_with_type<TComponent: Sync + Send + 'static>(&mut self, component: TComponent) -> &mut Self {
-        self.remove_component(&ComponentId::from_type::<TComponent>())
-    }
-
-    #[inline(always)]
-    pub (crate) fn remove_component(&mut self, omponent_id: &ComponentId) -> &mut Self {
-        self.entity_components.remove(omponent_id);
+        self.entity_components.insert(component_id, component);
         self
     }
 
     #[inline(always)]
-    pub (crate) fn build_archetype_type(&self) -> ArchetypeType {
-        self.entity_components.keys().map(|component_id| *component_id).collect::<Vec<_>>().into()
+    pub (crate) fn remove_component(&mut self, component_id: &ComponentId) -> Option<Box<dyn Any + Send + Sync>> {
+        self.components_ticks.remove(component_id);
+        self.entity_components.remove(component_id)
     }
 }
 
@@ -1,51 +1,295 @@
-use std::{collections::{HashMap, HashSet}, any::{TypeId, Any}, sync::Arc};
+use std::{collections::HashMap, any::{TypeId, Any}, ops::Deref, sync::{Arc, atomic::AtomicU32}};
 
 use tokio::sync::{RwLockReadGuard, RwLockWriteGuard, RwLock};
-use vec_map::VecMap;
 
-use crate::types::ComponentId;
+use crate::types::{ComponentId, EntityId};
 
-use super::{EcsDataManager, archetype::ArchetypeChunk};
+use super::{EcsDataManager, archetype::{ArchetypeChunk, ComponentTicks, update_change_tick}, resources::ResourceLock, component::dynamic_component::DynamicComponentsColumn};
 
 pub struct RoComponentDataAccessor<TComponent>(Arc<RwLock<Vec<TComponent>>>);
 
 impl<TComponent> RoComponentDataAccessor<TComponent> {
-    async fn read(&self) -> RwLockReadGuard<Vec<TComponent>> {
+    pub async fn read(&self) -> RwLockReadGuard<'_, Vec<TComponent>> {
         self.0.read().await
     }
 }
 
-pub struct RwComponentDataAccessor<TComponent>(Arc<RwLock<Vec<TComponent>>>);
+/// Доступ на запись к колонке чанка: колонка, ее такт изменения, такты строк и текущий такт мира
+pub struct RwComponentDataAccessor<TComponent>(Arc<RwLock<Vec<TComponent>>>, Arc<AtomicU32>, Arc<RwLock<Vec<ComponentTicks>>>, u32);
 
 impl<TComponent> RwComponentDataAccessor<TComponent> {
-    async fn read(&self) -> RwLockReadGuard<Vec<TComponent>> {
+    pub async fn read(&self) -> RwLockReadGuard<'_, Vec<TComponent>> {
         self.0.read().await
     }
 
-    async fn write(&self) -> RwLockWriteGuard<Vec<TComponent>> {
+    /// Колонка помечается измененной в текущем такте, строки - при изменении через `get_mut`/`iter_mut`
+    pub async fn write(&self) -> ComponentsWriteGuard<'_, TComponent> {
+        let components_write_lock = self.0.write().await;
+        let components_ticks_write_lock = self.2.write().await;
+
+        update_change_tick(&self.1, self.3);
+
+        ComponentsWriteGuard { components: components_write_lock, components_ticks: components_ticks_write_lock, change_tick: self.3 }
+    }
+}
+
+/// Колонка, захваченная на запись. Чтение через Deref, изменение строки отмечает ее такт изменения
+pub struct ComponentsWriteGuard<'a, TComponent> {
+    components: RwLockWriteGuard<'a, Vec<TComponent>>,
+    components_ticks: RwLockWriteGuard<'a, Vec<ComponentTicks>>,
+    change_tick: u32,
+}
+
+impl<'a, TComponent> ComponentsWriteGuard<'a, TComponent> {
+    pub fn get_mut(&mut self, row: usize) -> Option<&mut TComponent> {
+        let component = self.components.get_mut(row)?;
+        self.components_ticks[row].changed = self.change_tick;
+
+        Some(component)
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut TComponent> {
+        let change_tick = self.change_tick;
+        self.components_ticks.iter_mut().for_each(|component_ticks| component_ticks.changed = change_tick);
+
+        self.components.iter_mut()
+    }
+}
+
+impl<'a, TComponent> Deref for ComponentsWriteGuard<'a, TComponent> {
+    type Target = [TComponent];
+
+    fn deref(&self) -> &Self::Target {
+        &self.components
+    }
+}
+
+
+pub struct RoDynamicComponentDataAccessor(Arc<RwLock<DynamicComponentsColumn>>);
+
+impl RoDynamicComponentDataAccessor {
+    pub async fn read(&self) -> RwLockReadGuard<'_, DynamicComponentsColumn> {
+        self.0.read().await
+    }
+}
+
+/// Доступ на запись к колонке динамического компонента, такты обновляются так же, как у RwComponentDataAccessor
+pub struct RwDynamicComponentDataAccessor(Arc<RwLock<DynamicComponentsColumn>>, Arc<AtomicU32>, Arc<RwLock<Vec<ComponentTicks>>>, u32);
+
+impl RwDynamicComponentDataAccessor {
+    pub async fn read(&self) -> RwLockReadGuard<'_, DynamicComponentsColumn> {
+        self.0.read().await
+    }
+
+    pub async fn write(&self) -> DynamicComponentsWriteGuard<'_> {
+        let components_write_lock = self.0.write().await;
+        let components_ticks_write_lock = self.2.write().await;
+
+        update_change_tick(&self.1, self.3);
+
+        DynamicComponentsWriteGuard { components: components_write_lock, components_ticks: components_ticks_write_lock, change_tick: self.3 }
+    }
+}
+
+/// Колонка динамического компонента, захваченная на запись
+pub struct DynamicComponentsWriteGuard<'a> {
+    components: RwLockWriteGuard<'a, DynamicComponentsColumn>,
+    components_ticks: RwLockWriteGuard<'a, Vec<ComponentTicks>>,
+    change_tick: u32,
+}
+
+impl<'a> DynamicComponentsWriteGuard<'a> {
+    pub fn get_mut(&mut self, row: usize) -> Option<&mut [u8]> {
+        let component = self.components.get_mut(row)?;
+        self.components_ticks[row].changed = self.change_tick;
+
+        Some(component)
+    }
+
+    /// Все строки колонки помечаются измененными
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        let change_tick = self.change_tick;
+        self.components_ticks.iter_mut().for_each(|component_ticks| component_ticks.changed = change_tick);
+
+        self.components.as_bytes_mut()
+    }
+}
+
+impl<'a> Deref for DynamicComponentsWriteGuard<'a> {
+    type Target = DynamicComponentsColumn;
+
+    fn deref(&self) -> &Self::Target {
+        &self.components
+    }
+}
+
+/// Доступ на чтение к компоненту из разреженного множества. Значения ищутся по строке чанка, у части строк компонента может не быть
+pub struct RoSparseComponentDataAccessor<TComponent>(Arc<RwLock<Vec<TComponent>>>, Vec<Option<usize>>);
+
+impl<TComponent> RoSparseComponentDataAccessor<TComponent> {
+    pub async fn read(&self) -> SparseComponentsReadGuard<'_, TComponent> {
+        SparseComponentsReadGuard { components: self.0.read().await, positions: &self.1 }
+    }
+}
+
+/// Доступ на запись к компоненту из разреженного множества. Тактов у таких компонентов нет
+pub struct RwSparseComponentDataAccessor<TComponent>(Arc<RwLock<Vec<TComponent>>>, Vec<Option<usize>>);
+
+impl<TComponent> RwSparseComponentDataAccessor<TComponent> {
+    pub async fn read(&self) -> SparseComponentsReadGuard<'_, TComponent> {
+        SparseComponentsReadGuard { components: self.0.read().await, positions: &self.1 }
+    }
+
+    pub async fn write(&self) -> SparseComponentsWriteGuard<'_, TComponent> {
+        SparseComponentsWriteGuard { components: self.0.write().await, positions: &self.1 }
+    }
+}
+
+pub struct SparseComponentsReadGuard<'a, TComponent> {
+    components: RwLockReadGuard<'a, Vec<TComponent>>,
+    // строка чанка -> позиция значения в множестве
+    positions: &'a [Option<usize>],
+}
+
+impl<'a, TComponent> SparseComponentsReadGuard<'a, TComponent> {
+    pub fn get(&self, row: usize) -> Option<&TComponent> {
+        self.components.get((*self.positions.get(row)?)?)
+    }
+}
+
+pub struct SparseComponentsWriteGuard<'a, TComponent> {
+    components: RwLockWriteGuard<'a, Vec<TComponent>>,
+    positions: &'a [Option<usize>],
+}
+
+impl<'a, TComponent> SparseComponentsWriteGuard<'a, TComponent> {
+    pub fn get(&self, row: usize) -> Option<&TComponent> {
+        self.components.get((*self.positions.get(row)?)?)
+    }
+
+    pub fn get_mut(&mut self, row: usize) -> Option<&mut TComponent> {
+        self.components.get_mut((*self.positions.get(row)?)?)
+    }
+}
+
+pub struct RoResourceDataAccessor<TResource>(Arc<RwLock<TResource>>);
+
+impl<TResource> RoResourceDataAccessor<TResource> {
+    pub async fn read(&self) -> RwLockReadGuard<'_, TResource> {
+        self.0.read().await
+    }
+}
+
+pub struct RwResourceDataAccessor<TResource>(Arc<RwLock<TResource>>);
+
+impl<TResource> RwResourceDataAccessor<TResource> {
+    pub async fn read(&self) -> RwLockReadGuard<'_, TResource> {
+        self.0.read().await
+    }
+
+    pub async fn write(&self) -> RwLockWriteGuard<'_, TResource> {
         self.0.write().await
     }
 }
 
+// колонка, такт изменения колонки и такты строк
+type RwComponentData = (Arc<dyn Any + Send + Sync>, Arc<AtomicU32>, Arc<RwLock<Vec<ComponentTicks>>>);
+
+// значения множества и позиции значений по строкам чанка
+type SparseComponentData = (Arc<dyn Any + Send + Sync>, Vec<Option<usize>>);
 
 #[derive(Debug, Default)]
 pub struct ChunkDataAccessor {
     ro_data: HashMap<ComponentId, Arc<dyn Any + Send + Sync>>,
-    rw_data: HashMap<ComponentId, Arc<dyn Any + Send + Sync>>,
+    rw_data: HashMap<ComponentId, RwComponentData>,
+    ro_sparse_data: HashMap<ComponentId, SparseComponentData>,
+    rw_sparse_data: HashMap<ComponentId, SparseComponentData>,
+    // сущности чанка по строкам
+    entity_ids: Vec<EntityId>,
+    // строки, прошедшие фильтры запроса по отдельным сущностям
+    rows: Vec<usize>,
+    // такт запуска системы, которым помечаются изменения колонок
+    change_tick: u32,
+    // такт предыдущего запуска системы, относительно него выполняются фильтры added/changed запроса
+    last_run_tick: u32,
+    // ресурсы, объявленные системой
+    ro_resources: HashMap<TypeId, ResourceLock>,
+    rw_resources: HashMap<TypeId, ResourceLock>,
 }
 
 impl ChunkDataAccessor {
-    pub (crate) fn fill_data_from_chunk(&mut self, select_components: Vec<(ComponentId, bool)>, chunk: &ArchetypeChunk) {
+    /// `rows` - строки чанка, прошедшие фильтры запроса
+    pub (crate) fn fill_data_from_chunk(&mut self, select_components: Vec<(ComponentId, bool)>, chunk: &ArchetypeChunk, rows: Vec<usize>, ecs_data_manager: &EcsDataManager, change_tick: u32, last_run_tick: u32) {
+        self.fill_ticks(change_tick, last_run_tick);
+        self.entity_ids = chunk.entity_ids.clone();
+        self.rows = rows;
+
         select_components.into_iter().for_each(|(component_id, readonly)| {
+            if let Some(sparse_set) = ecs_data_manager.sparse_sets.get(&component_id) {
+                let positions = self.entity_ids.iter().map(|entity_id| sparse_set.position(*entity_id)).collect();
+
+                if readonly {
+                    self.ro_sparse_data.insert(component_id, (sparse_set.get_array(), positions));
+                } else {
+                    self.rw_sparse_data.insert(component_id, (sparse_set.get_array(), positions));
+                }
+
+                return;
+            }
+
+            // у меток нет колонок, они участвуют только в выборе архетипа
+            let components_array = match chunk.get_components_array(&component_id) {
+                Some(components_array) => components_array,
+                None => return,
+            };
+
             if readonly {
-                self.ro_data.insert(component_id, chunk.get_components_array(&component_id).unwrap().get_array());
+                self.ro_data.insert(component_id, components_array.get_array());
             } else {
-                self.rw_data.insert(component_id, chunk.get_components_array(&component_id).unwrap().get_array());
+                self.rw_data.insert(component_id, (components_array.get_array(), components_array.get_change_tick(), components_array.get_components_ticks()));
             }
-            
         });
     }
 
+    pub (crate) fn fill_ticks(&mut self, change_tick: u32, last_run_tick: u32) {
+        self.change_tick = change_tick;
+        self.last_run_tick = last_run_tick;
+    }
+
+    /// Ресурсы, отсутствующие в менеджере, пропускаются
+    pub (crate) fn fill_resources(&mut self, select_resources: &[(TypeId, bool)], ecs_data_manager: &EcsDataManager) {
+        select_resources.iter().for_each(|(resource_type_id, readonly)| {
+            let Some(resource_lock) = ecs_data_manager.resource_lock(resource_type_id) else {
+                return;
+            };
+
+            if *readonly {
+                self.ro_resources.insert(*resource_type_id, resource_lock);
+            } else {
+                self.rw_resources.insert(*resource_type_id, resource_lock);
+            }
+        });
+    }
+
+    pub fn entity_ids(&self) -> &[EntityId] {
+        &self.entity_ids
+    }
+
+    pub fn change_tick(&self) -> u32 {
+        self.change_tick
+    }
+
+    /// Такт предыдущего запуска системы, относительно него выполнены фильтры `added`/`changed` запроса
+    pub fn last_run_tick(&self) -> u32 {
+        self.last_run_tick
+    }
+
+    /// Строки чанка, прошедшие фильтры запроса по отдельным сущностям: компоненты из разреженных множеств и такты.
+    /// Колонки содержат все строки чанка, системе следует обходить только эти
+    pub fn rows(&self) -> &[usize] {
+        &self.rows
+    }
+
     // pub (crate) fn add_data(&mut self, components_type: ComponentId, components: Box<dyn Any + Send + Sync>) {
     //     self.data.insert(components_type, components);
     // }
@@ -58,14 +302,63 @@ impl ChunkDataAccessor {
     }
 
     pub fn resolve_rw_components<TComponent: Sync + Send + 'static>(&mut self) -> Option<RwComponentDataAccessor<TComponent>> {
-        self.rw_data.remove(&TypeId::of::<TComponent>().into()).map(|components| {
+        self.rw_data.remove(&TypeId::of::<TComponent>().into()).map(|(components, change_tick, components_ticks)| {
             let components_array = unsafe { components.downcast_unchecked::<RwLock<Vec<TComponent>>>() };
-            RwComponentDataAccessor::<TComponent>(components_array)
+            RwComponentDataAccessor::<TComponent>(components_array, change_tick, components_ticks, self.change_tick)
+        })
+    }
+
+    /// Колонка динамического компонента, тип значений известен только вызывающему
+    pub fn resolve_ro_dynamic_components(&mut self, component_id: ComponentId) -> Option<RoDynamicComponentDataAccessor> {
+        if !component_id.is_dynamic() {
+            return None;
+        }
+
+        self.ro_data.remove(&component_id).map(|components| {
+            RoDynamicComponentDataAccessor(unsafe { components.downcast_unchecked::<RwLock<DynamicComponentsColumn>>() })
+        })
+    }
+
+    pub fn resolve_rw_dynamic_components(&mut self, component_id: ComponentId) -> Option<RwDynamicComponentDataAccessor> {
+        if !component_id.is_dynamic() {
+            return None;
+        }
+
+        self.rw_data.remove(&component_id).map(|(components, change_tick, components_ticks)| {
+            let components_array = unsafe { components.downcast_unchecked::<RwLock<DynamicComponentsColumn>>() };
+            RwDynamicComponentDataAccessor(components_array, change_tick, components_ticks, self.change_tick)
+        })
+    }
+
+    pub fn resolve_ro_sparse_components<TComponent: Sync + Send + 'static>(&mut self) -> Option<RoSparseComponentDataAccessor<TComponent>> {
+        self.ro_sparse_data.remove(&TypeId::of::<TComponent>().into()).map(|(components, positions)| {
+            RoSparseComponentDataAccessor::<TComponent>(unsafe { components.downcast_unchecked::<RwLock<Vec<TComponent>>>() }, positions)
+        })
+    }
+
+    pub fn resolve_rw_sparse_components<TComponent: Sync + Send + 'static>(&mut self) -> Option<RwSparseComponentDataAccessor<TComponent>> {
+        self.rw_sparse_data.remove(&TypeId::of::<TComponent>().into()).map(|(components, positions)| {
+            RwSparseComponentDataAccessor::<TComponent>(unsafe { components.downcast_unchecked::<RwLock<Vec<TComponent>>>() }, positions)
+        })
+    }
+
+    pub fn resolve_ro_resource<TResource: Sync + Send + 'static>(&mut self) -> Option<RoResourceDataAccessor<TResource>> {
+        self.ro_resources.remove(&TypeId::of::<TResource>()).map(|resource_lock| {
+            RoResourceDataAccessor::<TResource>(unsafe { resource_lock.downcast_unchecked::<RwLock<TResource>>() })
+        })
+    }
+
+    pub fn resolve_rw_resource<TResource: Sync + Send + 'static>(&mut self) -> Option<RwResourceDataAccessor<TResource>> {
+        self.rw_resources.remove(&TypeId::of::<TResource>()).map(|resource_lock| {
+            RwResourceDataAccessor::<TResource>(unsafe { resource_lock.downcast_unchecked::<RwLock<TResource>>() })
         })
     }
 
     pub fn contains<TComponent: 'static>(&self) -> bool {
-        self.ro_data.contains_key(&TypeId::of::<TComponent>().into()) || self.rw_data.contains_key(&TypeId::of::<TComponent>().into())
+        let component_id = TypeId::of::<TComponent>().into();
+
+        self.ro_data.contains_key(&component_id) || self.rw_data.contains_key(&component_id) ||
+        self.ro_sparse_data.contains_key(&component_id) || self.rw_sparse_data.contains_key(&component_id)
     }
 }
 
@@ -137,3 +430,51 @@ impl ChunkDataAccessor {
 //         }).collect::<Vec<_>>()
 //     }
 // }
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use crate::behavior::query::ArchetypeQuery;
+
+    use super::{ChunkDataAccessor, super::{EcsDataManager, test_fixtures::{TestComponentA, TestTickComponent, register_sparse}}};
+
+    #[test]
+    fn test_sparse_components_in_chunk_queries() {
+        let mut ecs_data_manager = EcsDataManager::new();
+        let component_a_id = ecs_data_manager.register_component::<TestComponentA>();
+        let sparse_component_id = register_sparse::<TestTickComponent>(&mut ecs_data_manager);
+
+        let entity_a = ecs_data_manager.spawn((TestComponentA {},)).unwrap();
+        let entity_sparse = ecs_data_manager.spawn((TestComponentA {},)).unwrap();
+        ecs_data_manager.insert_component(entity_sparse, TestTickComponent(1)).unwrap();
+
+        let required_query = ArchetypeQuery::new(Some(HashSet::from([component_a_id, sparse_component_id])), None, None, None);
+        let except_query = ArchetypeQuery::new(Some(HashSet::from([component_a_id])), Some(HashSet::from([sparse_component_id])), None, None);
+
+        assert_eq!(ecs_data_manager.query_entities(&required_query).unwrap(), vec![entity_sparse]);
+        assert_eq!(ecs_data_manager.query_entities(&except_query).unwrap(), vec![entity_a]);
+
+        // строки чанка без компонента в доступ системы не попадают
+        let (chunk, rows) = ecs_data_manager.query_chunk_rows(&required_query).unwrap().pop().unwrap();
+
+        let mut chunk_data_accessor = ChunkDataAccessor::default();
+        chunk_data_accessor.fill_data_from_chunk(vec![(component_a_id, false), (sparse_component_id, false)], chunk, rows, &ecs_data_manager, 0, 0);
+
+        assert_eq!(chunk_data_accessor.rows().iter().map(|row| chunk_data_accessor.entity_ids()[*row]).collect::<Vec<_>>(), vec![entity_sparse]);
+
+        let sparse_accessor = chunk_data_accessor.resolve_rw_sparse_components::<TestTickComponent>().unwrap();
+        let row = chunk_data_accessor.rows()[0];
+
+        tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(async {
+            let mut sparse_components = sparse_accessor.write().await;
+
+            assert!(sparse_components.get(1 - row).is_none());
+            sparse_components.get_mut(row).unwrap().0 = 2;
+        });
+
+        drop(sparse_accessor);
+
+        assert_eq!(*ecs_data_manager.get::<TestTickComponent>(entity_sparse).unwrap(), TestTickComponent(2));
+    }
+}
@@ -0,0 +1,238 @@
+use std::{any::{TypeId, type_name}, marker::PhantomData};
+
+use crate::types::{ResourceResult, ResourceError};
+
+use super::EcsDataManager;
+
+/// Двойной буфер событий одного типа, хранится ресурсом сцены. События живут два обновления,
+/// поэтому их видят и системы, выполняемые после отправителя, и системы следующего обновления.
+///
+/// Системы обращаются к событиям как к ресурсу: запрос `ArchetypeQuery::resources_only()` и доступ
+/// `ResourcesAccess::new().write::<Events<TEvent>>()` для отправителя, `read::<Events<TEvent>>()` для читателя
+#[derive(Debug)]
+pub struct Events<TEvent> {
+    previous_events: Vec<TEvent>,
+    // номер первого события предыдущего обновления
+    previous_start_count: usize,
+    current_events: Vec<TEvent>,
+    current_start_count: usize,
+    // всего отправлено событий, номер следующего события
+    event_count: usize,
+}
+
+impl<TEvent> Default for Events<TEvent> {
+    fn default() -> Self {
+        Self {
+            previous_events: Default::default(),
+            previous_start_count: 0,
+            current_events: Default::default(),
+            current_start_count: 0,
+            event_count: 0,
+        }
+    }
+}
+
+impl<TEvent> Events<TEvent> {
+    pub fn send(&mut self, event: TEvent) {
+        self.current_events.push(event);
+        self.event_count += 1;
+    }
+
+    /// Смена буферов: события предыдущего обновления удаляются, текущие становятся предыдущими
+    pub fn update(&mut self) {
+        self.previous_events = std::mem::take(&mut self.current_events);
+        self.previous_start_count = self.current_start_count;
+        self.current_start_count = self.event_count;
+    }
+
+    pub fn len(&self) -> usize {
+        self.previous_events.len() + self.current_events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.previous_events.is_empty() && self.current_events.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.previous_events.clear();
+        self.current_events.clear();
+        self.previous_start_count = self.event_count;
+        self.current_start_count = self.event_count;
+    }
+
+    /// Новый читатель видит все хранимые события
+    pub fn reader(&self) -> EventReader<TEvent> {
+        EventReader::default()
+    }
+
+    /// Читатель, пропускающий уже отправленные события
+    pub fn reader_from_current(&self) -> EventReader<TEvent> {
+        EventReader { last_event_count: self.event_count, _marker: PhantomData }
+    }
+}
+
+/// Курсор чтения событий. Каждая система хранит свой курсор и читает события независимо от других.
+/// События, пропущенные дольше двух обновлений, теряются
+#[derive(Debug)]
+pub struct EventReader<TEvent> {
+    last_event_count: usize,
+    _marker: PhantomData<fn() -> TEvent>,
+}
+
+impl<TEvent> Default for EventReader<TEvent> {
+    fn default() -> Self {
+        Self { last_event_count: 0, _marker: PhantomData }
+    }
+}
+
+impl<TEvent> Clone for EventReader<TEvent> {
+    fn clone(&self) -> Self {
+        Self { last_event_count: self.last_event_count, _marker: PhantomData }
+    }
+}
+
+impl<TEvent> EventReader<TEvent> {
+    /// События, отправленные после предыдущего чтения, в порядке отправки
+    pub fn read<'a>(&mut self, events: &'a Events<TEvent>) -> impl Iterator<Item = &'a TEvent> {
+        let last_event_count = self.last_event_count;
+        self.last_event_count = events.event_count;
+
+        let previous_skip = last_event_count.saturating_sub(events.previous_start_count).min(events.previous_events.len());
+        let current_skip = last_event_count.saturating_sub(events.current_start_count).min(events.current_events.len());
+
+        events.previous_events[previous_skip..].iter().chain(events.current_events[current_skip..].iter())
+    }
+
+    /// Количество непрочитанных событий, курсор не сдвигается
+    pub fn len(&self, events: &Events<TEvent>) -> usize {
+        let previous_skip = self.last_event_count.saturating_sub(events.previous_start_count).min(events.previous_events.len());
+        let current_skip = self.last_event_count.saturating_sub(events.current_start_count).min(events.current_events.len());
+
+        events.previous_events.len() - previous_skip + events.current_events.len() - current_skip
+    }
+
+    pub fn is_empty(&self, events: &Events<TEvent>) -> bool {
+        self.len(events) == 0
+    }
+}
+
+impl EcsDataManager {
+    /// Регистрация типа события: ресурс `Events<TEvent>` и смена его буферов в начале каждого обновления
+    pub fn add_event<TEvent: Sync + Send + 'static>(&mut self) {
+        if !self.contains_resource::<Events<TEvent>>() {
+            self.insert_resource(Events::<TEvent>::default());
+        }
+
+        self.events_updaters.insert(TypeId::of::<TEvent>(), |ecs_data_manager| {
+            if let Ok(mut events) = ecs_data_manager.resource_mut::<Events<TEvent>>() {
+                events.update();
+            }
+        });
+    }
+
+    /// Отправка события вне систем. Тип события должен быть зарегистрирован `add_event`, иначе буферы не сменяются и события не удаляются
+    pub fn send<TEvent: Sync + Send + 'static>(&mut self, event: TEvent) -> ResourceResult<()> {
+        if !self.events_updaters.contains_key(&TypeId::of::<TEvent>()) {
+            return Err(ResourceError::EventNotRegistered { event_name: type_name::<TEvent>().to_string(), event_type_id: TypeId::of::<TEvent>() });
+        }
+
+        self.resource_mut::<Events<TEvent>>()?.send(event);
+
+        Ok(())
+    }
+
+    pub (crate) fn update_events(&mut self) {
+        let events_updaters = self.events_updaters.values().copied().collect::<Vec<_>>();
+
+        events_updaters.into_iter().for_each(|events_updater| events_updater(self));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use tokio::sync::RwLock;
+
+    use crate::{behavior::{EcsBehaviorManager, query::ArchetypeQuery, system::{IBlockingSystemHandler, ResourcesAccess}}, types::ResourceError};
+
+    use super::{Events, EventReader, super::{EcsDataManager, entity_data_accessor::ChunkDataAccessor, commands::Commands}};
+
+    #[derive(Debug)]
+    struct TestEvent(u32);
+
+    #[derive(Debug)]
+    struct TestEventSender(u32);
+
+    #[async_trait::async_trait(?Send)]
+    impl IBlockingSystemHandler for TestEventSender {
+        async fn handle(&mut self, mut chunk_data_accessor: ChunkDataAccessor, _: &mut Commands) {
+            self.0 += 1;
+            chunk_data_accessor.resolve_rw_resource::<Events<TestEvent>>().unwrap().write().await.send(TestEvent(self.0));
+        }
+
+        fn archetype_query(&self) -> ArchetypeQuery {
+            ArchetypeQuery::resources_only()
+        }
+
+        fn resources_access(&self) -> ResourcesAccess {
+            ResourcesAccess::new().write::<Events<TestEvent>>()
+        }
+    }
+
+    #[derive(Debug)]
+    struct TestEventReceiver {
+        event_reader: EventReader<TestEvent>,
+        received_events: Arc<Mutex<Vec<Vec<u32>>>>,
+    }
+
+    #[async_trait::async_trait(?Send)]
+    impl IBlockingSystemHandler for TestEventReceiver {
+        async fn handle(&mut self, mut chunk_data_accessor: ChunkDataAccessor, _: &mut Commands) {
+            let events_accessor = chunk_data_accessor.resolve_ro_resource::<Events<TestEvent>>().unwrap();
+            let events = events_accessor.read().await;
+
+            let received_events = self.event_reader.read(&events).map(|event| event.0).collect();
+            self.received_events.lock().unwrap().push(received_events);
+        }
+
+        fn archetype_query(&self) -> ArchetypeQuery {
+            ArchetypeQuery::resources_only()
+        }
+
+        fn resources_access(&self) -> ResourcesAccess {
+            ResourcesAccess::new().read::<Events<TestEvent>>()
+        }
+    }
+
+    #[test]
+    fn test_events_between_systems() {
+        let mut ecs_data_manager = EcsDataManager::new();
+
+        // без регистрации событие не отправляется
+        assert!(matches!(ecs_data_manager.send(TestEvent(0)), Err(ResourceError::EventNotRegistered { .. })));
+        assert!(!ecs_data_manager.contains_resource::<Events<TestEvent>>());
+
+        ecs_data_manager.add_event::<TestEvent>();
+        ecs_data_manager.send(TestEvent(0)).unwrap();
+
+        let ecs_data_manager = Arc::new(RwLock::new(ecs_data_manager));
+        let received_events = Arc::new(Mutex::new(Vec::new()));
+
+        let mut ecs_behavior_manager = EcsBehaviorManager::default();
+        ecs_behavior_manager.get_system_builder().unwrap().build_with_sync_handler(TestEventSender(0)).unwrap();
+
+        let mut receiver_builder = ecs_behavior_manager.get_system_builder().unwrap();
+        receiver_builder.need_system_result::<TestEventSender>();
+        receiver_builder.build_with_sync_handler(TestEventReceiver { event_reader: EventReader::default(), received_events: received_events.clone() }).unwrap();
+
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+
+        for _ in 0..3 {
+            ecs_behavior_manager.update(ecs_data_manager.clone(), runtime.handle().clone());
+        }
+
+        // событие вне систем видно в первом обновлении, события отправителя - в том же обновлении
+        assert_eq!(*received_events.lock().unwrap(), vec![vec![0, 1], vec![2], vec![3]]);
+    }
+}
@@ -0,0 +1,412 @@
+use std::{any::Any, fmt::Debug, ops::Deref, collections::{HashMap, HashSet}};
+
+use tokio::sync::RwLockReadGuard;
+
+use crate::types::{EntityId, ComponentId, EntityResult, EntityError, SnapshotResult, TextResult};
+
+use super::{EcsDataManager, component::storage_type::StorageType, component_access::{ComponentRef, ComponentMut}, snapshot::snapshot_io::{ISnapshotComponent, SnapshotWriter, SnapshotReader}, text::text_io::{ITextComponent, TextValue, TextContext}};
+
+/// Родитель сущности. Изменяется только через `EcsDataManager::set_parent`/`remove_parent`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Parent(pub (crate) EntityId);
+
+impl Parent {
+    pub fn get(&self) -> EntityId {
+        self.0
+    }
+}
+
+/// Дочерние сущности в порядке добавления, поддерживаются вместе с `Parent`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Children(pub (crate) Vec<EntityId>);
+
+impl Deref for Children {
+    type Target = [EntityId];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl ISnapshotComponent for Parent {
+    fn write_snapshot(&self, writer: &mut SnapshotWriter) {
+        self.0.write_snapshot(writer)
+    }
+
+    fn read_snapshot(reader: &mut SnapshotReader) -> SnapshotResult<Self> {
+        Ok(Self(EntityId::read_snapshot(reader)?))
+    }
+}
+
+impl ISnapshotComponent for Children {
+    fn write_snapshot(&self, writer: &mut SnapshotWriter) {
+        self.0.write_snapshot(writer)
+    }
+
+    fn read_snapshot(reader: &mut SnapshotReader) -> SnapshotResult<Self> {
+        Ok(Self(Vec::read_snapshot(reader)?))
+    }
+}
+
+impl ITextComponent for Parent {
+    fn write_text(&self, context: &TextContext) -> TextValue {
+        self.0.write_text(context)
+    }
+
+    fn read_text(value: &TextValue, context: &TextContext) -> TextResult<Self> {
+        Ok(Self(EntityId::read_text(value, context)?))
+    }
+}
+
+impl ITextComponent for Children {
+    fn write_text(&self, context: &TextContext) -> TextValue {
+        self.0.write_text(context)
+    }
+
+    fn read_text(value: &TextValue, context: &TextContext) -> TextResult<Self> {
+        Ok(Self(Vec::read_text(value, context)?))
+    }
+}
+
+/// Обход поддерева в глубину, корень не входит
+pub struct DescendantsIter<'a> {
+    ecs_data_manager: &'a EcsDataManager,
+    stack: Vec<EntityId>,
+}
+
+impl<'a> Iterator for DescendantsIter<'a> {
+    type Item = EntityResult<EntityId>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entity_id = self.stack.pop()?;
+
+        // дочерние сущности кладутся в обратном порядке, чтобы первой обходилась первая
+        match self.ecs_data_manager.children(entity_id) {
+            Ok(children) => self.stack.extend(children.iter().rev()),
+            Err(error) => {
+                self.stack.clear();
+                return Some(Err(error));
+            },
+        }
+
+        Some(Ok(entity_id))
+    }
+}
+
+/// Parent и Children изменяются только вместе, через методы иерархии
+pub (crate) fn is_hierarchy_component(component_id: &ComponentId) -> bool {
+    *component_id == ComponentId::from_type::<Parent>() || *component_id == ComponentId::from_type::<Children>()
+}
+
+/// Проверка связей иерархии загружаемых сущностей: родитель - одна из сущностей `entity_ids`, но не сама сущность и не ее потомок,
+/// `Children` родителя содержит сущность ровно один раз, у каждой дочерней сущности `Parent` указывает на родителя
+pub (crate) fn check_hierarchy_links(entity_ids: &HashSet<EntityId>, parents: &HashMap<EntityId, EntityId>, children: &HashMap<EntityId, Vec<EntityId>>) -> Result<(), String> {
+    for (entity_id, parent_id) in parents.iter() {
+        if !entity_ids.contains(parent_id) {
+            return Err(format!("dangling parent of entity {entity_id:?}"));
+        }
+
+        if children.get(parent_id).is_none_or(|child_ids| child_ids.iter().filter(|child_id| *child_id == entity_id).count() != 1) {
+            return Err(format!("children of {parent_id:?} do not contain {entity_id:?} exactly once"));
+        }
+    }
+
+    for (entity_id, child_ids) in children.iter() {
+        // пустой список удаляется вместе с последней дочерней сущностью
+        if child_ids.is_empty() {
+            return Err(format!("empty children of entity {entity_id:?}"));
+        }
+
+        if let Some(child_id) = child_ids.iter().find(|child_id| parents.get(*child_id) != Some(entity_id)) {
+            return Err(format!("parent of {child_id:?} does not match children of {entity_id:?}"));
+        }
+    }
+
+    // сущности, от которых цепочка родителей доходит до корня
+    let mut rooted_entity_ids = HashSet::new();
+
+    for entity_id in parents.keys() {
+        let mut path = HashSet::new();
+        let mut current_id = *entity_id;
+
+        while !rooted_entity_ids.contains(&current_id) {
+            if !path.insert(current_id) {
+                return Err(format!("hierarchy cycle through entity {current_id:?}"));
+            }
+
+            match parents.get(&current_id) {
+                Some(parent_id) => current_id = *parent_id,
+                None => break,
+            }
+        }
+
+        rooted_entity_ids.extend(path);
+    }
+
+    Ok(())
+}
+
+impl EcsDataManager {
+    /// Связи иерархии часто меняются, поэтому хранятся в разреженных множествах и не перемещают сущности между архетипами.
+    /// Регистрируются первыми, когда стабильные имена еще свободны
+    pub (crate) fn register_hierarchy_components(&mut self) {
+        let mut parent_builder = self.get_component_builder::<Parent>();
+        parent_builder.storage_type(StorageType::SparseSet).stable_id("anthill_ecs::Parent").snapshot().text();
+        parent_builder.build().unwrap();
+
+        let mut children_builder = self.get_component_builder::<Children>();
+        children_builder.storage_type(StorageType::SparseSet).stable_id("anthill_ecs::Children").snapshot().text();
+        children_builder.build().unwrap();
+    }
+
+    pub fn parent(&self, entity_id: EntityId) -> EntityResult<Option<EntityId>> {
+        if !self.is_alive(entity_id) {
+            return Err(EntityError::NoSuchEntity { entity_id });
+        }
+
+        Ok(self.hierarchy_component::<Parent>(entity_id)?.map(|parent| parent.get()))
+    }
+
+    /// Список остается захваченным на чтение, пока жива ссылка
+    pub fn children(&self, entity_id: EntityId) -> EntityResult<ComponentRef<'_, [EntityId]>> {
+        if !self.is_alive(entity_id) {
+            return Err(EntityError::NoSuchEntity { entity_id });
+        }
+
+        match self.hierarchy_component::<Children>(entity_id)? {
+            Some(children) => Ok(ComponentRef::Locked(RwLockReadGuard::map(children, |children| children.0.as_slice()))),
+            None => Ok(ComponentRef::Borrowed(&[])),
+        }
+    }
+
+    /// Err - значения захвачены ссылкой, которую удерживает система
+    fn hierarchy_component<TComponent: Debug + Sync + Send + 'static>(&self, entity_id: EntityId) -> EntityResult<Option<RwLockReadGuard<'_, TComponent>>> {
+        self.sparse_component::<TComponent>(entity_id).transpose()
+            .map_err(|_| EntityError::ComponentLocked { entity_id, component_id: ComponentId::from_type::<TComponent>() })
+    }
+
+    fn hierarchy_component_mut<TComponent: Debug + Sync + Send + 'static>(&mut self, entity_id: EntityId) -> EntityResult<Option<ComponentMut<'_, TComponent>>> {
+        self.sparse_component_mut::<TComponent>(entity_id).transpose()
+            .map_err(|_| EntityError::ComponentLocked { entity_id, component_id: ComponentId::from_type::<TComponent>() })
+    }
+
+    /// Связь меняется с обеих сторон, поэтому множества Parent и Children проверяются до первого изменения
+    fn check_hierarchy_unlocked(&self, entity_id: EntityId) -> EntityResult<()> {
+        match self.locked_sparse_component([ComponentId::from_type::<Parent>(), ComponentId::from_type::<Children>()].iter()) {
+            Some(component_id) => Err(EntityError::ComponentLocked { entity_id, component_id }),
+            None => Ok(()),
+        }
+    }
+
+    pub fn iter_descendants(&self, entity_id: EntityId) -> EntityResult<DescendantsIter<'_>> {
+        Ok(DescendantsIter { ecs_data_manager: self, stack: self.children(entity_id)?.iter().rev().copied().collect() })
+    }
+
+    /// Перенос сущности к новому родителю, у старого родителя сущность удаляется из `Children`
+    pub fn set_parent(&mut self, entity_id: EntityId, parent_id: EntityId) -> EntityResult<()> {
+        if !self.is_alive(entity_id) {
+            return Err(EntityError::NoSuchEntity { entity_id });
+        }
+
+        if !self.is_alive(parent_id) {
+            return Err(EntityError::NoSuchEntity { entity_id: parent_id });
+        }
+
+        // родитель не может быть самой сущностью или ее потомком
+        let mut ancestor_id = Some(parent_id);
+
+        while let Some(current_ancestor_id) = ancestor_id {
+            if current_ancestor_id == entity_id {
+                return Err(EntityError::HierarchyCycle { entity_id, parent_id });
+            }
+
+            ancestor_id = self.parent(current_ancestor_id)?;
+        }
+
+        if self.parent(entity_id)? == Some(parent_id) {
+            return Ok(());
+        }
+
+        self.check_hierarchy_unlocked(entity_id)?;
+
+        self.remove_parent(entity_id)?;
+
+        self.insert_component_unchecked(entity_id, Parent(parent_id))?;
+
+        let is_child_added = self.hierarchy_component_mut::<Children>(parent_id)?.map(|mut children| children.0.push(entity_id)).is_some();
+
+        if !is_child_added {
+            self.insert_component_unchecked(parent_id, Children(vec![entity_id]))?;
+        }
+
+        Ok(())
+    }
+
+    /// Отсоединение сущности от родителя, возвращает бывшего родителя
+    pub fn remove_parent(&mut self, entity_id: EntityId) -> EntityResult<Option<EntityId>> {
+        if !self.is_alive(entity_id) {
+            return Err(EntityError::NoSuchEntity { entity_id });
+        }
+
+        self.check_hierarchy_unlocked(entity_id)?;
+
+        let Some(Parent(parent_id)) = self.remove_component_unchecked::<Parent>(entity_id)? else {
+            return Ok(None);
+        };
+
+        let is_children_empty = self.hierarchy_component_mut::<Children>(parent_id)?.is_some_and(|mut children| {
+            children.0.retain(|child_id| *child_id != entity_id);
+            children.is_empty()
+        });
+
+        if is_children_empty {
+            self.remove_component_unchecked::<Children>(parent_id)?;
+        }
+
+        Ok(Some(parent_id))
+    }
+
+    /// Удаление сущности вместе со всеми потомками. Если колонки хотя бы одной из сущностей удерживаются, не удаляется ни одна
+    pub fn despawn_recursive(&mut self, entity_id: EntityId) -> EntityResult<()> {
+        if !self.is_alive(entity_id) {
+            return Err(EntityError::NoSuchEntity { entity_id });
+        }
+
+        let descendants = self.iter_descendants(entity_id)?.collect::<EntityResult<Vec<_>>>()?;
+
+        std::iter::once(&entity_id).chain(descendants.iter()).try_for_each(|entity_id| self.check_entity_unlocked(*entity_id))?;
+
+        self.remove_entity(entity_id)?;
+
+        // команды хуков могли удалить часть потомков
+        for descendant_id in descendants {
+            match self.remove_entity(descendant_id) {
+                Ok(()) | Err(EntityError::NoSuchEntity { .. }) => {},
+                Err(error) => return Err(error),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Перед удалением сущность отсоединяется от родителя, ее дочерние сущности становятся корневыми
+    pub (crate) fn detach_from_hierarchy(&mut self, entity_id: EntityId) -> EntityResult<()> {
+        self.remove_parent(entity_id)?;
+        self.detach_children(entity_id)?;
+
+        Ok(())
+    }
+
+    /// Дочерние сущности становятся корневыми
+    fn detach_children(&mut self, entity_id: EntityId) -> EntityResult<Option<Children>> {
+        let Some(children) = self.remove_component_unchecked::<Children>(entity_id)? else {
+            return Ok(None);
+        };
+
+        for child_id in children.iter() {
+            self.remove_component_unchecked::<Parent>(*child_id)?;
+        }
+
+        Ok(Some(children))
+    }
+
+    /// `remove_component::<Parent>` и `remove_component::<Children>` снимают связь с обеих сторон
+    pub (crate) fn remove_hierarchy_component(&mut self, entity_id: EntityId, component_id: ComponentId) -> EntityResult<Option<Box<dyn Any + Sync + Send>>> {
+        if component_id == ComponentId::from_type::<Parent>() {
+            return Ok(self.remove_parent(entity_id)?.map(|parent_id| Box::new(Parent(parent_id)) as Box<dyn Any + Sync + Send>));
+        }
+
+        if !self.is_alive(entity_id) {
+            return Err(EntityError::NoSuchEntity { entity_id });
+        }
+
+        self.check_hierarchy_unlocked(entity_id)?;
+
+        Ok(self.detach_children(entity_id)?.map(|children| Box::new(children) as Box<dyn Any + Sync + Send>))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use crate::{behavior::query::ArchetypeQuery, types::{ComponentId, EntityError, AddEntityError}};
+
+    use super::{Parent, Children, super::{EcsDataManager, entity_data_accessor::ChunkDataAccessor, test_fixtures::TestTickComponent}};
+
+    #[test]
+    fn test_hierarchy_components_change_both_sides() {
+        let mut ecs_data_manager = EcsDataManager::new();
+        ecs_data_manager.register_component::<TestTickComponent>();
+
+        let parent_id = ecs_data_manager.spawn((TestTickComponent(0),)).unwrap();
+        let child_id = ecs_data_manager.spawn((TestTickComponent(1),)).unwrap();
+
+        ecs_data_manager.set_parent(child_id, parent_id).unwrap();
+
+        assert!(matches!(ecs_data_manager.set_parent(parent_id, child_id), Err(EntityError::HierarchyCycle { .. })));
+        assert!(matches!(ecs_data_manager.insert_component(child_id, Parent(child_id)), Err(EntityError::HierarchyComponent { .. })));
+        assert!(matches!(ecs_data_manager.get_mut::<Parent>(child_id), Err(EntityError::HierarchyComponent { .. })));
+        assert!(matches!(ecs_data_manager.get_many_mut::<(TestTickComponent, Children)>(parent_id), Err(EntityError::HierarchyComponent { .. })));
+
+        assert_eq!(ecs_data_manager.remove_component::<Parent>(child_id).unwrap().map(|parent| parent.get()), Some(parent_id));
+        assert!(ecs_data_manager.children(parent_id).unwrap().is_empty());
+        assert!(!ecs_data_manager.has_component::<Children>(parent_id));
+
+        ecs_data_manager.set_parent(child_id, parent_id).unwrap();
+
+        assert_eq!(ecs_data_manager.remove_component::<Children>(parent_id).unwrap().map(|children| children.to_vec()), Some(vec![child_id]));
+        assert_eq!(ecs_data_manager.parent(child_id).unwrap(), None);
+    }
+
+    #[test]
+    fn test_hierarchy_components_are_not_spawned() {
+        let mut ecs_data_manager = EcsDataManager::new();
+        ecs_data_manager.register_component::<TestTickComponent>();
+
+        let entity_id = ecs_data_manager.spawn((TestTickComponent(0),)).unwrap();
+
+        // иерархия создается только через set_parent, иначе связь была бы односторонней
+        assert!(matches!(ecs_data_manager.spawn((TestTickComponent(1), Children(vec![]))), Err(AddEntityError::HierarchyComponent { .. })));
+        assert!(matches!(ecs_data_manager.add_entity(vec![Box::new(Parent(entity_id))]), Err(AddEntityError::HierarchyComponent { .. })));
+        assert!(matches!(ecs_data_manager.spawn_batch([(Parent(entity_id),)]), Err(AddEntityError::HierarchyComponent { .. })));
+
+        assert_eq!(ecs_data_manager.index_count, 1);
+        assert!(ecs_data_manager.children(entity_id).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_held_hierarchy_returns_errors() {
+        let mut ecs_data_manager = EcsDataManager::new();
+        ecs_data_manager.register_component::<TestTickComponent>();
+
+        let parent_id = ecs_data_manager.spawn((TestTickComponent(0),)).unwrap();
+        let child_id = ecs_data_manager.spawn((TestTickComponent(1),)).unwrap();
+        let grandchild_id = ecs_data_manager.spawn((TestTickComponent(2),)).unwrap();
+
+        ecs_data_manager.set_parent(child_id, parent_id).unwrap();
+        ecs_data_manager.set_parent(grandchild_id, child_id).unwrap();
+
+        assert_eq!(ecs_data_manager.iter_descendants(parent_id).unwrap().collect::<Result<Vec<_>, _>>().unwrap(), vec![child_id, grandchild_id]);
+
+        // система читает Parent: изменения иерархии отклоняются без ожидания
+        let parent_component_id = ComponentId::from_type::<Parent>();
+        let query = ArchetypeQuery::new(Some(HashSet::from([parent_component_id])), None, None, None);
+        let (chunk, rows) = ecs_data_manager.query_chunk_rows(&query).unwrap().pop().unwrap();
+
+        let mut chunk_data_accessor = ChunkDataAccessor::default();
+        chunk_data_accessor.fill_data_from_chunk(vec![(parent_component_id, true)], chunk, rows, &ecs_data_manager, 0, 0);
+
+        assert!(matches!(ecs_data_manager.remove_parent(grandchild_id), Err(EntityError::ComponentLocked { .. })));
+        assert!(matches!(ecs_data_manager.set_parent(grandchild_id, parent_id), Err(EntityError::ComponentLocked { .. })));
+        assert!(matches!(ecs_data_manager.despawn_recursive(parent_id), Err(EntityError::ComponentLocked { .. })));
+
+        drop(chunk_data_accessor);
+
+        assert_eq!(ecs_data_manager.parent(grandchild_id).unwrap(), Some(child_id));
+
+        ecs_data_manager.despawn_recursive(parent_id).unwrap();
+        assert!(![parent_id, child_id, grandchild_id].iter().any(|entity_id| ecs_data_manager.is_alive(*entity_id)));
+        assert!(matches!(ecs_data_manager.parent(child_id), Err(EntityError::NoSuchEntity { .. })));
+    }
+}
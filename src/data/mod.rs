@@ -4,36 +4,95 @@ pub mod entity_data_accessor;
 pub mod entity_data;
 pub mod new_entity_components_info;
 pub mod component;
+pub mod commands;
+pub mod hierarchy;
+pub mod component_access;
+pub mod resources;
+pub mod events;
+pub mod dynamic_components;
+pub mod snapshot;
+pub mod text;
+pub mod prefab;
+
+#[cfg(test)]
+pub (crate) mod test_fixtures;
 
 use std::{
-    collections::{HashMap, HashSet},
-    any::{TypeId, Any},
+    collections::HashMap,
+    any::{Any, TypeId},
     fmt::Debug, sync::Arc
 };
 
-use tokio::sync::RwLock;
 use vec_map::VecMap;
 
 use crate::types::{
     EntityId,
     ArchetypeType,
-    ComponentId, AddEntityResult, AddEntityError
+    ComponentId, AddEntityResult, AddEntityError, EntityResult, EntityError, ArchetypeId, EntityLocation, ComponentsBitSet, EntityIdRange,
+    StableComponentId, RegisterComponentResult, RegisterComponentError, QueryResult, CommandError
 };
 
-use self::{archetype::{Archetype, ArchetypeChunk}, /* entity_builder::EntityBuilder,  */entity_data_accessor::ChunkDataAccessor, entity_data::EntityData, component::component_info::ComponentInfo};
+use crate::behavior::query::{ArchetypeQuery, ArchetypeQuerySignature};
+
+use self::{
+    archetype::{Archetype, ArchetypeChunk, SharedComponentsKey, CHECK_TICK_THRESHOLD, is_tick_newer, check_tick}, entity_data::EntityData, new_entity_components_info::{INewEntityComponentsInfo, NewEntitiesBatchWriter},
+    commands::Commands,
+    resources::ResourceLock,
+    hierarchy::{Parent, Children, is_hierarchy_component},
+    component::{component_info::{ComponentInfo, ComponentHookClosure}, component_builder::ComponentBuilder, storage_type::StorageType, sparse_set::IComponentSparseSet, dynamic_component::boxed_component_id}
+};
 
-// байты
+// байты, бюджет чанка по умолчанию
 const CHUNK_SIZE: usize = 16_000;
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct EcsDataManager where Self: Sync + Send{
     free_entity_id: Vec<EntityId>,
-    entity_index: VecMap<ArchetypeType>,
+    // текущее поколение каждого слота идентификатора, увеличивается при удалении сущности
+    entity_versions: Vec<usize>,
+    entity_index: VecMap<EntityLocation>,
     index_count: usize,
 
-    pub (crate) archetype_map: HashMap<ArchetypeType, Archetype>,
+    pub (crate) archetypes: Vec<Archetype>,
+    archetype_map: HashMap<ArchetypeType, ArchetypeId>,
     components_info: HashMap<ComponentId, ComponentInfo>,
-    //components_count: u32,
+    // реестр стабильных идентификаторов, обратное соответствие хранится в ComponentInfo
+    stable_component_ids: HashMap<StableComponentId, ComponentId>,
+    // компоненты со StorageType::SparseSet, хранятся вне архетипов
+    sparse_sets: HashMap<ComponentId, Box<dyn IComponentSparseSet>>,
+    // значения общих компонентов (StorageType::Shared), чанки хранят индексы значений.
+    // Значение, на которое не ссылается ни одна сущность, освобождается, слот переиспользуется
+    shared_values: HashMap<ComponentId, Vec<Option<Box<dyn Any + Sync + Send>>>>,
+    // количество сущностей, ссылающихся на каждое значение общего компонента, по индексам shared_values
+    shared_value_refs: HashMap<ComponentId, Vec<usize>>,
+
+    // такт мира, колонки чанков запоминают такт последнего изменения
+    change_tick: u32,
+    // такты начала текущего и предыдущего обновлений систем
+    update_tick: u32,
+    pub (crate) previous_update_tick: u32,
+    // удаленные компоненты с тактом удаления, хранятся удаления двух последних обновлений
+    removed_components: HashMap<ComponentId, Vec<(EntityId, u32)>>,
+    // команды хуков компонентов, применяются после завершения структурного изменения
+    hook_commands: Commands,
+    // ошибки команд хуков, забираются через take_command_errors
+    command_errors: Vec<CommandError>,
+
+    // ресурсы - единственные значения своего типа, не привязанные к сущностям
+    resources: HashMap<TypeId, ResourceLock>,
+    // смена буферов зарегистрированных типов событий
+    events_updaters: HashMap<TypeId, fn(&mut EcsDataManager)>,
+    // количество зарегистрированных динамических компонентов, следующий номер ComponentKind::Dynamic
+    dynamic_components_count: usize,
+
+    // байты, из бюджета и размеров компонентов архетипа выводится вместимость его чанков
+    chunk_size: usize,
+}
+
+impl Default for EcsDataManager {
+    fn default() -> Self {
+        Self::with_chunk_size(CHUNK_SIZE)
+    }
 }
 
 impl EcsDataManager where Self: Sync + Send {
@@ -41,12 +100,292 @@ impl EcsDataManager where Self: Sync + Send {
         Self { ..Default::default() }
     }
 
+    pub (crate) fn component_info(&self, component_id: &ComponentId) -> Option<&ComponentInfo> {
+        self.components_info.get(component_id)
+    }
+
+    /// Чанки подходящих под запрос архетипов, с учетом фильтра по значениям общих компонентов.
+    /// Чанк без единой сущности, прошедшей фильтры по отдельным сущностям, не подходит
+    pub fn query_chunks(&self, query: &ArchetypeQuery) -> QueryResult<Vec<&ArchetypeChunk>> {
+        Ok(self.query_chunk_rows(query)?.into_iter().map(|(chunk, _)| chunk).collect())
+    }
+
+    /// Чанки запроса и их строки, прошедшие фильтры по отдельным сущностям: такты компонентов и компоненты из разреженных множеств.
+    /// Такты, захваченные выполняющейся системой на запись, не ожидаются: ошибка ComponentLocked
+    pub (crate) fn query_chunk_rows(&self, query: &ArchetypeQuery) -> QueryResult<Vec<(&ArchetypeChunk, Vec<usize>)>> {
+        let query_signature = match query.build_signature(self) {
+            Some(query_signature) => query_signature,
+            None => return Ok(Vec::new()),
+        };
+
+        self.archetypes.iter()
+            .filter(|archetype| query_signature.is_match(&archetype.signature))
+            .flat_map(|archetype| archetype.get_chunks())
+            .filter(|chunk| query_signature.is_shared_match(&chunk.shared_components_key) && query.is_chunk_match(chunk))
+            .filter_map(|chunk| {
+                let rows = match chunk.filter_rows(&query_signature.ticks_filters, query_signature.last_run_tick) {
+                    Ok(rows) => rows,
+                    Err(error) => return Some(Err(error)),
+                };
+
+                let rows = rows.into_iter()
+                    .filter(|row| self.is_sparse_match(&query_signature, chunk.entity_ids[*row]))
+                    .collect::<Vec<_>>();
+
+                (!rows.is_empty()).then_some(Ok((chunk, rows)))
+            })
+            .collect()
+    }
+
+    fn is_sparse_match(&self, query_signature: &ArchetypeQuerySignature, entity_id: EntityId) -> bool {
+        query_signature.required_sparse.iter().all(|component_id| self.sparse_sets[component_id].contains(entity_id)) &&
+        !query_signature.except_sparse.iter().any(|component_id| self.sparse_sets[component_id].contains(entity_id))
+    }
+
+    /// Значение общего компонента чанка
+    pub fn shared_component<TComponent: Sync + Send + 'static>(&self, archetype_chunk: &ArchetypeChunk) -> Option<&TComponent> {
+        let component_id = ComponentId::from_type::<TComponent>();
+
+        archetype_chunk.shared_components_key.iter()
+            .find(|(shared_component_id, _)| *shared_component_id == component_id)
+            .map(|(_, value_index)| unsafe { &*(self.shared_value(&component_id, *value_index) as *const (dyn Any + Sync + Send) as *const TComponent) })
+    }
+
+    /// Значение общего компонента по индексу из ключа существующей группы чанков
+    pub (crate) fn shared_value(&self, component_id: &ComponentId, value_index: usize) -> &(dyn Any + Sync + Send) {
+        self.shared_values[component_id][value_index].as_deref().unwrap()
+    }
+
+    /// Индекс значения общего компонента, если такое значение уже встречалось
+    pub (crate) fn find_shared_value(&self, component_id: &ComponentId, value: &dyn Any) -> Option<usize> {
+        let shared_value_eq = self.components_info.get(component_id)?.shared_value_eq?;
+
+        self.shared_values.get(component_id)?.iter().position(|shared_value| shared_value.as_deref().is_some_and(|shared_value| shared_value_eq(shared_value, value)))
+    }
+
+    /// Индекс значения общего компонента, одинаковые значения хранятся один раз
+    fn intern_shared_value(&mut self, component_id: ComponentId, value: Box<dyn Any + Sync + Send>) -> usize {
+        if let Some(value_index) = self.find_shared_value(&component_id, value.as_ref()) {
+            return value_index;
+        }
+
+        let shared_values = self.shared_values.entry(component_id).or_default();
+
+        match shared_values.iter().position(Option::is_none) {
+            Some(value_index) => {
+                shared_values[value_index] = Some(value);
+                value_index
+            },
+            None => {
+                shared_values.push(Some(value));
+                shared_values.len() - 1
+            },
+        }
+    }
+
+    /// Сущность, размещенная в группе чанков с ключом, ссылается на его значения
+    fn retain_shared_values(&mut self, shared_components_key: &SharedComponentsKey) {
+        shared_components_key.iter().for_each(|(component_id, value_index)| {
+            let shared_value_refs = self.shared_value_refs.entry(*component_id).or_default();
+
+            if shared_value_refs.len() <= *value_index {
+                shared_value_refs.resize(value_index + 1, 0);
+            }
+
+            shared_value_refs[*value_index] += 1;
+        });
+    }
+
+    /// Сущность больше не ссылается на значения ключа, значение без ссылок освобождается.
+    /// Вызывается после переноса сущности, когда ее новый ключ уже ссылается на свои значения
+    fn release_shared_values(&mut self, shared_components_key: &SharedComponentsKey) {
+        shared_components_key.iter().for_each(|(component_id, value_index)| {
+            let shared_value_refs = &mut self.shared_value_refs.get_mut(component_id).unwrap()[*value_index];
+            *shared_value_refs -= 1;
+
+            if *shared_value_refs == 0 {
+                self.shared_values.get_mut(component_id).unwrap()[*value_index] = None;
+            }
+        });
+    }
+
+    /// Ключ группы чанков для значений общих компонентов без сохранения значений. None, если хотя бы одно значение еще не встречалось
+    fn find_shared_components_key(&self, archetype_type: &ArchetypeType, components_map: &HashMap<ComponentId, Box<dyn Any + Send + Sync>>) -> Option<SharedComponentsKey> {
+        archetype_type.iter()
+            .filter(|component_id| self.components_info[*component_id].storage_type == StorageType::Shared)
+            .map(|component_id| self.find_shared_value(component_id, components_map[component_id].as_ref()).map(|value_index| (*component_id, value_index)))
+            .collect()
+    }
+
+    /// Значения общих компонентов извлекаются из данных сущности и заменяются ключом чанка
+    fn extract_shared_values(&mut self, archetype_id: ArchetypeId, entity_data: &mut EntityData) -> SharedComponentsKey {
+        self.archetypes[*archetype_id].shared_component_ids.clone().into_iter().map(|component_id| {
+            let value = entity_data.remove_component(&component_id).unwrap();
+            (component_id, self.intern_shared_value(component_id, value))
+        }).collect()
+    }
+
+    /// Сущности, подходящие под запрос. Учитываются компоненты обоих способов хранения
+    pub fn query_entities(&self, query: &ArchetypeQuery) -> QueryResult<Vec<EntityId>> {
+        Ok(self.query_chunk_rows(query)?.into_iter()
+            .flat_map(|(chunk, rows)| rows.into_iter().map(|row| chunk.entity_ids[row]))
+            .collect())
+    }
+
+    /// `chunk_size` - бюджет чанка в байтах
+    pub fn with_chunk_size(chunk_size: usize) -> Self {
+        let mut ecs_data_manager = Self {
+            free_entity_id: Default::default(),
+            entity_versions: Default::default(),
+            entity_index: Default::default(),
+            index_count: 0,
+            archetypes: Default::default(),
+            archetype_map: Default::default(),
+            components_info: Default::default(),
+            stable_component_ids: Default::default(),
+            sparse_sets: Default::default(),
+            shared_values: Default::default(),
+            shared_value_refs: Default::default(),
+            change_tick: 0,
+            update_tick: 0,
+            previous_update_tick: 0,
+            removed_components: Default::default(),
+            hook_commands: Default::default(),
+            command_errors: Default::default(),
+            resources: Default::default(),
+            events_updaters: Default::default(),
+            dynamic_components_count: 0,
+            chunk_size,
+        };
+
+        ecs_data_manager.register_hierarchy_components();
+
+        ecs_data_manager
+    }
+
+    pub fn change_tick(&self) -> u32 {
+        self.change_tick
+    }
+
+    /// Новый такт мира. Изменения колонок после этого помечаются новым тактом.
+    /// Планировщик выделяет такт началу обновления, каждому запуску системы и применению команд систем
+    pub fn increment_change_tick(&mut self) -> u32 {
+        self.change_tick = self.change_tick.wrapping_add(1);
+
+        if self.change_tick.is_multiple_of(CHECK_TICK_THRESHOLD) {
+            self.check_change_ticks();
+        }
+
+        self.change_tick
+    }
+
+    /// Начало обновления систем. Журнал удалений хранит удаления двух последних обновлений, включая команды между ними
+    pub fn begin_update(&mut self) -> u32 {
+        self.previous_update_tick = self.update_tick;
+        self.update_tick = self.increment_change_tick();
+
+        let previous_update_tick = self.previous_update_tick;
+
+        self.removed_components.values_mut().for_each(|removed_entities| {
+            removed_entities.retain(|(_, removed_tick)| !is_tick_newer(previous_update_tick, *removed_tick));
+        });
+
+        self.update_tick
+    }
+
+    /// Такты старше MAX_CHANGE_AGE прижимаются к этому возрасту, чтобы сравнение с переполнением счетчика оставалось верным
+    fn check_change_ticks(&mut self) {
+        let change_tick = self.change_tick;
+
+        self.archetypes.iter_mut()
+            .flat_map(|archetype| archetype.chunks.iter_mut())
+            .for_each(|archetype_chunk| archetype_chunk.check_change_ticks(change_tick));
+
+        check_tick(&mut self.update_tick, change_tick);
+        check_tick(&mut self.previous_update_tick, change_tick);
+    }
+
+    /// RemovedComponents<T>: сущности, потерявшие компонент (в том числе удаленные) после такта `since_tick`.
+    /// Доступны удаления двух последних обновлений систем
+    pub fn removed_components<TComponent: 'static>(&self, since_tick: u32) -> Vec<EntityId> {
+        self.removed_components.get(&ComponentId::from_type::<TComponent>()).map_or(Vec::new(), |removed_entities| {
+            removed_entities.iter()
+                .filter(|(_, removed_tick)| is_tick_newer(*removed_tick, since_tick))
+                .map(|(entity_id, _)| *entity_id)
+                .collect()
+        })
+    }
+
+    fn record_removed_component(&mut self, component_id: ComponentId, entity_id: EntityId) {
+        self.removed_components.entry(component_id).or_default().push((entity_id, self.change_tick));
+    }
+
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    /// Количество сущностей в одном чанке архетипа. None, если какой-либо компонент не зарегистрирован
+    pub fn chunk_capacity(&self, archetype_type: &ArchetypeType) -> Option<usize> {
+        self.archetype_chunk_capacity(archetype_type, self.chunk_size)
+    }
+
+    /// Вместимость чанка при бюджете `chunk_size` байт
+    fn archetype_chunk_capacity(&self, archetype_type: &ArchetypeType, chunk_size: usize) -> Option<usize> {
+        if archetype_type.check(&self.components_info).is_some() {
+            return None;
+        }
+
+        // каждая колонка чанка выравнивается под свой компонент, в худшем случае теряется align - 1 байт на колонку.
+        // общие компоненты и метки колонок не имеют
+        let (single_entity_size, columns_padding) = archetype_type.iter()
+            .map(|component_id| self.components_info.get(component_id).unwrap())
+            .filter(|component_info| component_info.storage_type == StorageType::Table && !component_info.is_tag)
+            .fold((std::mem::size_of::<EntityId>(), 0), |(single_entity_size, columns_padding), component_info| {
+                (single_entity_size + component_info.size, columns_padding + component_info.align - 1)
+            });
+
+        Some((chunk_size.saturating_sub(columns_padding) / single_entity_size).max(1))
+    }
+
     pub fn register_component<TComponent: Debug + Sync + Send + 'static>(&mut self) -> ComponentId {
-        let component_id = ComponentId::new(TypeId::of::<TComponent>());
-        //self.components_count += 1;
+        // без стабильного идентификатора регистрация не завершается ошибкой
+        self.get_component_builder::<TComponent>().build().unwrap()
+    }
+
+    pub fn component_id_by_stable_id(&self, stable_id: &StableComponentId) -> Option<ComponentId> {
+        self.stable_component_ids.get(stable_id).copied()
+    }
+
+    pub fn stable_component_id(&self, component_id: &ComponentId) -> Option<&StableComponentId> {
+        self.components_info.get(component_id).and_then(|component_info| component_info.stable_id.as_ref())
+    }
+
+    /// Имя вида `#123` в тексте читается как номер, такой идентификатор не пережил бы сохранение в текст
+    pub (crate) fn check_stable_id(&self, stable_id: &StableComponentId) -> RegisterComponentResult<()> {
+        if stable_id.to_string().parse::<StableComponentId>().as_ref() != Ok(stable_id) {
+            return Err(RegisterComponentError::InvalidStableName { stable_id: stable_id.clone() });
+        }
+
+        match self.stable_component_ids.get(stable_id) {
+            Some(component_id) => Err(RegisterComponentError::DuplicateStableId { stable_id: stable_id.clone(), component_id: *component_id }),
+            None => Ok(()),
+        }
+    }
+
+    /// Стабильный идентификатор должен быть проверен через check_stable_id
+    pub (crate) fn insert_component_info(&mut self, component_id: ComponentId, mut component_info: ComponentInfo, stable_id: Option<StableComponentId>) {
+        if let Some(stable_id) = &stable_id {
+            self.stable_component_ids.insert(stable_id.clone(), component_id);
+        }
+
+        component_info.stable_id = stable_id;
 
-        self.components_info.insert(ComponentId::from_type::<TComponent>(), ComponentInfo::new::<TComponent>());
-        component_id
+        self.components_info.insert(component_id, component_info);
+    }
+
+    /// Регистрация компонента с настройками, например со способом хранения
+    pub fn get_component_builder<'a, TComponent: Debug + Sync + Send + 'static>(&'a mut self) -> ComponentBuilder<'a, TComponent> {
+        ComponentBuilder::<'a, TComponent>::new(self)
     }
 
     // pub fn new_entity<'a, const COMPONENTS_COUNT: usize>(&'a mut self, archetype: [ComponentId; COMPONENTS_COUNT]) -> EntityBuilder<'a, COMPONENTS_COUNT> {
@@ -57,52 +396,948 @@ impl EcsDataManager where Self: Sync + Send {
     //     ArchetypeDataAccessorBuilder::<'a>::new(self)
     // }
 
-    pub fn remove_entity(&mut self, entity_id: EntityId) {
-        let archetype_type = self.entity_index.remove(*entity_id).unwrap();
-        self.archetype_map.get_mut(&archetype_type).unwrap().remove_entity(entity_id);
+    pub fn remove_entity(&mut self, entity_id: EntityId) -> EntityResult<()> {
+        if !self.is_alive(entity_id) {
+            return Err(EntityError::NoSuchEntity { entity_id });
+        }
+
+        self.check_entity_unlocked(entity_id)?;
+
+        self.detach_from_hierarchy(entity_id)?;
+
+        // команды хуков могли удалить сущность или переместить сущности ее группы
+        if !self.is_alive(entity_id) {
+            return Ok(());
+        }
+
+        self.check_entity_unlocked(entity_id)?;
+
+        let entity_location = self.entity_index[*entity_id];
+
+        let mut removed_component_ids = self.archetypes[*entity_location.archetype_id].archetype_type().component_ids.clone();
+
+        removed_component_ids.extend(self.sparse_sets.iter()
+            .filter(|(_, sparse_set)| sparse_set.contains(entity_id))
+            .map(|(component_id, _)| *component_id));
+
+        self.run_component_hooks(entity_id, &removed_component_ids, |component_info| component_info.on_remove.as_ref());
+
+        self.entity_index.remove(*entity_id);
+        let (_, shared_components_key) = self.take_entity(entity_location);
+        self.release_shared_values(&shared_components_key);
+
+        self.sparse_sets.values_mut().for_each(|sparse_set| {
+            sparse_set.remove_component(entity_id);
+        });
+
+        removed_component_ids.into_iter().for_each(|component_id| self.record_removed_component(component_id, entity_id));
+
+        // слот освобождается с новым поколением, старые идентификаторы перестают быть валидными
+        let version = &mut self.entity_versions[*entity_id];
+        *version = version.wrapping_add(1);
+
+        self.free_entity_id.push(EntityId { id: entity_id.id, version: *version });
+
+        self.apply_hook_commands();
+
+        Ok(())
+    }
+
+    /// Удаление сущности изменяет ее чанк, последний чанк ее группы, множества с ее компонентами и, если сущность в иерархии,
+    /// Parent и Children соседних сущностей. Удерживаемые ChunkDataAccessor (например, сохраненным системой) колонки не изменяются
+    fn check_entity_unlocked(&self, entity_id: EntityId) -> EntityResult<()> {
+        let entity_location = self.entity_index[*entity_id];
+        let is_in_hierarchy = self.has_component::<Parent>(entity_id) || self.has_component::<Children>(entity_id);
+
+        let locked_component_id = self.archetypes[*entity_location.archetype_id].locked_component_on_remove(entity_location.chunk_index)
+            .or_else(|| self.sparse_sets.iter()
+                .find(|(component_id, sparse_set)| (sparse_set.contains(entity_id) || is_in_hierarchy && is_hierarchy_component(component_id)) && sparse_set.is_shared())
+                .map(|(component_id, _)| *component_id));
+
+        match locked_component_id {
+            Some(component_id) => Err(EntityError::ComponentLocked { entity_id, component_id }),
+            None => Ok(()),
+        }
+    }
+
+    /// Разреженное множество одного из компонентов, которое удерживает ChunkDataAccessor
+    pub (crate) fn locked_sparse_component<'a>(&self, mut component_ids: impl Iterator<Item = &'a ComponentId>) -> Option<ComponentId> {
+        component_ids.find(|component_id| self.sparse_sets.get(*component_id).is_some_and(|sparse_set| sparse_set.is_shared())).copied()
+    }
+
+    /// Удерживаемая колонка или множество, которые изменит добавление сущности: множества ее компонентов и чанк группы `shared_components_key`,
+    /// в который она попадет. Без ключа (группы с такими значениями еще нет) сущность попадает в новый чанк
+    fn locked_component_on_add(&self, archetype_id: ArchetypeId, component_ids: &[ComponentId], shared_components_key: Option<&SharedComponentsKey>) -> Option<ComponentId> {
+        self.locked_sparse_component(component_ids.iter())
+            .or_else(|| self.archetypes[*archetype_id].locked_component_on_add(shared_components_key?))
+    }
+
+    /// Удерживаемая колонка или множество, которые может изменить создание пачки сущностей: множества их компонентов и любой чанк их архетипа
+    pub (crate) fn locked_component_on_batch(&self, archetype_type: &ArchetypeType) -> Option<ComponentId> {
+        self.locked_sparse_component(archetype_type.iter()).or_else(|| {
+            let archetype_id = self.archetype_map.get(&self.table_archetype_type(archetype_type.clone()))?;
+            self.archetypes[**archetype_id].chunks.iter().find_map(ArchetypeChunk::locked_component)
+        })
+    }
+
+    /// Удерживаемая колонка, которую изменит перенос сущности: ее чанк, последний чанк ее группы и чанк группы `shared_components_key`
+    /// целевого архетипа. Без ключа (группы с такими значениями еще нет) сущность попадает в новый чанк
+    fn locked_component_on_move(&self, entity_location: EntityLocation, archetype_id: ArchetypeId, shared_components_key: Option<&SharedComponentsKey>) -> Option<ComponentId> {
+        self.archetypes[*entity_location.archetype_id].locked_component_on_remove(entity_location.chunk_index)
+            .or_else(|| self.archetypes[*archetype_id].locked_component_on_add(shared_components_key?))
+    }
+
+    /// Сущность существует и поколение идентификатора совпадает с текущим поколением слота
+    pub fn is_alive(&self, entity_id: EntityId) -> bool {
+        self.entity_index.contains_key(*entity_id) && self.entity_versions.get(*entity_id) == Some(&entity_id.version)
     }
 
-    // можно упростить если сделать проверку на наличие архетипа. если есть, не делать новое замыкание
     pub fn add_entity(&mut self, components: Vec<Box<dyn Any + Send + Sync>>) -> AddEntityResult<EntityId> {
-        let archetype_type: ArchetypeType = components.iter().map(|component| (**component).type_id().into()).collect::<Vec<ComponentId>>().into();
-        
+        let components_map = components.into_iter().map(|component| (boxed_component_id(component.as_ref()), component)).collect::<HashMap<ComponentId, _>>();
+        let archetype_type: ArchetypeType = components_map.keys().copied().collect::<Vec<_>>().into();
+
+        self.check_components(&archetype_type)?;
+
+        let archetype_id = self.get_or_create_archetype(self.table_archetype_type(archetype_type.clone()))?;
+
+        let shared_components_key = self.find_shared_components_key(&archetype_type, &components_map);
+
+        if let Some(component_id) = self.locked_component_on_add(archetype_id, &archetype_type, shared_components_key.as_ref()) {
+            return Err(AddEntityError::ComponentLocked { component_id });
+        }
+
+        let entity_id = self.new_entity_id();
+
+        self.insert_boxed_entity(entity_id, archetype_id, components_map);
+
+        self.run_added_components_hooks(entity_id, &archetype_type);
+
+        Ok(entity_id)
+    }
+
+    /// Размещение упакованных компонентов новой сущности: разреженные - в свои множества, общие - в значения чанков.
+    /// Удерживаемые ChunkDataAccessor колонки и множества проверяет вызывающий
+    fn insert_boxed_entity(&mut self, entity_id: EntityId, archetype_id: ArchetypeId, components_map: HashMap<ComponentId, Box<dyn Any + Send + Sync>>) {
+        let (sparse_components, components_map): (HashMap<_, _>, HashMap<_, _>) = components_map.into_iter().partition(|(component_id, _)| self.sparse_sets.contains_key(component_id));
+
+        sparse_components.into_iter().for_each(|(component_id, component)| {
+            self.sparse_sets.get_mut(&component_id).unwrap().insert_component(entity_id, component);
+        });
+
+        let mut entity_data = EntityData::new(entity_id, components_map);
+        let shared_components_key = self.extract_shared_values(archetype_id, &mut entity_data);
+
+        self.move_entity(entity_data, &shared_components_key, archetype_id);
+    }
+
+    /// Создание сущности из кортежа компонентов: `spawn((Position, Velocity, Health))`
+    pub fn spawn<TComponents: INewEntityComponentsInfo>(&mut self, components: TComponents) -> AddEntityResult<EntityId> {
+        let archetype_type = TComponents::archetype_type();
+
+        self.check_components(&archetype_type)?;
+
+        let archetype_id = self.get_or_create_archetype(self.table_archetype_type(archetype_type.clone()))?;
+
+        // значения общих компонентов сравниваются через ComponentInfo, для этого компоненты упаковываются
+        if !self.archetypes[*archetype_id].shared_component_ids.is_empty() {
+            return self.add_entity(components.into_boxed_components());
+        }
+
+        if let Some(component_id) = self.locked_component_on_add(archetype_id, &archetype_type, Some(&SharedComponentsKey::new())) {
+            return Err(AddEntityError::ComponentLocked { component_id });
+        }
+
+        let entity_id = self.new_entity_id();
+
+        let entity_location = self.archetypes[*archetype_id].add_components(entity_id, components, &mut self.sparse_sets, self.change_tick);
+
+        self.entity_index.insert(entity_id.id(), entity_location);
+
+        self.run_added_components_hooks(entity_id, &archetype_type);
+
+        Ok(entity_id)
+    }
+
+    /// Создание сущностей одного архетипа из итератора. Архетип определяется один раз, чанки создаются заранее по нижней оценке размера итератора,
+    /// значения записываются в колонки по чанку за раз. Команды хуков применяются после создания всей пачки.
+    ///
+    /// Идентификаторы выделяются подряд из новых слотов: освобожденные слоты не переиспользуются, массив поколений растет на размер каждой пачки.
+    /// При частом создании и удалении сущностей следует использовать `spawn_batch_recycled`
+    pub fn spawn_batch<TComponents: INewEntityComponentsInfo, TIter: IntoIterator<Item = TComponents>>(&mut self, iter: TIter) -> AddEntityResult<EntityIdRange> {
+        let start = self.index_count;
+
+        self.spawn_batch_with(iter, Self::new_index_entity_id)?;
+
+        Ok(EntityIdRange::new(start, self.index_count))
+    }
+
+    /// Как `spawn_batch`, но идентификаторы сначала берутся из освобожденных слотов и не образуют непрерывный диапазон
+    pub fn spawn_batch_recycled<TComponents: INewEntityComponentsInfo, TIter: IntoIterator<Item = TComponents>>(&mut self, iter: TIter) -> AddEntityResult<Vec<EntityId>> {
+        self.spawn_batch_with(iter, Self::new_entity_id)
+    }
+
+    fn spawn_batch_with<TComponents: INewEntityComponentsInfo, TIter: IntoIterator<Item = TComponents>>(&mut self, iter: TIter, new_entity_id: fn(&mut Self) -> EntityId) -> AddEntityResult<Vec<EntityId>> {
+        let archetype_type = TComponents::archetype_type();
+
+        self.check_components(&archetype_type)?;
+
+        let archetype_id = self.get_or_create_archetype(self.table_archetype_type(archetype_type.clone()))?;
+
+        if let Some(component_id) = self.locked_component_on_batch(&archetype_type) {
+            return Err(AddEntityError::ComponentLocked { component_id });
+        }
+
+        let mut iter = iter.into_iter();
+        let (entities_count, _) = iter.size_hint();
+
+        self.entity_versions.reserve(entities_count.saturating_sub(self.free_entity_id.len()));
+
+        // у общих компонентов группа чанков известна только после сравнения значений
+        let entity_ids = if !self.archetypes[*archetype_id].shared_component_ids.is_empty() {
+            iter.map(|components| {
+                let entity_id = new_entity_id(self);
+
+                let components_map = components.into_boxed_components().into_iter().map(|component| (boxed_component_id(component.as_ref()), component)).collect();
+                self.insert_boxed_entity(entity_id, archetype_id, components_map);
+
+                entity_id
+            }).collect::<Vec<_>>()
+        } else {
+            self.spawn_table_batch(archetype_id, entities_count, new_entity_id, |free_rows| iter.by_ref().take(free_rows).collect(), TComponents::set_data_batch)
+        };
+
+        entity_ids.iter().for_each(|entity_id| {
+            self.run_component_hooks(*entity_id, &archetype_type, |component_info| component_info.on_add.as_ref());
+            self.run_component_hooks(*entity_id, &archetype_type, |component_info| component_info.on_insert.as_ref());
+        });
+
+        self.apply_hook_commands();
+
+        Ok(entity_ids)
+    }
+
+    /// Сущности архетипа без общих компонентов, чанки заполняются пачками: `take_items` выдает значения не более чем для заданного
+    /// числа сущностей, `write` записывает их в колонки чанка. Пустая пачка завершает создание. Хуки не вызываются.
+    /// Удерживаемые ChunkDataAccessor колонки и множества проверяет вызывающий
+    pub (crate) fn spawn_table_batch<TItem>(
+        &mut self,
+        archetype_id: ArchetypeId,
+        entities_count: usize,
+        new_entity_id: fn(&mut Self) -> EntityId,
+        mut take_items: impl FnMut(usize) -> Vec<TItem>,
+        mut write: impl FnMut(Vec<TItem>, &mut NewEntitiesBatchWriter),
+    ) -> Vec<EntityId> {
+        self.archetypes[*archetype_id].reserve_chunks(&Default::default(), entities_count);
+
+        let mut entity_ids = Vec::with_capacity(entities_count);
+
+        loop {
+            let items = take_items(self.archetypes[*archetype_id].free_chunk_rows(&Default::default()));
+
+            if items.is_empty() {
+                break;
+            }
+
+            let chunk_entity_ids = (0..items.len()).map(|_| new_entity_id(self)).collect::<Vec<_>>();
+
+            let first_location = self.archetypes[*archetype_id].add_components_batch(&chunk_entity_ids, &mut self.sparse_sets, self.change_tick, |batch_writer| {
+                write(items, batch_writer);
+            });
+
+            chunk_entity_ids.iter().enumerate().for_each(|(position, entity_id)| {
+                self.entity_index.insert(entity_id.id(), EntityLocation::new(archetype_id, first_location.chunk_index(), first_location.row() + position));
+            });
+
+            entity_ids.extend(chunk_entity_ids);
+        }
+
+        entity_ids
+    }
+
+    /// Добавление (или замена) компонента существующей сущности, сущность переносится в архетип с новым компонентом
+    pub fn insert_component<TComponent: Debug + Sync + Send + 'static>(&mut self, entity_id: EntityId, component: TComponent) -> EntityResult<()> {
+        let component_id = ComponentId::from_type::<TComponent>();
+
+        // связи иерархии задаются с обеих сторон через set_parent
+        if is_hierarchy_component(&component_id) {
+            return Err(EntityError::HierarchyComponent { component_id });
+        }
+
+        self.insert_component_unchecked(entity_id, component)
+    }
+
+    /// Запись компонента без проверки компонентов иерархии, для hierarchy.rs
+    pub (crate) fn insert_component_unchecked<TComponent: Debug + Sync + Send + 'static>(&mut self, entity_id: EntityId, component: TComponent) -> EntityResult<()> {
+        let component_id = ComponentId::from_type::<TComponent>();
+
+        if !self.components_info.contains_key(&component_id) {
+            return Err(EntityError::ComponentNotRegistered { component_id });
+        }
+
+        let entity_location = self.entity_location(entity_id).ok_or(EntityError::NoSuchEntity { entity_id })?;
+
+        // компонент вне архетипа, сущность не перемещается
+        if let Some(sparse_set) = self.sparse_sets.get_mut(&component_id) {
+            if sparse_set.is_shared() {
+                return Err(EntityError::ComponentLocked { entity_id, component_id });
+            }
+
+            let is_replaced = unsafe { sparse_set.as_typed_mut::<TComponent>() }.insert(entity_id, component).is_some();
+
+            self.run_inserted_component_hooks(entity_id, component_id, is_replaced);
+
+            return Ok(());
+        }
+
+        self.insert_boxed_component(entity_id, entity_location, component_id, Box::new(component))
+    }
+
+    /// Запись упакованного компонента, хранимого в чанках: в колонке или значением общего компонента
+    fn insert_boxed_component(&mut self, entity_id: EntityId, entity_location: EntityLocation, component_id: ComponentId, component: Box<dyn Any + Send + Sync>) -> EntityResult<()> {
+        let is_replaced = self.archetypes[*entity_location.archetype_id].archetype_type().contains(&component_id);
+        let is_shared = self.components_info[&component_id].storage_type == StorageType::Shared;
+
+        let target_archetype_id = self.archetype_with_component(entity_location.archetype_id, component_id);
+
+        // значение общего компонента меняет ключ чанка, а не данные сущности
+        let previous_shared_components_key = self.archetypes[*entity_location.archetype_id].chunks[entity_location.chunk_index].shared_components_key.clone();
+
+        let target_shared_components_key = match is_shared {
+            true => self.find_shared_value(&component_id, component.as_ref()).map(|value_index| with_shared_value(&previous_shared_components_key, component_id, value_index)),
+            false => Some(previous_shared_components_key.clone()),
+        };
+
+        if let Some(locked_component_id) = self.locked_component_on_move(entity_location, target_archetype_id, target_shared_components_key.as_ref()) {
+            return Err(EntityError::ComponentLocked { entity_id, component_id: locked_component_id });
+        }
+
+        let (mut entity_data, mut shared_components_key) = self.take_entity(entity_location);
+
+        if is_shared {
+            let value_index = self.intern_shared_value(component_id, component);
+
+            shared_components_key = with_shared_value(&shared_components_key, component_id, value_index);
+        } else {
+            // замена значения сохраняет такт добавления
+            if let Some(component_ticks) = entity_data.components_ticks.get_mut(&component_id) {
+                component_ticks.changed = self.change_tick;
+            }
+
+            entity_data.add_component(component_id, component);
+        }
+
+        self.move_entity(entity_data, &shared_components_key, target_archetype_id);
+        self.release_shared_values(&previous_shared_components_key);
+
+        self.run_inserted_component_hooks(entity_id, component_id, is_replaced);
+
+        Ok(())
+    }
+
+    /// Удаление компонента у существующей сущности, сущность переносится в архетип без компонента. Ok(None), если компонента у сущности нет.
+    /// Значение метки не хранится: для нее возвращается `Default::default()`, если метка зарегистрирована с `ComponentBuilder::tag_default`, иначе None
+    /// Удаление Parent отсоединяет сущность от родителя, удаление Children - все дочерние сущности
+    pub fn remove_component<TComponent: Debug + Sync + Send + 'static>(&mut self, entity_id: EntityId) -> EntityResult<Option<TComponent>> {
+        let component_id = ComponentId::from_type::<TComponent>();
+
+        if is_hierarchy_component(&component_id) {
+            return Ok(self.remove_hierarchy_component(entity_id, component_id)?.map(|component| unsafe { *component.downcast_unchecked::<TComponent>() }));
+        }
+
+        self.remove_component_unchecked(entity_id)
+    }
+
+    /// Удаление компонента без обновления другой стороны связи иерархии, для hierarchy.rs
+    pub (crate) fn remove_component_unchecked<TComponent: Debug + Sync + Send + 'static>(&mut self, entity_id: EntityId) -> EntityResult<Option<TComponent>> {
+        let component_id = ComponentId::from_type::<TComponent>();
+
+        if !self.components_info.contains_key(&component_id) {
+            return Err(EntityError::ComponentNotRegistered { component_id });
+        }
+
+        let entity_location = self.entity_location(entity_id).ok_or(EntityError::NoSuchEntity { entity_id })?;
+
+        if let Some(sparse_set) = self.sparse_sets.get(&component_id) {
+            if !sparse_set.contains(entity_id) {
+                return Ok(None);
+            }
+
+            if sparse_set.is_shared() {
+                return Err(EntityError::ComponentLocked { entity_id, component_id });
+            }
+
+            self.run_component_hooks(entity_id, &[component_id], |component_info| component_info.on_remove.as_ref());
+
+            let component = unsafe { self.sparse_sets.get_mut(&component_id).unwrap().as_typed_mut::<TComponent>() }.remove(entity_id);
+
+            self.record_removed_component(component_id, entity_id);
+            self.apply_hook_commands();
+
+            return Ok(component);
+        }
+
+        Ok(self.remove_boxed_component(entity_id, entity_location, component_id)?.map(|component| unsafe { *component.downcast_unchecked::<TComponent>() }))
+    }
+
+    /// Удаление компонента, хранимого в чанках. Значение общего компонента возвращается копией
+    fn remove_boxed_component(&mut self, entity_id: EntityId, entity_location: EntityLocation, component_id: ComponentId) -> EntityResult<Option<Box<dyn Any + Send + Sync>>> {
+        if !self.archetypes[*entity_location.archetype_id].archetype_type().contains(&component_id) {
+            return Ok(None);
+        }
+
+        let target_archetype_id = self.archetype_without_component(entity_location.archetype_id, component_id);
+
+        let previous_shared_components_key = self.archetypes[*entity_location.archetype_id].chunks[entity_location.chunk_index].shared_components_key.clone();
+
+        let shared_components_key = previous_shared_components_key.iter()
+            .filter(|(shared_component_id, _)| *shared_component_id != component_id)
+            .copied()
+            .collect::<SharedComponentsKey>();
+
+        if let Some(locked_component_id) = self.locked_component_on_move(entity_location, target_archetype_id, Some(&shared_components_key)) {
+            return Err(EntityError::ComponentLocked { entity_id, component_id: locked_component_id });
+        }
+
+        self.run_component_hooks(entity_id, &[component_id], |component_info| component_info.on_remove.as_ref());
+
+        let (mut entity_data, _) = self.take_entity(entity_location);
+
+        let component = match previous_shared_components_key.iter().find(|(shared_component_id, _)| *shared_component_id == component_id) {
+            Some((_, value_index)) => {
+                let shared_value_clone = self.components_info[&component_id].shared_value_clone.unwrap();
+
+                Some(shared_value_clone(self.shared_value(&component_id, *value_index)))
+            },
+            // значение метки не хранится, возвращается значение по умолчанию, если оно задано при регистрации
+            None if self.components_info[&component_id].is_tag => self.components_info[&component_id].tag_default.map(|tag_default| tag_default()),
+            None => entity_data.remove_component(&component_id),
+        };
+
+        self.move_entity(entity_data, &shared_components_key, target_archetype_id);
+        self.release_shared_values(&previous_shared_components_key);
+
+        self.record_removed_component(component_id, entity_id);
+        self.apply_hook_commands();
+
+        Ok(component)
+    }
+
+    /// Хуки on_add и on_insert всех компонентов новой сущности
+    fn run_added_components_hooks(&mut self, entity_id: EntityId, component_ids: &[ComponentId]) {
+        self.run_component_hooks(entity_id, component_ids, |component_info| component_info.on_add.as_ref());
+        self.run_component_hooks(entity_id, component_ids, |component_info| component_info.on_insert.as_ref());
+
+        self.apply_hook_commands();
+    }
+
+    /// Хуки записанного компонента, on_add только если компонента у сущности не было
+    fn run_inserted_component_hooks(&mut self, entity_id: EntityId, component_id: ComponentId, is_replaced: bool) {
+        if !is_replaced {
+            self.run_component_hooks(entity_id, &[component_id], |component_info| component_info.on_add.as_ref());
+        }
+
+        self.run_component_hooks(entity_id, &[component_id], |component_info| component_info.on_insert.as_ref());
+
+        self.apply_hook_commands();
+    }
+
+    /// Хуки только читают менеджер и записывают команды, сами команды применяются в apply_hook_commands
+    fn run_component_hooks(&mut self, entity_id: EntityId, component_ids: &[ComponentId], component_hook: fn(&ComponentInfo) -> Option<&Arc<dyn ComponentHookClosure + Sync + Send>>) {
+        let mut hook_commands = std::mem::take(&mut self.hook_commands);
+
+        component_ids.iter()
+            .filter_map(|component_id| self.components_info.get(component_id).and_then(component_hook))
+            .for_each(|hook| hook(entity_id, self, &mut hook_commands));
+
+        self.hook_commands = hook_commands;
+    }
+
+    /// Команды хуков применяются после завершения структурного изменения, когда данные менеджера согласованы
+    fn apply_hook_commands(&mut self) {
+        if self.hook_commands.is_empty() {
+            return;
+        }
+
+        let mut hook_commands = std::mem::take(&mut self.hook_commands);
+        let command_errors = hook_commands.apply(self);
+
+        self.command_errors.extend(command_errors);
+    }
+
+    /// Ошибки команд хуков, накопленные с предыдущего вызова
+    pub fn take_command_errors(&mut self) -> Vec<CommandError> {
+        std::mem::take(&mut self.command_errors)
+    }
+
+    /// Размещение уже извлеченной из архетипа сущности в целевой архетип
+    fn move_entity(&mut self, entity_data: EntityData, shared_components_key: &SharedComponentsKey, archetype_id: ArchetypeId) {
+        let entity_id = entity_data.entity_id;
+
+        let entity_location = self.archetypes[*archetype_id].add_entity(entity_data, shared_components_key, self.change_tick);
+        self.retain_shared_values(shared_components_key);
+
+        self.entity_index.insert(entity_id.id(), entity_location);
+    }
+
+    /// Извлечение сущности из архетипа с обновлением положений сущностей, перемещенных при уплотнении чанков
+    fn take_entity(&mut self, entity_location: EntityLocation) -> (EntityData, SharedComponentsKey) {
+        let (entity_data, shared_components_key, moved_entities) = self.archetypes[*entity_location.archetype_id].remove_entity(entity_location);
+
+        moved_entities.into_iter().for_each(|(moved_entity_id, moved_entity_location)| {
+            self.entity_index.insert(moved_entity_id.id(), moved_entity_location);
+        });
+
+        (entity_data, shared_components_key)
+    }
+
+    /// Архетип после добавления компонента, переход кешируется в графе архетипов
+    fn archetype_with_component(&mut self, archetype_id: ArchetypeId, component_id: ComponentId) -> ArchetypeId {
+        if let Some(target_archetype_id) = self.archetypes[*archetype_id].add_component_edges.get(&component_id) {
+            return *target_archetype_id;
+        }
+
+        let archetype_type = self.archetypes[*archetype_id].archetype_type();
+
+        // замена значения существующего компонента, сущность остается в своем архетипе
+        if archetype_type.contains(&component_id) {
+            return archetype_id;
+        }
+
+        let mut component_ids = archetype_type.component_ids.clone();
+        component_ids.push(component_id);
+
+        // все компоненты уже прошли проверку регистрации
+        let target_archetype_id = self.get_or_create_archetype(component_ids.into()).unwrap();
+
+        self.archetypes[*archetype_id].add_component_edges.insert(component_id, target_archetype_id);
+        self.archetypes[*target_archetype_id].remove_component_edges.insert(component_id, archetype_id);
+
+        target_archetype_id
+    }
+
+    /// Архетип после удаления компонента, переход кешируется в графе архетипов
+    fn archetype_without_component(&mut self, archetype_id: ArchetypeId, component_id: ComponentId) -> ArchetypeId {
+        if let Some(target_archetype_id) = self.archetypes[*archetype_id].remove_component_edges.get(&component_id) {
+            return *target_archetype_id;
+        }
+
+        let component_ids = self.archetypes[*archetype_id].archetype_type().iter()
+            .filter(|x| **x != component_id).copied()
+            .collect::<Vec<_>>();
+
+        let target_archetype_id = self.get_or_create_archetype(component_ids.into()).unwrap();
+
+        self.archetypes[*archetype_id].remove_component_edges.insert(component_id, target_archetype_id);
+        self.archetypes[*target_archetype_id].add_component_edges.insert(component_id, archetype_id);
+
+        target_archetype_id
+    }
+
+    fn get_or_create_archetype(&mut self, archetype_type: ArchetypeType) -> AddEntityResult<ArchetypeId> {
+        if let Some(archetype_id) = self.archetype_map.get(&archetype_type) {
+            return Ok(*archetype_id);
+        }
+
+        let archetype_id = ArchetypeId::new(self.archetypes.len());
+        let archetype = self.build_archetype(archetype_id, archetype_type.clone(), self.chunk_size)?;
+
+        self.archetypes.push(archetype);
+        self.archetype_map.insert(archetype_type, archetype_id);
+
+        Ok(archetype_id)
+    }
+
+    fn new_entity_id(&mut self) -> EntityId {
+        self.free_entity_id.pop().unwrap_or_else(|| self.new_index_entity_id())
+    }
+
+    /// Идентификатор из нового слота, минуя освобожденные
+    fn new_index_entity_id(&mut self) -> EntityId {
+        let new_entity_id = EntityId::new(self.index_count);
+        self.entity_versions.push(new_entity_id.version);
+        self.index_count += 1;
+        new_entity_id
+    }
+
+    /// Положение живой сущности: архетип, чанк и строка в чанке
+    pub fn entity_location(&self, entity_id: EntityId) -> Option<EntityLocation> {
+        if !self.is_alive(entity_id) {
+            return None;
+        }
+
+        self.entity_index.get(*entity_id).copied()
+    }
+
+    fn check_components(&self, archetype_type: &ArchetypeType) -> AddEntityResult<()> {
         if let Some(component_id) = archetype_type.check(&self.components_info) {
             return Err(AddEntityError::ComponentNotRegistered { component_id });
         }
 
-        let entity_id = self.free_entity_id.pop().unwrap_or_else(|| {
-            let new_entity_id = EntityId::new(self.index_count);
-            self.index_count += 1;
-            new_entity_id
-        });
+        // идентификаторы отсортированы, дубликаты всегда соседние
+        if let Some(component_id) = archetype_type.windows(2).find(|pair| pair[0] == pair[1]).map(|pair| pair[0]) {
+            return Err(AddEntityError::ComponentDuplicated { component_id });
+        }
+
+        // связи иерархии задаются с обеих сторон через set_parent
+        if let Some(component_id) = archetype_type.iter().find(|component_id| is_hierarchy_component(component_id)) {
+            return Err(AddEntityError::HierarchyComponent { component_id: *component_id });
+        }
+
+        Ok(())
+    }
+
+    /// Сигнатура архетипа содержит компоненты, хранимые в колонках и общие компоненты чанков
+    fn table_archetype_type(&self, archetype_type: ArchetypeType) -> ArchetypeType {
+        if self.sparse_sets.is_empty() {
+            return archetype_type;
+        }
+
+        archetype_type.component_ids.into_iter()
+            .filter(|component_id| self.components_info[component_id].storage_type != StorageType::SparseSet)
+            .collect::<Vec<_>>()
+            .into()
+    }
+
+    /// Замыкание создания чанка строится один раз, при создании архетипа. Вместимость чанков выводится из бюджета `chunk_size`
+    pub (crate) fn build_archetype(&self, archetype_id: ArchetypeId, archetype_type: ArchetypeType, chunk_size: usize) -> AddEntityResult<Archetype> {
+        self.check_components(&archetype_type)?;
+
+        // для меток и общих компонентов колонки не создаются
+        let components_array_build_closure_collection = archetype_type.iter()
+            .filter(|component_id| !self.components_info[*component_id].is_tag && self.components_info[*component_id].storage_type == StorageType::Table)
+            .map(|component_id| {
+                let components_array_build_closure = self.components_info.get(component_id).unwrap().component_array_fabric_cloure.clone();
+                (*component_id, components_array_build_closure)
+            }).collect::<Vec<(_,_)>>();
 
-        let components_map = components.into_iter().map(|component| ((*component).type_id().into(), component)).collect::<HashMap<_,_>>();
-        let entity_data = EntityData::new(entity_id, components_map);
-        let archetype_type = entity_data.build_archetype_type();
+        let tag_component_ids = archetype_type.iter()
+            .filter(|component_id| self.components_info[*component_id].is_tag)
+            .copied()
+            .collect::<Vec<_>>();
 
-        let components_array_build_closure_collection = archetype_type.iter().map(|component_id| {
-            let components_array_build_closure = self.components_info.get(component_id).unwrap().component_array_fabric_cloure.clone();
-            (*component_id, components_array_build_closure)
-        }).collect::<Vec<(_,_)>>();
+        let shared_component_ids = archetype_type.iter()
+            .filter(|component_id| self.components_info[*component_id].storage_type == StorageType::Shared).copied()
+            .collect::<Vec<_>>();
 
-        let single_entity_size: usize = archetype_type.iter().map(|x| self.components_info.get(x).unwrap().size).sum();
-        let chunk_components_count = single_entity_size / CHUNK_SIZE;
+        let chunk_capacity = self.archetype_chunk_capacity(&archetype_type, chunk_size).unwrap();
+
+        let signature = archetype_type.iter().map(|component_id| self.components_info.get(component_id).unwrap().index).collect::<ComponentsBitSet>();
 
         let build_archetype_chunk_clousre = move || -> ArchetypeChunk {
             let components_array_collection = components_array_build_closure_collection.iter().map(|(component_id, components_array_build_closure)| {
-                (*component_id, (components_array_build_closure)(chunk_components_count))
+                (*component_id, (components_array_build_closure)(chunk_capacity))
             }).collect::<HashMap<_,_>>();
 
-            ArchetypeChunk::new(components_array_collection)
+            ArchetypeChunk::new(components_array_collection, chunk_capacity)
         };
 
-        let archetype = self.archetype_map.entry(archetype_type.clone())
-            .or_insert(Archetype::new(archetype_type.clone(), Box::new(build_archetype_chunk_clousre)));
+        Ok(Archetype::new(archetype_id, archetype_type, signature, Box::new(build_archetype_chunk_clousre), chunk_capacity, tag_component_ids, shared_component_ids))
+    }
+}
 
-        archetype.add_entity(entity_data);
+/// Ключ с новым значением общего компонента, ключ остается отсортированным по компоненту
+fn with_shared_value(shared_components_key: &SharedComponentsKey, component_id: ComponentId, value_index: usize) -> SharedComponentsKey {
+    let mut shared_components_key = shared_components_key.iter()
+        .filter(|(shared_component_id, _)| *shared_component_id != component_id)
+        .copied()
+        .collect::<SharedComponentsKey>();
 
-        self.entity_index.insert(entity_id.id(), archetype_type);
+    shared_components_key.push((component_id, value_index));
+    shared_components_key.sort();
 
-        Ok(entity_id)
+    shared_components_key
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::{any::Any, collections::HashSet, sync::{Arc, Mutex, atomic::{AtomicUsize, Ordering}}};
+
+    use crate::{behavior::query::ArchetypeQuery, types::{EntityId, ComponentId, EntityError}};
+
+    use super::{EcsDataManager, component::storage_type::StorageType, test_fixtures::{TestComponentA, TestComponentB, TestComponentC, TestTickComponent, TestSharedComponent, register_with, register_sparse, register_shared, register_tag}};
+
+    #[test]
+    fn test_archetype_edges_are_cached() {
+        let mut ecs_data_manager = EcsDataManager::new();
+        let component_a_id = ecs_data_manager.register_component::<TestComponentA>();
+        let component_b_id = ecs_data_manager.register_component::<TestComponentB>();
+
+        let entity_a = ecs_data_manager.spawn((TestComponentA {},)).unwrap();
+        let entity_b = ecs_data_manager.spawn((TestComponentA {},)).unwrap();
+        let source_archetype_id = ecs_data_manager.entity_location(entity_a).unwrap().archetype_id();
+
+        ecs_data_manager.insert_component(entity_a, TestComponentB {}).unwrap();
+        let target_archetype_id = ecs_data_manager.entity_location(entity_a).unwrap().archetype_id();
+        let archetypes_count = ecs_data_manager.archetypes.len();
+
+        // переход записывается в обе стороны
+        assert_eq!(ecs_data_manager.archetypes[*source_archetype_id].add_component_edges.get(&component_b_id), Some(&target_archetype_id));
+        assert_eq!(ecs_data_manager.archetypes[*target_archetype_id].remove_component_edges.get(&component_b_id), Some(&source_archetype_id));
+
+        // повторные переходы идут по ребрам, новые архетипы не создаются
+        ecs_data_manager.insert_component(entity_b, TestComponentB {}).unwrap();
+        assert_eq!(ecs_data_manager.entity_location(entity_b).unwrap().archetype_id(), target_archetype_id);
+
+        ecs_data_manager.remove_component::<TestComponentB>(entity_a).unwrap();
+        assert_eq!(ecs_data_manager.entity_location(entity_a).unwrap().archetype_id(), source_archetype_id);
+        assert_eq!(ecs_data_manager.archetypes.len(), archetypes_count);
+
+        ecs_data_manager.remove_component::<TestComponentA>(entity_b).unwrap();
+        let component_b_archetype_id = ecs_data_manager.entity_location(entity_b).unwrap().archetype_id();
+
+        assert_eq!(ecs_data_manager.archetypes[*target_archetype_id].remove_component_edges.get(&component_a_id), Some(&component_b_archetype_id));
+        assert_eq!(ecs_data_manager.archetypes[*component_b_archetype_id].add_component_edges.get(&component_a_id), Some(&target_archetype_id));
+    }
+
+    #[test]
+    fn test_stale_entity_id_rejected_after_reuse() {
+        let mut ecs_data_manager = EcsDataManager::new();
+        ecs_data_manager.register_component::<TestTickComponent>();
+        ecs_data_manager.register_component::<TestComponentA>();
+
+        let stale_id = ecs_data_manager.spawn((TestTickComponent(1),)).unwrap();
+        ecs_data_manager.remove_entity(stale_id).unwrap();
+
+        // слот переиспользуется с новым поколением
+        let entity_id = ecs_data_manager.spawn((TestTickComponent(2),)).unwrap();
+        assert_eq!(entity_id.id(), stale_id.id());
+        assert_ne!(entity_id.version(), stale_id.version());
+
+        assert!(!ecs_data_manager.is_alive(stale_id));
+        assert!(ecs_data_manager.entity_location(stale_id).is_none());
+        assert!(!ecs_data_manager.has_component::<TestTickComponent>(stale_id));
+        assert!(matches!(ecs_data_manager.get::<TestTickComponent>(stale_id), Err(EntityError::NoSuchEntity { .. })));
+        assert!(matches!(ecs_data_manager.get_mut::<TestTickComponent>(stale_id), Err(EntityError::NoSuchEntity { .. })));
+        assert!(matches!(ecs_data_manager.insert_component(stale_id, TestComponentA {}), Err(EntityError::NoSuchEntity { .. })));
+        assert!(matches!(ecs_data_manager.remove_component::<TestTickComponent>(stale_id), Err(EntityError::NoSuchEntity { .. })));
+        assert!(matches!(ecs_data_manager.remove_entity(stale_id), Err(EntityError::NoSuchEntity { .. })));
+
+        // операции со старым идентификатором не затрагивают новую сущность
+        assert_eq!(*ecs_data_manager.get::<TestTickComponent>(entity_id).unwrap(), TestTickComponent(2));
+        assert!(!ecs_data_manager.has_component::<TestComponentA>(entity_id));
+    }
+
+    #[derive(Debug)]
+    struct TestWideComponent {
+        _value: u64,
+    }
+
+    #[derive(Debug)]
+    struct TestByteComponent {
+        _value: u8,
+    }
+
+    #[derive(Debug)]
+    #[repr(align(8))]
+    struct TestAlignedTag;
+
+    #[test]
+    fn test_chunk_capacity() {
+        let capacity_manager = |chunk_size| {
+            let mut ecs_data_manager = EcsDataManager::with_chunk_size(chunk_size);
+            ecs_data_manager.register_component::<TestWideComponent>();
+            ecs_data_manager.register_component::<TestByteComponent>();
+            ecs_data_manager.register_component::<TestAlignedTag>();
+            register_sparse::<TestTickComponent>(&mut ecs_data_manager);
+            ecs_data_manager
+        };
+
+        let archetype_type = vec![ComponentId::from_type::<TestWideComponent>(), ComponentId::from_type::<TestByteComponent>()].into();
+        let single_entity_size = std::mem::size_of::<EntityId>() + 8 + 1;
+
+        // бюджет чанка за вычетом выравнивания колонок делится на размер строки
+        assert_eq!(capacity_manager(256).chunk_capacity(&archetype_type), Some((256 - 7) / single_entity_size));
+
+        // строка больше бюджета, в чанке все равно есть место для одной сущности
+        assert_eq!(capacity_manager(16).chunk_capacity(&archetype_type), Some(1));
+
+        // метки и разреженные компоненты колонок не имеют и места в строке не занимают
+        let archetype_type = vec![ComponentId::from_type::<TestWideComponent>(), ComponentId::from_type::<TestByteComponent>(), ComponentId::from_type::<TestAlignedTag>(), ComponentId::from_type::<TestTickComponent>()].into();
+        assert_eq!(capacity_manager(256).chunk_capacity(&archetype_type), Some((256 - 7) / single_entity_size));
+
+        let archetype_type = vec![ComponentId::from_type::<TestComponentA>()].into();
+        assert_eq!(capacity_manager(256).chunk_capacity(&archetype_type), None);
+    }
+
+    #[test]
+    fn test_archetype_type_order_independent() {
+        let mut ecs_data_manager = EcsDataManager::new();
+        ecs_data_manager.register_component::<TestComponentA>();
+        ecs_data_manager.register_component::<TestComponentB>();
+        register_tag::<TestComponentC>(&mut ecs_data_manager);
+
+        let entity_ab = ecs_data_manager.spawn((TestComponentA {}, TestComponentB {})).unwrap();
+        let entity_ba = ecs_data_manager.spawn((TestComponentB {}, TestComponentA {})).unwrap();
+        let entity_boxed = ecs_data_manager.add_entity(vec![Box::new(TestComponentB {}) as Box<dyn Any + Send + Sync>, Box::new(TestComponentA {})]).unwrap();
+
+        let entity_c = ecs_data_manager.spawn((TestComponentC {}, TestComponentB {})).unwrap();
+        ecs_data_manager.insert_component(entity_c, TestComponentA {}).unwrap();
+        ecs_data_manager.remove_component::<TestComponentC>(entity_c).unwrap();
+
+        let archetype_id = ecs_data_manager.entity_location(entity_ab).unwrap().archetype_id();
+
+        for entity_id in [entity_ba, entity_boxed, entity_c] {
+            assert_eq!(ecs_data_manager.entity_location(entity_id).unwrap().archetype_id(), archetype_id);
+        }
+
+        assert!(ecs_data_manager.spawn((TestComponentA {}, TestComponentA {})).is_err());
+    }
+
+    static DROPPED_TAGS: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Debug)]
+    struct TestDropTag;
+
+    impl Drop for TestDropTag {
+        fn drop(&mut self) {
+            DROPPED_TAGS.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_tag_values_are_not_stored() {
+        let mut ecs_data_manager = EcsDataManager::new();
+        ecs_data_manager.register_component::<TestComponentA>();
+        ecs_data_manager.register_component::<TestDropTag>();
+
+        // значение метки удаляется при добавлении, ровно один раз
+        let entity_id = ecs_data_manager.spawn((TestComponentA {}, TestDropTag)).unwrap();
+        assert_eq!(DROPPED_TAGS.load(Ordering::SeqCst), 1);
+
+        ecs_data_manager.insert_component(entity_id, TestDropTag).unwrap();
+        assert_eq!(DROPPED_TAGS.load(Ordering::SeqCst), 2);
+
+        // ссылка на значение метки не создается
+        assert!(ecs_data_manager.has_component::<TestDropTag>(entity_id));
+        assert!(ecs_data_manager.get::<TestDropTag>(entity_id).is_err());
+
+        // без tag_default значение при удалении не создается
+        assert!(ecs_data_manager.remove_component::<TestDropTag>(entity_id).unwrap().is_none());
+        assert!(!ecs_data_manager.has_component::<TestDropTag>(entity_id));
+        assert_eq!(DROPPED_TAGS.load(Ordering::SeqCst), 2);
+
+        ecs_data_manager.add_entity(vec![Box::new(TestComponentA {}), Box::new(TestDropTag)]).unwrap();
+        assert_eq!(DROPPED_TAGS.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_shared_values_released_with_chunk_group() {
+        let mut ecs_data_manager = EcsDataManager::new();
+        ecs_data_manager.register_component::<TestComponentA>();
+
+        let mut invalid_builder = ecs_data_manager.get_component_builder::<TestSharedComponent>();
+        invalid_builder.storage_type(StorageType::Shared);
+        assert!(invalid_builder.build().is_err());
+
+        let shared_component_id = register_shared::<TestSharedComponent>(&mut ecs_data_manager);
+
+        let shared_values = |ecs_data_manager: &EcsDataManager| ecs_data_manager.shared_values[&shared_component_id].iter()
+            .map(|shared_value| shared_value.as_ref().map(|shared_value| shared_value.downcast_ref::<TestSharedComponent>().unwrap().0))
+            .collect::<Vec<_>>();
+
+        let entity_1 = ecs_data_manager.spawn((TestComponentA {}, TestSharedComponent(1))).unwrap();
+        let entity_2 = ecs_data_manager.spawn((TestComponentA {}, TestSharedComponent(2))).unwrap();
+        let entity_2_copy = ecs_data_manager.spawn((TestSharedComponent(2),)).unwrap();
+
+        // одно значение у сущностей разных архетипов
+        assert_eq!(ecs_data_manager.shared_value_refs[&shared_component_id], vec![1, 2]);
+
+        ecs_data_manager.remove_entity(entity_2).unwrap();
+        assert_eq!(shared_values(&ecs_data_manager), vec![Some(1), Some(2)]);
+        assert_eq!(ecs_data_manager.shared_value_refs[&shared_component_id], vec![1, 1]);
+
+        ecs_data_manager.remove_component::<TestSharedComponent>(entity_2_copy).unwrap();
+        assert_eq!(shared_values(&ecs_data_manager), vec![Some(1), None]);
+        assert_eq!(ecs_data_manager.shared_value_refs[&shared_component_id], vec![1, 0]);
+
+        // освобожденный слот переиспользуется, замена значения освобождает прежнее
+        ecs_data_manager.insert_component(entity_1, TestSharedComponent(3)).unwrap();
+        assert_eq!(shared_values(&ecs_data_manager), vec![None, Some(3)]);
+        assert_eq!(ecs_data_manager.shared_value_refs[&shared_component_id], vec![0, 1]);
+
+        let entity_location = ecs_data_manager.entity_location(entity_1).unwrap();
+        let archetype_chunk = &ecs_data_manager.archetypes[*entity_location.archetype_id].chunks[entity_location.chunk_index];
+        assert_eq!(ecs_data_manager.shared_component::<TestSharedComponent>(archetype_chunk), Some(&TestSharedComponent(3)));
+    }
+
+    #[test]
+    fn test_component_hooks_order() {
+        let mut ecs_data_manager = EcsDataManager::new();
+
+        let hook_calls = Arc::new(Mutex::new(Vec::new()));
+
+        let on_add_calls = hook_calls.clone();
+        let on_insert_calls = hook_calls.clone();
+        let on_remove_calls = hook_calls.clone();
+
+        register_with::<TestTickComponent>(&mut ecs_data_manager, move |component_builder| {
+            component_builder.on_add(move |entity_id, ecs_data_manager, _| on_add_calls.lock().unwrap().push(("add", ecs_data_manager.get::<TestTickComponent>(entity_id).unwrap().0)));
+            component_builder.on_insert(move |entity_id, ecs_data_manager, _| on_insert_calls.lock().unwrap().push(("insert", ecs_data_manager.get::<TestTickComponent>(entity_id).unwrap().0)));
+
+            // значение удаляемого компонента еще доступно хуку
+            component_builder.on_remove(move |entity_id, ecs_data_manager, commands| {
+                on_remove_calls.lock().unwrap().push(("remove", ecs_data_manager.get::<TestTickComponent>(entity_id).unwrap().0));
+                commands.spawn((TestComponentB {},));
+            });
+        });
+
+        ecs_data_manager.register_component::<TestComponentB>();
+
+        let entity_id = ecs_data_manager.spawn((TestTickComponent(1),)).unwrap();
+        ecs_data_manager.insert_component(entity_id, TestTickComponent(2)).unwrap();
+        assert_eq!(ecs_data_manager.remove_component::<TestTickComponent>(entity_id).unwrap(), Some(TestTickComponent(2)));
+
+        ecs_data_manager.insert_component(entity_id, TestTickComponent(3)).unwrap();
+        ecs_data_manager.remove_entity(entity_id).unwrap();
+
+        assert_eq!(*hook_calls.lock().unwrap(), vec![("add", 1), ("insert", 1), ("insert", 2), ("remove", 2), ("add", 3), ("insert", 3), ("remove", 3)]);
+
+        // команды хуков применены после удаления
+        let query = ArchetypeQuery::new(Some(HashSet::from([ComponentId::from_type::<TestComponentB>()])), None, None, None);
+        assert_eq!(ecs_data_manager.query_entities(&query).unwrap().len(), 2);
+        assert!(ecs_data_manager.take_command_errors().is_empty());
+    }
+
+    #[test]
+    fn test_spawn_batch_spans_chunks() {
+        let mut ecs_data_manager = EcsDataManager::with_chunk_size(64);
+        ecs_data_manager.register_component::<TestTickComponent>();
+        ecs_data_manager.register_component::<TestComponentA>();
+        register_sparse::<TestSharedComponent>(&mut ecs_data_manager);
+
+        // разреженный компонент колонки в чанке не занимает
+        let archetype_type = vec![ComponentId::from_type::<TestTickComponent>()].into();
+        let chunk_capacity = ecs_data_manager.chunk_capacity(&archetype_type).unwrap();
+        let entities_count = chunk_capacity * 2 + 3;
+
+        let entity_id_range = ecs_data_manager.spawn_batch((0..entities_count as u32).map(|value| (TestTickComponent(value), TestSharedComponent(value * 2)))).unwrap();
+        assert_eq!(entity_id_range.len(), entities_count);
+
+        let chunk_indexes = entity_id_range.iter().map(|entity_id| ecs_data_manager.entity_location(entity_id).unwrap().chunk_index()).collect::<HashSet<_>>();
+        assert_eq!(chunk_indexes.len(), 3);
+
+        for (value, entity_id) in entity_id_range.iter().enumerate() {
+            assert_eq!(*ecs_data_manager.get::<TestTickComponent>(entity_id).unwrap(), TestTickComponent(value as u32));
+            assert_eq!(*ecs_data_manager.get::<TestSharedComponent>(entity_id).unwrap(), TestSharedComponent(value as u32 * 2));
+        }
+
+        // освобожденные слоты переиспользуются только spawn_batch_recycled
+        let removed_ids = entity_id_range.iter().take(2).collect::<Vec<_>>();
+        removed_ids.iter().for_each(|entity_id| ecs_data_manager.remove_entity(*entity_id).unwrap());
+
+        let new_range = ecs_data_manager.spawn_batch((0..2).map(|value| (TestTickComponent(value),))).unwrap();
+        assert!(removed_ids.iter().all(|entity_id| !new_range.iter().any(|new_id| new_id.id() == entity_id.id())));
+
+        let recycled_ids = ecs_data_manager.spawn_batch_recycled((0..3).map(|value| (TestTickComponent(100 + value), TestComponentA {}))).unwrap();
+        let recycled_slots = recycled_ids.iter().map(|entity_id| entity_id.id()).collect::<HashSet<_>>();
+        assert!(removed_ids.iter().all(|entity_id| recycled_slots.contains(&entity_id.id())));
+
+        for (value, entity_id) in recycled_ids.iter().enumerate() {
+            assert_eq!(*ecs_data_manager.get::<TestTickComponent>(*entity_id).unwrap(), TestTickComponent(100 + value as u32));
+            assert!(ecs_data_manager.has_component::<TestComponentA>(*entity_id));
+        }
+
+        assert!(removed_ids.iter().all(|entity_id| !ecs_data_manager.is_alive(*entity_id)));
     }
 }
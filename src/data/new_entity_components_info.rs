@@ -1,75 +1,143 @@
-// use std::{collections::HashMap, any::Any};
-// use std::fmt::Debug;
-
-// use crate::types::{ComponentId, ArchetypeType};
-
-// use super::archetype::{IComponentsArray, ComponentsArray, ArchetypeChunk, ArchetypeChunkFabricClosure};
-
-// pub struct NewEntityComponentsInfo {
-//     pub components_map: HashMap<ComponentId, Box<dyn Any + Send + Sync>>,
-//     pub archetype_chunk_fabric_closure: Box<dyn ArchetypeChunkFabricClosure + Sync + Send>,
-// }
-
-// pub struct
-
-
-// // impl<T0, T1> Into<ArchetypeInfo> for (T0, T1)
-// // where
-// //     T0: Sync + Send + 'static,
-// //     T1: Sync + Send + 'static,
-// // {
-// //     fn into(self) -> ArchetypeInfo {
-// //         let components_map = HashMap::new();
-// //         components_map.insert(ComponentId::from_type::<T0>(), Box::new(self.0) as Box<dyn Any + Send + Sync>);
-// //         components_map.insert(ComponentId::from_type::<T1>(), Box::new(self.1) as Box<dyn Any + Send + Sync>);
-// //         let archetype_chunk_fabric_closure = Box::new(move || -> _ {
-// //             let archetype_components_map = HashMap::new();
-// //             archetype_components_map.insert(ComponentId::from_type::<T0>(), Box::new(ComponentsArray::<T0>::new()) as Box<dyn IComponentsArray>);
-// //             archetype_components_map.insert(ComponentId::from_type::<T1>(), Box::new(ComponentsArray::<T1>::new()) as Box<dyn IComponentsArray>);
-
-// //             ArchetypeChunk::new(archetype_components_map)
-// //         });
-// //         ArchetypeInfo {
-// //             components_map,
-// //             archetype_chunk_fabric_closure
-// //         }
-// //     }
-// // }
-
-// macro_rules! component_tuple_into_new_entity_components_info {
-//     ( $( $name:ident ),+ ) => {
-//         impl<$($name: Debug + Sync + Send + 'static),+> Into<NewEntityComponentsInfo> for ($($name,)+)
-//         {
-//             fn into(self) -> NewEntityComponentsInfo {
-//                 let mut components_map = HashMap::new();
-//                 let ($($name,)+) = self;
-//                 $(components_map.insert(ComponentId::from_type::<$name>(), Box::new($name) as Box<dyn Any + Send + Sync>);)+
-                
-//                 let archetype_chunk_fabric_closure = Box::new(move || -> _ {
-//                     let mut archetype_components_map = HashMap::new();
-
-//                     $(archetype_components_map.insert(ComponentId::from_type::<$name>(), Box::new(ComponentsArray::<$name>::new()) as Box<dyn IComponentsArray>);)+
-        
-//                     ArchetypeChunk::new(archetype_components_map)
-//                 });
-
-//                 NewEntityComponentsInfo {
-//                     components_map,
-//                     archetype_chunk_fabric_closure
-//                 }
-//             }
-//         }
-//     };
-// }
-
-// component_tuple_into_new_entity_components_info!(T0);
-// component_tuple_into_new_entity_components_info!(T0, T1);
-// component_tuple_into_new_entity_components_info!(T0, T1, T2);
-// component_tuple_into_new_entity_components_info!(T0, T1, T2, T3);
-// component_tuple_into_new_entity_components_info!(T0, T1, T2, T3, T4);
-// component_tuple_into_new_entity_components_info!(T0, T1, T2, T3, T4, T5);
-// component_tuple_into_new_entity_components_info!(T0, T1, T2, T3, T4, T5, T6);
-// component_tuple_into_new_entity_components_info!(T0, T1, T2, T3, T4, T5, T6, T7);
-// component_tuple_into_new_entity_components_info!(T0, T1, T2, T3, T4, T5, T6, T7, T8);
-// component_tuple_into_new_entity_components_info!(T0, T1, T2, T3, T4, T5, T6, T7, T8, T9);
-// component_tuple_into_new_entity_components_info!(T0, T1, T2, T3, T4, T5, T6, T7, T8, T9, T10);
\ No newline at end of file
+use std::{any::Any, fmt::Debug, collections::HashMap};
+
+use crate::types::{ComponentId, ArchetypeType, EntityId};
+
+use super::{archetype::{ArchetypeChunk, ComponentTicks}, component::{sparse_set::IComponentSparseSet, dynamic_component::DynamicComponent}};
+
+/// Набор компонентов новой сущности, известный на этапе компиляции (кортеж компонентов).
+/// Компоненты пишутся напрямую в хранилища (колонки чанка или разреженные множества), без упаковки каждого компонента в Box
+pub trait INewEntityComponentsInfo where Self: Sync + Send + 'static {
+    fn archetype_type() -> ArchetypeType;
+    fn set_data(self, components_writer: &mut NewEntityComponentsWriter);
+    /// Пачка наборов в свободные строки одного чанка: значения каждого компонента записываются в колонку одним вызовом
+    fn set_data_batch(components: Vec<Self>, batch_writer: &mut NewEntitiesBatchWriter) where Self: Sized;
+    /// Упакованные компоненты, для архетипов, которые нельзя заполнить напрямую (с общими компонентами)
+    fn into_boxed_components(self) -> Vec<Box<dyn Any + Send + Sync>>;
+}
+
+/// Распределяет компоненты новой сущности по хранилищам: колонки чанка или разреженные множества
+pub struct NewEntityComponentsWriter<'a> {
+    pub (crate) entity_id: EntityId,
+    pub (crate) archetype_chunk: &'a mut ArchetypeChunk,
+    pub (crate) sparse_sets: &'a mut HashMap<ComponentId, Box<dyn IComponentSparseSet>>,
+    pub (crate) change_tick: u32,
+}
+
+impl<'a> NewEntityComponentsWriter<'a> {
+    pub (crate) fn write<TComponent: Debug + Sync + Send + 'static>(&mut self, component: TComponent) {
+        if let Some(sparse_set) = self.sparse_sets.get_mut(&ComponentId::from_type::<TComponent>()) {
+            unsafe { sparse_set.as_typed_mut::<TComponent>() }.insert(self.entity_id, component);
+            return;
+        }
+
+        // метка хранится только в сигнатуре архетипа, значение удаляется
+        if std::mem::size_of::<TComponent>() == 0 {
+            drop(component);
+            return;
+        }
+
+        self.archetype_chunk.set_component(component, ComponentTicks::new(self.change_tick));
+    }
+}
+
+/// Распределяет значения пачки новых сущностей по хранилищам. Сущности пачки занимают последние строки одного чанка
+pub struct NewEntitiesBatchWriter<'a> {
+    pub (crate) entity_ids: &'a [EntityId],
+    pub (crate) archetype_chunk: &'a mut ArchetypeChunk,
+    pub (crate) sparse_sets: &'a mut HashMap<ComponentId, Box<dyn IComponentSparseSet>>,
+    pub (crate) change_tick: u32,
+}
+
+impl<'a> NewEntitiesBatchWriter<'a> {
+    /// Значения компонента по порядку сущностей пачки
+    pub (crate) fn write<TComponent: Debug + Sync + Send + 'static>(&mut self, components: Vec<TComponent>) {
+        debug_assert_eq!(self.entity_ids.len(), components.len());
+
+        if let Some(sparse_set) = self.sparse_sets.get_mut(&ComponentId::from_type::<TComponent>()) {
+            let sparse_set = unsafe { sparse_set.as_typed_mut::<TComponent>() };
+            self.entity_ids.iter().zip(components).for_each(|(entity_id, component)| _ = sparse_set.insert(*entity_id, component));
+            return;
+        }
+
+        // метка хранится только в сигнатуре архетипа, значения удаляются
+        if std::mem::size_of::<TComponent>() == 0 {
+            drop(components);
+            return;
+        }
+
+        self.archetype_chunk.extend_components(components.into_iter(), ComponentTicks::new(self.change_tick));
+    }
+
+    /// Копия значения для каждой сущности пачки, клоны пишутся сразу в хранилище
+    pub (crate) fn write_cloned<TComponent: Clone + Debug + Sync + Send + 'static>(&mut self, component: &TComponent) {
+        if let Some(sparse_set) = self.sparse_sets.get_mut(&ComponentId::from_type::<TComponent>()) {
+            let sparse_set = unsafe { sparse_set.as_typed_mut::<TComponent>() };
+            self.entity_ids.iter().for_each(|entity_id| _ = sparse_set.insert(*entity_id, component.clone()));
+            return;
+        }
+
+        if std::mem::size_of::<TComponent>() == 0 {
+            return;
+        }
+
+        self.archetype_chunk.extend_components(std::iter::repeat_n(component, self.entity_ids.len()).cloned(), ComponentTicks::new(self.change_tick));
+    }
+
+    /// Копии байтов динамического компонента, колонка хранит значения без типа
+    pub (crate) fn write_dynamic_cloned(&mut self, dynamic_component: &DynamicComponent) {
+        let components_array = self.archetype_chunk.archetype_components_map.get_mut(&dynamic_component.component_id()).unwrap();
+
+        self.entity_ids.iter().for_each(|_| components_array.set_component(Box::new(dynamic_component.copy_bytes()), ComponentTicks::new(self.change_tick)));
+    }
+}
+
+macro_rules! component_tuple_into_new_entity_components_info {
+    ( $( $name:ident $index:tt ),+ ) => {
+        impl<$($name: Debug + Sync + Send + 'static),+> INewEntityComponentsInfo for ($($name,)+)
+        {
+            fn archetype_type() -> ArchetypeType {
+                vec![$(ComponentId::from_type::<$name>(),)+].into()
+            }
+
+            #[allow(non_snake_case)]
+            fn set_data(self, components_writer: &mut NewEntityComponentsWriter) {
+                let ($($name,)+) = self;
+                $(components_writer.write::<$name>($name);)+
+            }
+
+            #[allow(non_snake_case)]
+            fn set_data_batch(components: Vec<Self>, batch_writer: &mut NewEntitiesBatchWriter) {
+                let mut columns = ($(Vec::<$name>::with_capacity(components.len()),)+);
+
+                components.into_iter().for_each(|($($name,)+)| {
+                    $(columns.$index.push($name);)+
+                });
+
+                $(batch_writer.write::<$name>(columns.$index);)+
+            }
+
+            #[allow(non_snake_case)]
+            fn into_boxed_components(self) -> Vec<Box<dyn Any + Send + Sync>> {
+                let ($($name,)+) = self;
+                vec![$(Box::new($name) as Box<dyn Any + Send + Sync>,)+]
+            }
+        }
+    };
+}
+
+component_tuple_into_new_entity_components_info!(T0 0);
+component_tuple_into_new_entity_components_info!(T0 0, T1 1);
+component_tuple_into_new_entity_components_info!(T0 0, T1 1, T2 2);
+component_tuple_into_new_entity_components_info!(T0 0, T1 1, T2 2, T3 3);
+component_tuple_into_new_entity_components_info!(T0 0, T1 1, T2 2, T3 3, T4 4);
+component_tuple_into_new_entity_components_info!(T0 0, T1 1, T2 2, T3 3, T4 4, T5 5);
+component_tuple_into_new_entity_components_info!(T0 0, T1 1, T2 2, T3 3, T4 4, T5 5, T6 6);
+component_tuple_into_new_entity_components_info!(T0 0, T1 1, T2 2, T3 3, T4 4, T5 5, T6 6, T7 7);
+component_tuple_into_new_entity_components_info!(T0 0, T1 1, T2 2, T3 3, T4 4, T5 5, T6 6, T7 7, T8 8);
+component_tuple_into_new_entity_components_info!(T0 0, T1 1, T2 2, T3 3, T4 4, T5 5, T6 6, T7 7, T8 8, T9 9);
+component_tuple_into_new_entity_components_info!(T0 0, T1 1, T2 2, T3 3, T4 4, T5 5, T6 6, T7 7, T8 8, T9 9, T10 10);
+component_tuple_into_new_entity_components_info!(T0 0, T1 1, T2 2, T3 3, T4 4, T5 5, T6 6, T7 7, T8 8, T9 9, T10 10, T11 11);
+component_tuple_into_new_entity_components_info!(T0 0, T1 1, T2 2, T3 3, T4 4, T5 5, T6 6, T7 7, T8 8, T9 9, T10 10, T11 11, T12 12);
+component_tuple_into_new_entity_components_info!(T0 0, T1 1, T2 2, T3 3, T4 4, T5 5, T6 6, T7 7, T8 8, T9 9, T10 10, T11 11, T12 12, T13 13);
+component_tuple_into_new_entity_components_info!(T0 0, T1 1, T2 2, T3 3, T4 4, T5 5, T6 6, T7 7, T8 8, T9 9, T10 10, T11 11, T12 12, T13 13, T14 14);
+component_tuple_into_new_entity_components_info!(T0 0, T1 1, T2 2, T3 3, T4 4, T5 5, T6 6, T7 7, T8 8, T9 9, T10 10, T11 11, T12 12, T13 13, T14 14, T15 15);
@@ -0,0 +1,270 @@
+use std::{any::Any, collections::HashMap, fmt::Debug};
+
+use crate::types::{ComponentId, ArchetypeType, AddEntityResult, AddEntityError, EntityIdRange};
+
+use super::{EcsDataManager, new_entity_components_info::{INewEntityComponentsInfo, NewEntitiesBatchWriter}, component::dynamic_component::{DynamicComponent, boxed_component_id}, hierarchy::{Parent, Children}};
+
+// значения компонентов сущности по идентификаторам
+type ComponentsMap = HashMap<ComponentId, Box<dyn Any + Sync + Send>>;
+
+/// Шаблон сущности: значения компонентов и дочерние шаблоны. Компоненты шаблона должны клонироваться
+/// (`ComponentBuilder::cloneable`), кроме меток: их значения не хранятся
+#[derive(Debug, Default)]
+pub struct Prefab {
+    components: HashMap<ComponentId, Box<dyn Any + Sync + Send>>,
+    children: Vec<Prefab>,
+}
+
+impl Prefab {
+    pub fn new() -> Self {
+        Self { ..Default::default() }
+    }
+
+    /// Значение уже добавленного компонента заменяется
+    pub fn add_component<TComponent: Debug + Sync + Send + 'static>(&mut self, component: TComponent) -> &mut Self {
+        self.components.insert(ComponentId::from_type::<TComponent>(), Box::new(component));
+        self
+    }
+
+    pub fn add_dynamic_component(&mut self, dynamic_component: DynamicComponent) -> &mut Self {
+        self.components.insert(dynamic_component.component_id(), Box::new(dynamic_component));
+        self
+    }
+
+    /// Дочерний шаблон: у каждого экземпляра шаблона создается своя дочерняя сущность
+    pub fn add_child(&mut self, child: Prefab) -> &mut Self {
+        self.children.push(child);
+        self
+    }
+
+    pub fn children(&self) -> &[Prefab] {
+        &self.children
+    }
+
+    pub fn archetype_type(&self) -> ArchetypeType {
+        self.components.keys().copied().collect::<Vec<_>>().into()
+    }
+}
+
+impl EcsDataManager {
+    /// `count` экземпляров шаблона, возвращает идентификаторы корневых сущностей
+    pub fn instantiate_prefab(&mut self, prefab: &Prefab, count: usize) -> AddEntityResult<EntityIdRange> {
+        self.instantiate_prefab_overrides(prefab, &[], vec![(); count], |_, _| {}, |_| HashMap::new())
+    }
+
+    /// Экземпляр шаблона на каждый элемент итератора. Компоненты элемента заменяют значения шаблона в корневой сущности
+    /// или добавляются к ним, все корневые сущности попадают в один архетип
+    pub fn instantiate_prefab_with<TComponents: INewEntityComponentsInfo, TIter: IntoIterator<Item = TComponents>>(&mut self, prefab: &Prefab, overrides: TIter) -> AddEntityResult<EntityIdRange> {
+        let override_ids = TComponents::archetype_type();
+
+        self.instantiate_prefab_overrides(prefab, &override_ids, overrides.into_iter().collect(), TComponents::set_data_batch, |components| {
+            components.into_boxed_components().into_iter().map(|component| (boxed_component_id(component.as_ref()), component)).collect()
+        })
+    }
+
+    /// Шаблон и переопределения проверяются целиком до создания первой сущности. Каждый уровень шаблона создается пачкой,
+    /// как в spawn_batch: архетип определяется один раз, идентификаторы выделяются подряд, хуки вызываются после создания всех сущностей.
+    /// `write_overrides` записывает переопределения в колонки чанка, `box_overrides` упаковывает их для архетипа с общими компонентами
+    fn instantiate_prefab_overrides<TOverrides>(
+        &mut self,
+        prefab: &Prefab,
+        override_ids: &[ComponentId],
+        overrides: Vec<TOverrides>,
+        write_overrides: fn(Vec<TOverrides>, &mut NewEntitiesBatchWriter),
+        box_overrides: fn(TOverrides) -> ComponentsMap
+    ) -> AddEntityResult<EntityIdRange> {
+        self.check_components(&override_ids.to_vec().into())?;
+        self.check_prefab(prefab, override_ids)?;
+
+        let mut created_entities = Vec::new();
+
+        let entity_id_range = self.spawn_prefab_batch(prefab, override_ids, overrides, write_overrides, box_overrides, &mut created_entities)?;
+
+        self.spawn_prefab_children(prefab, entity_id_range, &mut created_entities)?;
+
+        created_entities.iter().for_each(|(entity_id_range, archetype_type)| {
+            entity_id_range.iter().for_each(|entity_id| {
+                self.run_component_hooks(entity_id, archetype_type, |component_info| component_info.on_add.as_ref());
+                self.run_component_hooks(entity_id, archetype_type, |component_info| component_info.on_insert.as_ref());
+            });
+        });
+
+        self.apply_hook_commands();
+
+        Ok(entity_id_range)
+    }
+
+    fn check_prefab(&self, prefab: &Prefab, override_ids: &[ComponentId]) -> AddEntityResult<()> {
+        for component_id in prefab.components.keys() {
+            let component_info = self.components_info.get(component_id).ok_or(AddEntityError::ComponentNotRegistered { component_id: *component_id })?;
+
+            // переопределенное значение не клонируется
+            if !override_ids.contains(component_id) && !component_info.is_tag && component_info.clone.is_none() {
+                return Err(AddEntityError::ComponentNotCloneable { component_id: *component_id });
+            }
+        }
+
+        let archetype_type = prefab_archetype_type(prefab, override_ids);
+
+        self.check_components(&archetype_type)?;
+
+        // дочерние сущности привязываются к родителю через set_parent
+        let hierarchy_component_ids = match prefab.children.is_empty() {
+            true => Vec::new(),
+            false => vec![ComponentId::from_type::<Parent>(), ComponentId::from_type::<Children>()],
+        };
+
+        if let Some(component_id) = self.locked_component_on_batch(&archetype_type).or_else(|| self.locked_sparse_component(hierarchy_component_ids.iter())) {
+            return Err(AddEntityError::ComponentLocked { component_id });
+        }
+
+        prefab.children.iter().try_for_each(|child| self.check_prefab(child, &[]))
+    }
+
+    /// Сущности одного уровня шаблона, по одной на каждый набор переопределений. Значения шаблона клонируются сразу в колонки чанков, переопределенные компоненты не клонируются
+    fn spawn_prefab_batch<TOverrides>(
+        &mut self,
+        prefab: &Prefab,
+        override_ids: &[ComponentId],
+        overrides: Vec<TOverrides>,
+        write_overrides: fn(Vec<TOverrides>, &mut NewEntitiesBatchWriter),
+        box_overrides: fn(TOverrides) -> ComponentsMap,
+        created_entities: &mut Vec<(EntityIdRange, ArchetypeType)>
+    ) -> AddEntityResult<EntityIdRange> {
+        let archetype_type = prefab_archetype_type(prefab, override_ids);
+        let archetype_id = self.get_or_create_archetype(self.table_archetype_type(archetype_type.clone()))?;
+
+        self.entity_versions.reserve(overrides.len());
+
+        let start = self.index_count;
+
+        // метки есть только в сигнатуре архетипа, их значения не создаются
+        let cloned_components = prefab.components.iter()
+            .filter(|(component_id, _)| !override_ids.contains(component_id) && !self.components_info[*component_id].is_tag)
+            .map(|(component_id, component)| (*component_id, component.as_ref()))
+            .collect::<Vec<_>>();
+
+        // у общих компонентов группа чанков известна только после сравнения значений
+        if !self.archetypes[*archetype_id].shared_component_ids.is_empty() {
+            for overrides in overrides {
+                let mut components_map = box_overrides(overrides);
+
+                cloned_components.iter().for_each(|(component_id, component)| {
+                    components_map.insert(*component_id, (self.components_info[component_id].clone.unwrap())(*component));
+                });
+
+                let entity_id = self.new_index_entity_id();
+
+                self.insert_boxed_entity(entity_id, archetype_id, components_map);
+            }
+        } else {
+            let clone_into_fns = cloned_components.iter()
+                .map(|(component_id, component)| (self.components_info[component_id].clone_into.unwrap(), *component))
+                .collect::<Vec<_>>();
+
+            let entities_count = overrides.len();
+            let mut overrides = overrides.into_iter();
+
+            self.spawn_table_batch(archetype_id, entities_count, Self::new_index_entity_id, |free_rows| overrides.by_ref().take(free_rows).collect(), |overrides, batch_writer| {
+                write_overrides(overrides, batch_writer);
+                clone_into_fns.iter().for_each(|(clone_into, component)| clone_into(*component, batch_writer));
+            });
+        }
+
+        let entity_id_range = EntityIdRange::new(start, self.index_count);
+
+        created_entities.push((entity_id_range, archetype_type));
+
+        Ok(entity_id_range)
+    }
+
+    /// Дочерние сущности создаются пачкой на каждый дочерний шаблон и привязываются к экземплярам родителя по порядку
+    fn spawn_prefab_children(&mut self, prefab: &Prefab, parent_id_range: EntityIdRange, created_entities: &mut Vec<(EntityIdRange, ArchetypeType)>) -> AddEntityResult<()> {
+        for child in prefab.children.iter() {
+            let child_id_range = self.spawn_prefab_batch(child, &[], vec![(); parent_id_range.len()], |_, _| {}, |_| HashMap::new(), created_entities)?;
+
+            for (child_id, parent_id) in child_id_range.iter().zip(parent_id_range.iter()) {
+                self.set_parent(child_id, parent_id)?;
+            }
+
+            self.spawn_prefab_children(child, child_id_range, created_entities)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn prefab_archetype_type(prefab: &Prefab, override_ids: &[ComponentId]) -> ArchetypeType {
+    let mut component_ids = prefab.components.keys().copied().collect::<Vec<_>>();
+    component_ids.extend(override_ids.iter().filter(|component_id| !prefab.components.contains_key(component_id)));
+    component_ids.into()
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use crate::types::{ComponentId, AddEntityError};
+
+    use super::{Prefab, super::{EcsDataManager, component::{storage_type::StorageType, dynamic_component::DynamicComponentDescriptor}, test_fixtures::{TestComponentC, TestTickComponent, TestSharedComponent, TestNameComponent, register_with, register_tag}}};
+
+    #[test]
+    fn test_prefab_spans_chunks() {
+        let mut ecs_data_manager = EcsDataManager::with_chunk_size(256);
+        ecs_data_manager.register_component::<TestTickComponent>();
+        register_tag::<TestComponentC>(&mut ecs_data_manager);
+
+        register_with::<TestSharedComponent>(&mut ecs_data_manager, |component_builder| {
+            component_builder.cloneable();
+        });
+
+        register_with::<TestNameComponent>(&mut ecs_data_manager, |component_builder| {
+            component_builder.storage_type(StorageType::SparseSet).cloneable();
+        });
+
+        let component_id = ecs_data_manager.register_dynamic_component(DynamicComponentDescriptor::new("prefab_bytes", 4, 4)).unwrap();
+        let dynamic_component = unsafe { ecs_data_manager.new_dynamic_component(component_id, &7u32.to_ne_bytes()) }.unwrap();
+
+        let mut child = Prefab::new();
+        child.add_component(TestNameComponent("child".to_string())).add_component(TestSharedComponent(5));
+
+        let mut prefab = Prefab::new();
+        prefab.add_component(TestSharedComponent(1))
+            .add_component(TestNameComponent("parent".to_string()))
+            .add_component(TestComponentC {})
+            .add_dynamic_component(dynamic_component)
+            .add_component(TestTickComponent(0))
+            .add_child(child);
+
+        // у шаблона с нескопируемым компонентом ничего не создается
+        assert!(matches!(ecs_data_manager.instantiate_prefab(&prefab, 1), Err(AddEntityError::ComponentNotCloneable { .. })));
+        assert_eq!(ecs_data_manager.index_count, 0);
+
+        let archetype_type = vec![ComponentId::from_type::<TestTickComponent>(), ComponentId::from_type::<TestSharedComponent>(), component_id].into();
+        let chunk_capacity = ecs_data_manager.chunk_capacity(&archetype_type).unwrap();
+        let entities_count = chunk_capacity * 2 + 3;
+
+        // переопределенный компонент шаблона не клонируется
+        let entity_id_range = ecs_data_manager.instantiate_prefab_with(&prefab, (0..entities_count as u32).map(|value| (TestTickComponent(value),))).unwrap();
+        assert_eq!(entity_id_range.len(), entities_count);
+
+        let chunk_indexes = entity_id_range.iter().map(|entity_id| ecs_data_manager.entity_location(entity_id).unwrap().chunk_index()).collect::<HashSet<_>>();
+        assert_eq!(chunk_indexes.len(), 3);
+
+        for (value, entity_id) in entity_id_range.iter().enumerate() {
+            assert_eq!(*ecs_data_manager.get::<TestTickComponent>(entity_id).unwrap(), TestTickComponent(value as u32));
+            assert_eq!(*ecs_data_manager.get::<TestSharedComponent>(entity_id).unwrap(), TestSharedComponent(1));
+            assert_eq!(*ecs_data_manager.get::<TestNameComponent>(entity_id).unwrap(), TestNameComponent("parent".to_string()));
+            assert_eq!(&*ecs_data_manager.get_dynamic(entity_id, component_id).unwrap(), &7u32.to_ne_bytes());
+            assert!(ecs_data_manager.has_component::<TestComponentC>(entity_id));
+
+            let children = ecs_data_manager.children(entity_id).unwrap().to_vec();
+            assert_eq!(children.len(), 1);
+            assert_eq!(ecs_data_manager.parent(children[0]).unwrap(), Some(entity_id));
+            assert_eq!(*ecs_data_manager.get::<TestNameComponent>(children[0]).unwrap(), TestNameComponent("child".to_string()));
+            assert_eq!(*ecs_data_manager.get::<TestSharedComponent>(children[0]).unwrap(), TestSharedComponent(5));
+        }
+
+        assert_eq!(ecs_data_manager.index_count, entities_count * 2);
+    }
+}
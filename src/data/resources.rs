@@ -0,0 +1,61 @@
+use std::{any::{Any, TypeId, type_name}, sync::Arc};
+
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::types::{ResourceResult, ResourceError};
+
+use super::EcsDataManager;
+
+/// Ресурс хранится под собственной блокировкой, чтобы системы могли обращаться к нему во время обновления
+pub (crate) type ResourceLock = Arc<dyn Any + Sync + Send>;
+
+impl EcsDataManager {
+    /// Добавление ресурса, возвращает замененное значение
+    pub fn insert_resource<TResource: Sync + Send + 'static>(&mut self, resource: TResource) -> Option<TResource> {
+        self.resources.insert(TypeId::of::<TResource>(), Arc::new(RwLock::new(resource)))
+            .and_then(|resource_lock| Self::unwrap_resource(resource_lock))
+    }
+
+    /// Удаление ресурса. Если ресурс еще удерживается системой, значение не возвращается
+    pub fn remove_resource<TResource: Sync + Send + 'static>(&mut self) -> Option<TResource> {
+        self.resources.remove(&TypeId::of::<TResource>())
+            .and_then(|resource_lock| Self::unwrap_resource(resource_lock))
+    }
+
+    pub fn contains_resource<TResource: 'static>(&self) -> bool {
+        self.resources.contains_key(&TypeId::of::<TResource>())
+    }
+
+    pub fn resource<TResource: Sync + Send + 'static>(&self) -> ResourceResult<RwLockReadGuard<'_, TResource>> {
+        self.typed_resource_lock::<TResource>()?.try_read().map_err(|_| ResourceError::ResourceLocked {
+            resource_name: type_name::<TResource>().to_string(),
+            resource_type_id: TypeId::of::<TResource>(),
+        })
+    }
+
+    pub fn resource_mut<TResource: Sync + Send + 'static>(&mut self) -> ResourceResult<RwLockWriteGuard<'_, TResource>> {
+        self.typed_resource_lock::<TResource>()?.try_write().map_err(|_| ResourceError::ResourceLocked {
+            resource_name: type_name::<TResource>().to_string(),
+            resource_type_id: TypeId::of::<TResource>(),
+        })
+    }
+
+    pub (crate) fn resource_lock(&self, resource_type_id: &TypeId) -> Option<ResourceLock> {
+        self.resources.get(resource_type_id).cloned()
+    }
+
+    fn typed_resource_lock<TResource: Sync + Send + 'static>(&self) -> ResourceResult<&RwLock<TResource>> {
+        let resource_lock = self.resources.get(&TypeId::of::<TResource>()).ok_or_else(|| ResourceError::NoSuchResource {
+            resource_name: type_name::<TResource>().to_string(),
+            resource_type_id: TypeId::of::<TResource>(),
+        })?;
+
+        Ok(unsafe { &*(resource_lock.as_ref() as *const (dyn Any + Sync + Send) as *const RwLock<TResource>) })
+    }
+
+    fn unwrap_resource<TResource: Sync + Send + 'static>(resource_lock: ResourceLock) -> Option<TResource> {
+        let resource_lock = unsafe { resource_lock.downcast_unchecked::<RwLock<TResource>>() };
+
+        Arc::try_unwrap(resource_lock).ok().map(|resource_lock| resource_lock.into_inner())
+    }
+}
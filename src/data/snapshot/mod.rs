@@ -0,0 +1,769 @@
+pub mod snapshot_io;
+
+use std::{any::Any, collections::{HashMap, HashSet}, path::Path, sync::atomic::Ordering};
+
+use vec_map::VecMap;
+
+use crate::types::{ComponentId, EntityId, EntityLocation, ArchetypeType, ArchetypeId, StableComponentId, SnapshotResult, SnapshotError};
+
+use self::snapshot_io::{ISnapshotComponent, SnapshotWriter, SnapshotReader};
+
+use super::{
+    EcsDataManager,
+    archetype::{Archetype, ComponentTicks, SharedComponentsKey},
+    hierarchy::{Parent, Children, check_hierarchy_links},
+    entity_data::EntityData,
+    component::{component_info::ComponentInfo, storage_type::StorageType, dynamic_component::DynamicComponent},
+};
+
+const SNAPSHOT_MAGIC: &[u8; 4] = b"AECS";
+const SNAPSHOT_VERSION: u32 = 1;
+
+/// Метки пишутся без значений, остальным компонентам нужны хуки записи и чтения
+fn is_snapshot_component(component_info: &ComponentInfo) -> bool {
+    component_info.is_tag || component_info.snapshot_write.is_some() && (component_info.snapshot_read.is_some() || component_info.dynamic_descriptor.is_some())
+}
+
+/// Компонент по его номеру в таблице компонентов снимка
+fn read_snapshot_component_id(reader: &mut SnapshotReader, component_ids: &[ComponentId]) -> SnapshotResult<ComponentId> {
+    let position = reader.position();
+    let component_index = reader.read_usize()?;
+
+    component_ids.get(component_index).copied().ok_or(SnapshotError::InvalidData { position, message: format!("invalid component index: {component_index}") })
+}
+
+/// Снимок сцены: архетипы, чанки, идентификаторы сущностей и значения компонентов.
+///
+/// Формат: заголовок, таблица компонентов (стабильный идентификатор, способ хранения), состояние идентификаторов сущностей,
+/// значения общих компонентов, архетипы с чанками и группами чанков, разреженные множества.
+/// Компоненты в снимке указываются номером в таблице компонентов, поэтому снимок не зависит от сборки.
+/// Ресурсы, события и журнал удаленных компонентов в снимок не входят
+impl EcsDataManager {
+    pub fn save_snapshot(&self) -> SnapshotResult<Vec<u8>> {
+        let mut writer = SnapshotWriter::new();
+
+        writer.write_raw(SNAPSHOT_MAGIC);
+        writer.write_u32(SNAPSHOT_VERSION);
+
+        let component_ids = self.snapshot_component_ids()?;
+        let component_indexes = component_ids.iter().enumerate().map(|(component_index, component_id)| (*component_id, component_index)).collect::<HashMap<_, _>>();
+
+        writer.write_usize(component_ids.len());
+
+        component_ids.iter().for_each(|component_id| {
+            let component_info = &self.components_info[component_id];
+
+            component_info.stable_id.as_ref().unwrap().write_snapshot(&mut writer);
+            component_info.storage_type.write_snapshot(&mut writer);
+            writer.write_bool(component_info.is_tag);
+        });
+
+        writer.write_usize(self.chunk_size);
+        writer.write_u32(self.change_tick);
+
+        self.entity_versions.write_snapshot(&mut writer);
+        self.free_entity_id.write_snapshot(&mut writer);
+
+        let shared_component_ids = component_ids.iter().filter(|component_id| self.shared_values.contains_key(component_id)).collect::<Vec<_>>();
+
+        writer.write_usize(shared_component_ids.len());
+
+        shared_component_ids.into_iter().for_each(|component_id| {
+            let snapshot_write = self.components_info[component_id].snapshot_write.unwrap();
+            let shared_values = &self.shared_values[component_id];
+
+            writer.write_usize(component_indexes[component_id]);
+            writer.write_usize(shared_values.len());
+            // освобожденный слот записывается признаком, чтобы индексы значений в ключах чанков не сдвигались
+            shared_values.iter().for_each(|shared_value| {
+                writer.write_bool(shared_value.is_some());
+                shared_value.iter().for_each(|shared_value| snapshot_write(shared_value.as_ref(), &mut writer));
+            });
+        });
+
+        writer.write_usize(self.archetypes.len());
+
+        for archetype in self.archetypes.iter() {
+            writer.write_usize(archetype.archetype_type.len());
+            archetype.archetype_type.iter().for_each(|component_id| writer.write_usize(component_indexes[component_id]));
+
+            writer.write_usize(archetype.chunks.len());
+
+            for archetype_chunk in archetype.chunks.iter() {
+                writer.write_usize(archetype_chunk.shared_components_key.len());
+                archetype_chunk.shared_components_key.iter().for_each(|(component_id, value_index)| {
+                    writer.write_usize(component_indexes[component_id]);
+                    writer.write_usize(*value_index);
+                });
+
+                archetype_chunk.entity_ids.write_snapshot(&mut writer);
+
+                // колонки в порядке таблицы компонентов, чтобы одинаковое состояние давало одинаковый снимок
+                let mut columns = archetype_chunk.archetype_components_map.iter().collect::<Vec<_>>();
+                columns.sort_by_key(|(component_id, _)| component_indexes[component_id]);
+
+                writer.write_usize(columns.len());
+
+                for (component_id, components_array) in columns {
+                    let snapshot_write = self.components_info[component_id].snapshot_write.unwrap();
+
+                    writer.write_usize(component_indexes[component_id]);
+                    writer.write_u32(components_array.change_tick());
+
+                    let components_ticks = components_array.get_components_ticks();
+                    let components_ticks = components_ticks.try_read().map_err(|_| SnapshotError::ComponentLocked { component_id: *component_id })?;
+
+                    components_ticks.iter().for_each(|component_ticks| {
+                        writer.write_u32(component_ticks.added);
+                        writer.write_u32(component_ticks.changed);
+                    });
+
+                    components_array.for_each_component(&mut |component| snapshot_write(component, &mut writer))
+                        .map_err(|_| SnapshotError::ComponentLocked { component_id: *component_id })?;
+                }
+            }
+
+            let mut chunk_groups = archetype.chunk_groups.values().collect::<Vec<_>>();
+            chunk_groups.sort_by_key(|chunk_group| chunk_group.first().copied());
+
+            writer.write_usize(chunk_groups.len());
+            chunk_groups.into_iter().for_each(|chunk_group| chunk_group.write_snapshot(&mut writer));
+        }
+
+        let sparse_component_ids = component_ids.iter().filter(|component_id| self.sparse_sets.contains_key(component_id)).collect::<Vec<_>>();
+
+        writer.write_usize(sparse_component_ids.len());
+
+        for component_id in sparse_component_ids {
+            let snapshot_write = self.components_info[component_id].snapshot_write.unwrap();
+            let sparse_set = &self.sparse_sets[component_id];
+
+            writer.write_usize(component_indexes[component_id]);
+            writer.write_usize(sparse_set.len());
+
+            sparse_set.for_each_component(&mut |entity_id, component| {
+                writer.write_entity_id(entity_id);
+                snapshot_write(component, &mut writer);
+            }).map_err(|_| SnapshotError::ComponentLocked { component_id: *component_id })?;
+        }
+
+        Ok(writer.into_bytes())
+    }
+
+    pub fn save_snapshot_file(&self, path: impl AsRef<Path>) -> SnapshotResult<()> {
+        std::fs::write(path, self.save_snapshot()?)?;
+        Ok(())
+    }
+
+    /// Компоненты, которые встречаются в архетипах и непустых разреженных множествах, в порядке регистрации.
+    /// Каждому нужен стабильный идентификатор, а компоненту со значением - хуки снимка
+    fn snapshot_component_ids(&self) -> SnapshotResult<Vec<ComponentId>> {
+        let mut component_ids = self.archetypes.iter()
+            .flat_map(|archetype| archetype.archetype_type.iter().copied())
+            .chain(self.sparse_sets.iter().filter(|(_, sparse_set)| sparse_set.len() != 0).map(|(component_id, _)| *component_id))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+
+        component_ids.sort_by_key(|component_id| self.components_info[component_id].index);
+
+        for component_id in component_ids.iter() {
+            let component_info = &self.components_info[component_id];
+
+            if component_info.stable_id.is_none() {
+                return Err(SnapshotError::MissingStableId { component_id: *component_id, component_name: component_info.name.clone() });
+            }
+
+            if !is_snapshot_component(component_info) {
+                return Err(SnapshotError::ComponentNotSerializable { component_id: *component_id, component_name: component_info.name.clone() });
+            }
+        }
+
+        Ok(component_ids)
+    }
+
+    /// Загрузка снимка в менеджер без сущностей, компоненты снимка должны быть зарегистрированы с теми же стабильными идентификаторами.
+    /// Хуки компонентов не вызываются. Снимок читается и проверяется целиком, при ошибке менеджер не изменяется.
+    /// Архетипы, созданные до загрузки, пересобираются под бюджет чанка снимка
+    pub fn load_snapshot(&mut self, bytes: &[u8]) -> SnapshotResult<()> {
+        if self.index_count != 0 {
+            return Err(SnapshotError::NotEmpty);
+        }
+
+        let mut reader = SnapshotReader::new(bytes);
+
+        if reader.read_raw(SNAPSHOT_MAGIC.len()).ok() != Some(SNAPSHOT_MAGIC.as_slice()) {
+            return Err(SnapshotError::InvalidHeader);
+        }
+
+        let version = reader.read_u32()?;
+
+        if version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion { version });
+        }
+
+        let components_count = reader.read_len()?;
+        let component_ids = (0..components_count).map(|_| self.read_snapshot_component(&mut reader)).collect::<SnapshotResult<Vec<_>>>()?;
+
+        // вместимость чанков выводится из бюджета, он должен совпадать с сохраненным
+        let chunk_size = reader.read_usize()?;
+        let change_tick = reader.read_u32()?;
+
+        let mut state = self.new_snapshot_state(&reader, chunk_size, change_tick)?;
+
+        state.entity_versions = Vec::read_snapshot(&mut reader)?;
+
+        let position = reader.position();
+        state.free_entity_id = Vec::read_snapshot(&mut reader)?;
+
+        if state.free_entity_id.iter().any(|entity_id| state.entity_versions.get(entity_id.id) != Some(&entity_id.version)) {
+            return Err(SnapshotError::InvalidData { position, message: "free entity id does not match entity versions".to_string() });
+        }
+
+        let shared_components_count = reader.read_len()?;
+
+        for _ in 0..shared_components_count {
+            let component_id = read_snapshot_component_id(&mut reader, &component_ids)?;
+
+            if self.components_info[&component_id].storage_type != StorageType::Shared {
+                return Err(reader.invalid_data("shared values of not shared component"));
+            }
+
+            let values_count = reader.read_len()?;
+            let shared_values = (0..values_count).map(|_| match reader.read_bool()? {
+                true => self.read_snapshot_value(component_id, &mut reader).map(Some),
+                false => Ok(None),
+            }).collect::<SnapshotResult<Vec<_>>>()?;
+
+            if state.shared_values.insert(component_id, shared_values).is_some() {
+                return Err(reader.invalid_data("shared values duplicated"));
+            }
+        }
+
+        let archetypes_count = reader.read_len()?;
+        let mut read_archetype_ids = HashSet::with_capacity(archetypes_count);
+
+        for _ in 0..archetypes_count {
+            let archetype_id = self.read_snapshot_archetype(&mut reader, &component_ids, &mut state)?;
+
+            if !read_archetype_ids.insert(archetype_id) {
+                return Err(reader.invalid_data("archetype duplicated"));
+            }
+        }
+
+        // каждый слот либо свободен, либо занят живой сущностью
+        let mut free_slots = HashSet::with_capacity(state.free_entity_id.len());
+
+        let is_valid = state.free_entity_id.iter().all(|entity_id| !state.entity_index.contains_key(entity_id.id) && free_slots.insert(entity_id.id)) &&
+            free_slots.len() + state.entity_index.len() == state.entity_versions.len();
+
+        if !is_valid {
+            return Err(reader.invalid_data("entity slots are neither free nor alive"));
+        }
+
+        let sparse_components_count = reader.read_len()?;
+
+        for _ in 0..sparse_components_count {
+            let component_id = read_snapshot_component_id(&mut reader, &component_ids)?;
+
+            if !self.sparse_sets.contains_key(&component_id) {
+                return Err(reader.invalid_data("sparse values of not sparse component"));
+            }
+
+            let components_count = reader.read_len()?;
+            let mut entity_ids = HashSet::with_capacity(components_count);
+            let mut components = Vec::with_capacity(components_count);
+
+            for _ in 0..components_count {
+                let entity_id = reader.read_entity_id()?;
+
+                if !state.is_alive(entity_id) || !entity_ids.insert(entity_id) {
+                    return Err(reader.invalid_data(format!("invalid sparse component entity: {entity_id:?}")));
+                }
+
+                components.push((entity_id, self.read_snapshot_value(component_id, &mut reader)?));
+            }
+
+            if state.sparse_components.insert(component_id, components).is_some() {
+                return Err(reader.invalid_data("sparse values duplicated"));
+            }
+        }
+
+        if !reader.is_end() {
+            return Err(reader.invalid_data("unexpected data after snapshot end"));
+        }
+
+        state.check_hierarchy().map_err(|message| reader.invalid_data(message))?;
+
+        let shared_value_refs = state.shared_value_refs();
+
+        // освобожденное значение сохраняется пустым слотом
+        let is_valid = state.shared_values.iter().all(|(component_id, shared_values)| shared_values.iter().enumerate().all(|(value_index, shared_value)| {
+            shared_value.is_none() || shared_value_refs.get(component_id).and_then(|refs| refs.get(value_index)).is_some_and(|refs| *refs != 0)
+        }));
+
+        if !is_valid {
+            return Err(reader.invalid_data("shared value is not used by any chunk"));
+        }
+
+        if let Some(component_id) = self.locked_sparse_component(state.sparse_components.keys()) {
+            return Err(SnapshotError::ComponentLocked { component_id });
+        }
+
+        self.chunk_size = state.chunk_size;
+        self.change_tick = state.change_tick;
+        // обновления систем до сохранения снимка не учитываются
+        self.update_tick = state.change_tick;
+        self.previous_update_tick = state.change_tick;
+
+        self.index_count = state.entity_versions.len();
+        self.entity_versions = state.entity_versions;
+        self.free_entity_id = state.free_entity_id;
+        self.entity_index = state.entity_index;
+
+        self.shared_values = state.shared_values;
+        self.shared_value_refs = shared_value_refs;
+
+        self.archetypes = state.archetypes;
+        self.archetype_map = state.archetype_map;
+
+        state.sparse_components.into_iter().for_each(|(component_id, components)| {
+            let sparse_set = self.sparse_sets.get_mut(&component_id).unwrap();
+            components.into_iter().for_each(|(entity_id, component)| sparse_set.insert_component(entity_id, component));
+        });
+
+        Ok(())
+    }
+
+    pub fn load_snapshot_file(&mut self, path: impl AsRef<Path>) -> SnapshotResult<()> {
+        let bytes = std::fs::read(path)?;
+        self.load_snapshot(&bytes)
+    }
+
+    /// Компонент таблицы компонентов снимка, найденный по стабильному идентификатору
+    fn read_snapshot_component(&self, reader: &mut SnapshotReader) -> SnapshotResult<ComponentId> {
+        let stable_id = StableComponentId::read_snapshot(reader)?;
+        let storage_type = StorageType::read_snapshot(reader)?;
+        let is_tag = reader.read_bool()?;
+
+        let component_id = self.component_id_by_stable_id(&stable_id).ok_or_else(|| SnapshotError::UnknownComponent { stable_id: stable_id.clone() })?;
+        let component_info = &self.components_info[&component_id];
+
+        if component_info.storage_type != storage_type || component_info.is_tag != is_tag {
+            return Err(SnapshotError::StorageTypeMismatch { stable_id });
+        }
+
+        if !is_snapshot_component(component_info) {
+            return Err(SnapshotError::ComponentNotSerializable { component_id, component_name: component_info.name.clone() });
+        }
+
+        Ok(component_id)
+    }
+
+    /// Значение динамического компонента хранится байтами, остальные читаются хуком компонента
+    fn read_snapshot_value(&self, component_id: ComponentId, reader: &mut SnapshotReader) -> SnapshotResult<Box<dyn Any + Sync + Send>> {
+        let component_info = &self.components_info[&component_id];
+
+        let Some(dynamic_descriptor) = &component_info.dynamic_descriptor else {
+            return (component_info.snapshot_read.unwrap())(reader);
+        };
+
+        let bytes = reader.read_bytes()?;
+
+        if bytes.len() != dynamic_descriptor.size {
+            return Err(reader.invalid_data(format!("dynamic component [{}] expects {} bytes, found {}", component_info.name, dynamic_descriptor.size, bytes.len())));
+        }
+
+        // у компонента без drop значение - просто байты
+        Ok(Box::new(unsafe { DynamicComponent::from_bytes(component_id, dynamic_descriptor, bytes) }))
+    }
+
+    /// Пустое загружаемое состояние. Архетипы менеджера пустые, они пересобираются под бюджет чанка снимка с сохранением переходов
+    fn new_snapshot_state(&self, reader: &SnapshotReader, chunk_size: usize, change_tick: u32) -> SnapshotResult<SnapshotState> {
+        let archetypes = self.archetypes.iter().map(|archetype| {
+            let mut rebuilt_archetype = self.build_archetype(archetype.archetype_id, archetype.archetype_type.clone(), chunk_size)
+                .map_err(|error| reader.invalid_data(error.to_string()))?;
+
+            rebuilt_archetype.add_component_edges = archetype.add_component_edges.clone();
+            rebuilt_archetype.remove_component_edges = archetype.remove_component_edges.clone();
+
+            Ok(rebuilt_archetype)
+        }).collect::<SnapshotResult<Vec<_>>>()?;
+
+        Ok(SnapshotState {
+            chunk_size,
+            change_tick,
+            entity_versions: Vec::new(),
+            free_entity_id: Vec::new(),
+            entity_index: VecMap::new(),
+            shared_values: HashMap::new(),
+            archetypes,
+            archetype_map: self.archetype_map.clone(),
+            sparse_components: HashMap::new(),
+        })
+    }
+
+    fn read_snapshot_archetype(&self, reader: &mut SnapshotReader, component_ids: &[ComponentId], state: &mut SnapshotState) -> SnapshotResult<ArchetypeId> {
+        let components_count = reader.read_len()?;
+        let archetype_type: ArchetypeType = (0..components_count).map(|_| read_snapshot_component_id(reader, component_ids)).collect::<SnapshotResult<Vec<_>>>()?.into();
+
+        // разреженные компоненты хранятся вне архетипов
+        if archetype_type.iter().any(|component_id| self.sparse_sets.contains_key(component_id)) {
+            return Err(reader.invalid_data("sparse component in archetype"));
+        }
+
+        let archetype_id = match state.archetype_map.get(&archetype_type) {
+            Some(archetype_id) => *archetype_id,
+            None => {
+                let archetype_id = ArchetypeId::new(state.archetypes.len());
+                let archetype = self.build_archetype(archetype_id, archetype_type.clone(), state.chunk_size)
+                    .map_err(|error| reader.invalid_data(error.to_string()))?;
+
+                state.archetypes.push(archetype);
+                state.archetype_map.insert(archetype_type, archetype_id);
+
+                archetype_id
+            },
+        };
+
+        let chunks_count = reader.read_len()?;
+
+        for _ in 0..chunks_count {
+            self.read_snapshot_chunk(reader, component_ids, archetype_id, state)?;
+        }
+
+        let chunk_groups_count = reader.read_len()?;
+
+        for _ in 0..chunk_groups_count {
+            let position = reader.position();
+            let chunk_group = Vec::<usize>::read_snapshot(reader)?;
+
+            let archetype = &mut state.archetypes[*archetype_id];
+
+            let shared_components_key = chunk_group.first()
+                .and_then(|chunk_index| archetype.chunks.get(*chunk_index))
+                .map(|archetype_chunk| archetype_chunk.shared_components_key.clone())
+                .ok_or(SnapshotError::InvalidData { position, message: "invalid chunk group".to_string() })?;
+
+            let is_valid = chunk_group.iter().all(|chunk_index| archetype.chunks.get(*chunk_index).is_some_and(|archetype_chunk| archetype_chunk.shared_components_key == shared_components_key));
+
+            if !is_valid || archetype.chunk_groups.insert(shared_components_key, chunk_group).is_some() {
+                return Err(SnapshotError::InvalidData { position, message: "invalid chunk group".to_string() });
+            }
+        }
+
+        // каждый чанк входит ровно в одну группу, непустой, и все чанки группы, кроме последнего, заполнены
+        let archetype = &state.archetypes[*archetype_id];
+        let mut chunk_groups_count = vec![0; archetype.chunks.len()];
+
+        for chunk_group in archetype.chunk_groups.values() {
+            for (position, chunk_index) in chunk_group.iter().enumerate() {
+                chunk_groups_count[*chunk_index] += 1;
+
+                let archetype_chunk = &archetype.chunks[*chunk_index];
+                let is_last = position == chunk_group.len() - 1;
+
+                if archetype_chunk.is_empty() || !is_last && !archetype_chunk.is_filled() {
+                    return Err(reader.invalid_data("chunk group is not packed"));
+                }
+            }
+        }
+
+        if chunk_groups_count.iter().any(|count| *count != 1) {
+            return Err(reader.invalid_data("chunk must be in exactly one chunk group"));
+        }
+
+        Ok(archetype_id)
+    }
+
+    fn read_snapshot_chunk(&self, reader: &mut SnapshotReader, component_ids: &[ComponentId], archetype_id: ArchetypeId, state: &mut SnapshotState) -> SnapshotResult<()> {
+        let shared_components_count = reader.read_len()?;
+
+        let mut shared_components_key = (0..shared_components_count).map(|_| {
+            let component_id = read_snapshot_component_id(reader, component_ids)?;
+            let value_index = reader.read_usize()?;
+
+            match state.shared_values.get(&component_id).is_some_and(|shared_values| shared_values.get(value_index).is_some_and(Option::is_some)) {
+                true => Ok((component_id, value_index)),
+                false => Err(reader.invalid_data("invalid shared value index")),
+            }
+        }).collect::<SnapshotResult<SharedComponentsKey>>()?;
+
+        // порядок идентификаторов компонентов зависит от сборки
+        shared_components_key.sort();
+
+        if !shared_components_key.iter().map(|(component_id, _)| component_id).eq(state.archetypes[*archetype_id].shared_component_ids.iter()) {
+            return Err(reader.invalid_data("chunk shared components do not match archetype"));
+        }
+
+        let position = reader.position();
+        let entity_ids = Vec::<EntityId>::read_snapshot(reader)?;
+
+        let is_valid = entity_ids.len() <= state.archetypes[*archetype_id].chunk_capacity && entity_ids.iter().all(|entity_id| {
+            state.entity_versions.get(entity_id.id) == Some(&entity_id.version) && !state.entity_index.contains_key(entity_id.id)
+        });
+
+        if !is_valid {
+            return Err(SnapshotError::InvalidData { position, message: "invalid chunk entities".to_string() });
+        }
+
+        let chunk_index = state.archetypes[*archetype_id].push_chunk(shared_components_key);
+
+        let columns_count = reader.read_len()?;
+
+        if columns_count != state.archetypes[*archetype_id].chunks[chunk_index].archetype_components_map.len() {
+            return Err(reader.invalid_data("chunk columns do not match archetype"));
+        }
+
+        let mut columns = Vec::with_capacity(columns_count);
+
+        for _ in 0..columns_count {
+            let component_id = read_snapshot_component_id(reader, component_ids)?;
+
+            let Some(components_array) = state.archetypes[*archetype_id].chunks[chunk_index].archetype_components_map.get(&component_id) else {
+                return Err(reader.invalid_data("chunk columns do not match archetype"));
+            };
+
+            components_array.get_change_tick().store(reader.read_u32()?, Ordering::Release);
+
+            let components_ticks = (0..entity_ids.len())
+                .map(|_| Ok(ComponentTicks { added: reader.read_u32()?, changed: reader.read_u32()? }))
+                .collect::<SnapshotResult<Vec<_>>>()?;
+
+            let components = (0..entity_ids.len()).map(|_| self.read_snapshot_value(component_id, reader)).collect::<SnapshotResult<Vec<_>>>()?;
+
+            columns.push((component_id, components_ticks.into_iter(), components.into_iter()));
+        }
+
+        if columns.iter().map(|(component_id, _, _)| component_id).collect::<HashSet<_>>().len() != columns_count {
+            return Err(reader.invalid_data("chunk column duplicated"));
+        }
+
+        entity_ids.into_iter().enumerate().for_each(|(row, entity_id)| {
+            let mut entity_data = EntityData::new(entity_id, HashMap::with_capacity(columns.len()));
+
+            columns.iter_mut().for_each(|(component_id, components_ticks, components)| {
+                entity_data.add_component(*component_id, components.next().unwrap());
+                entity_data.components_ticks.insert(*component_id, components_ticks.next().unwrap());
+            });
+
+            state.archetypes[*archetype_id].chunks[chunk_index].set_data(entity_data, 0);
+            state.entity_index.insert(entity_id.id, EntityLocation::new(archetype_id, chunk_index, row));
+        });
+
+        Ok(())
+    }
+}
+
+type SparseComponentValues = Vec<(EntityId, Box<dyn Any + Sync + Send>)>;
+
+/// Состояние, прочитанное из снимка. Переносится в менеджер только после проверки всего снимка
+struct SnapshotState {
+    chunk_size: usize,
+    change_tick: u32,
+    entity_versions: Vec<usize>,
+    free_entity_id: Vec<EntityId>,
+    entity_index: VecMap<EntityLocation>,
+    shared_values: HashMap<ComponentId, Vec<Option<Box<dyn Any + Sync + Send>>>>,
+    archetypes: Vec<Archetype>,
+    archetype_map: HashMap<ArchetypeType, ArchetypeId>,
+    sparse_components: HashMap<ComponentId, SparseComponentValues>,
+}
+
+impl SnapshotState {
+    fn is_alive(&self, entity_id: EntityId) -> bool {
+        self.entity_index.contains_key(entity_id.id) && self.entity_versions.get(entity_id.id) == Some(&entity_id.version)
+    }
+
+    /// Значения разреженного компонента по сущностям
+    fn sparse_values<TComponent: Clone + 'static>(&self) -> HashMap<EntityId, TComponent> {
+        self.sparse_components.get(&ComponentId::from_type::<TComponent>()).into_iter()
+            .flatten()
+            .map(|(entity_id, component)| (*entity_id, component.downcast_ref::<TComponent>().unwrap().clone()))
+            .collect()
+    }
+
+    fn check_hierarchy(&self) -> Result<(), String> {
+        let entity_ids = self.entity_index.keys().map(|id| EntityId { id, version: self.entity_versions[id] }).collect::<HashSet<_>>();
+
+        let parents = self.sparse_values::<Parent>().into_iter().map(|(entity_id, parent)| (entity_id, parent.get())).collect();
+        let children = self.sparse_values::<Children>().into_iter().map(|(entity_id, children)| (entity_id, children.to_vec())).collect();
+
+        check_hierarchy_links(&entity_ids, &parents, &children)
+    }
+
+    /// Количество сущностей, ссылающихся на каждое значение общего компонента
+    fn shared_value_refs(&self) -> HashMap<ComponentId, Vec<usize>> {
+        let mut shared_value_refs = HashMap::<ComponentId, Vec<usize>>::new();
+
+        self.archetypes.iter().flat_map(|archetype| archetype.chunks.iter()).for_each(|archetype_chunk| {
+            archetype_chunk.shared_components_key.iter().for_each(|(component_id, value_index)| {
+                let refs = shared_value_refs.entry(*component_id).or_default();
+
+                if refs.len() <= *value_index {
+                    refs.resize(*value_index + 1, 0);
+                }
+
+                refs[*value_index] += archetype_chunk.components_count;
+            });
+        });
+
+        shared_value_refs
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::types::{EntityId, SnapshotResult, SnapshotError};
+
+    use super::{snapshot_io::{ISnapshotComponent, SnapshotWriter, SnapshotReader}, super::{EcsDataManager, component::storage_type::StorageType, hierarchy::Children, test_fixtures::{TestComponentC, TestTickComponent, TestSharedComponent, TestNameComponent, register_with}}};
+
+    impl ISnapshotComponent for TestTickComponent {
+        fn write_snapshot(&self, writer: &mut SnapshotWriter) {
+            self.0.write_snapshot(writer)
+        }
+
+        fn read_snapshot(reader: &mut SnapshotReader) -> SnapshotResult<Self> {
+            Ok(Self(u32::read_snapshot(reader)?))
+        }
+    }
+
+    impl ISnapshotComponent for TestSharedComponent {
+        fn write_snapshot(&self, writer: &mut SnapshotWriter) {
+            self.0.write_snapshot(writer)
+        }
+
+        fn read_snapshot(reader: &mut SnapshotReader) -> SnapshotResult<Self> {
+            Ok(Self(u32::read_snapshot(reader)?))
+        }
+    }
+
+    impl ISnapshotComponent for TestNameComponent {
+        fn write_snapshot(&self, writer: &mut SnapshotWriter) {
+            self.0.write_snapshot(writer)
+        }
+
+        fn read_snapshot(reader: &mut SnapshotReader) -> SnapshotResult<Self> {
+            Ok(Self(String::read_snapshot(reader)?))
+        }
+    }
+
+    fn snapshot_test_manager(chunk_size: usize) -> EcsDataManager {
+        let mut ecs_data_manager = EcsDataManager::with_chunk_size(chunk_size);
+
+        register_with::<TestTickComponent>(&mut ecs_data_manager, |component_builder| {
+            component_builder.stable_id("test::TestTickComponent").snapshot();
+        });
+
+        register_with::<TestSharedComponent>(&mut ecs_data_manager, |component_builder| {
+            component_builder.shared().stable_id("test::TestSharedComponent").snapshot();
+        });
+
+        register_with::<TestNameComponent>(&mut ecs_data_manager, |component_builder| {
+            component_builder.storage_type(StorageType::SparseSet).stable_id("test::TestNameComponent").snapshot();
+        });
+
+        register_with::<TestComponentC>(&mut ecs_data_manager, |component_builder| {
+            component_builder.tag_default().stable_id("test::TestComponentC");
+        });
+
+        ecs_data_manager
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut ecs_data_manager = snapshot_test_manager(64);
+
+        let entity_id_range = ecs_data_manager.spawn_batch((0..40).map(|value| (TestTickComponent(value),))).unwrap();
+        let entity_ids = entity_id_range.iter().collect::<Vec<_>>();
+
+        for (index, entity_id) in entity_ids.iter().enumerate() {
+            match index % 4 {
+                0 => ecs_data_manager.remove_entity(*entity_id).unwrap(),
+                1 => ecs_data_manager.insert_component(*entity_id, TestSharedComponent(index as u32 % 3)).unwrap(),
+                2 => ecs_data_manager.insert_component(*entity_id, TestNameComponent(format!("entity {index}"))).unwrap(),
+                _ => ecs_data_manager.insert_component(*entity_id, TestComponentC {}).unwrap(),
+            }
+        }
+
+        // освобожденное значение общего компонента остается пустым слотом
+        entity_ids.iter().enumerate()
+            .filter(|(index, _)| index % 4 == 1 && index % 3 == 2)
+            .for_each(|(_, entity_id)| ecs_data_manager.remove_entity(*entity_id).unwrap());
+
+        ecs_data_manager.spawn((TestTickComponent(1000), TestNameComponent("recycled".to_string()))).unwrap();
+        ecs_data_manager.set_parent(entity_ids[3], entity_ids[2]).unwrap();
+
+        let snapshot = ecs_data_manager.save_snapshot().unwrap();
+
+        let mut loaded_manager = snapshot_test_manager(64);
+        loaded_manager.load_snapshot(&snapshot).unwrap();
+
+        assert_eq!(loaded_manager.save_snapshot().unwrap(), snapshot);
+        assert_eq!(loaded_manager.shared_value_refs, ecs_data_manager.shared_value_refs);
+
+        let alive_entity_ids = (0..ecs_data_manager.index_count)
+            .map(|id| EntityId { id, version: ecs_data_manager.entity_versions[id] })
+            .filter(|entity_id| ecs_data_manager.is_alive(*entity_id))
+            .collect::<Vec<_>>();
+
+        assert_eq!(alive_entity_ids.len(), 40 - 10 - 3 + 1);
+
+        for entity_id in alive_entity_ids {
+            assert_eq!(loaded_manager.entity_location(entity_id), ecs_data_manager.entity_location(entity_id));
+            assert_eq!(*loaded_manager.get::<TestTickComponent>(entity_id).unwrap(), *ecs_data_manager.get::<TestTickComponent>(entity_id).unwrap());
+            assert_eq!(loaded_manager.get::<TestSharedComponent>(entity_id).ok().map(|value| value.clone()), ecs_data_manager.get::<TestSharedComponent>(entity_id).ok().map(|value| value.clone()));
+            assert_eq!(loaded_manager.get::<TestNameComponent>(entity_id).ok().map(|value| value.clone()), ecs_data_manager.get::<TestNameComponent>(entity_id).ok().map(|value| value.clone()));
+            assert_eq!(loaded_manager.has_component::<TestComponentC>(entity_id), ecs_data_manager.has_component::<TestComponentC>(entity_id));
+            assert_eq!(loaded_manager.parent(entity_id).unwrap(), ecs_data_manager.parent(entity_id).unwrap());
+        }
+
+        // освобожденные слоты переиспользуются в том же порядке
+        assert_eq!(loaded_manager.spawn((TestTickComponent(0),)).unwrap(), ecs_data_manager.spawn((TestTickComponent(0),)).unwrap());
+    }
+
+    #[test]
+    fn test_failed_snapshot_load_keeps_manager() {
+        let mut source_manager = snapshot_test_manager(64);
+        source_manager.spawn_batch((0..10).map(|value| (TestTickComponent(value), TestSharedComponent(value % 2)))).unwrap();
+        source_manager.spawn((TestTickComponent(10), TestNameComponent("name".to_string()))).unwrap();
+
+        let snapshot = source_manager.save_snapshot().unwrap();
+
+        // архетип без сущностей, созданный под другой бюджет чанка
+        let mut ecs_data_manager = snapshot_test_manager(1024);
+        ecs_data_manager.spawn_batch(std::iter::empty::<(TestTickComponent, TestSharedComponent)>()).unwrap();
+
+        let empty_snapshot = ecs_data_manager.save_snapshot().unwrap();
+
+        // обрезанный снимок не загружается и ничего не меняет
+        for length in [snapshot.len() / 2, snapshot.len() - 1] {
+            assert!(ecs_data_manager.load_snapshot(&snapshot[..length]).is_err());
+            assert_eq!(ecs_data_manager.save_snapshot().unwrap(), empty_snapshot);
+            assert_eq!(ecs_data_manager.chunk_size, 1024);
+        }
+
+        ecs_data_manager.load_snapshot(&snapshot).unwrap();
+        assert_eq!(ecs_data_manager.save_snapshot().unwrap(), snapshot);
+
+        let archetype_ids = ecs_data_manager.archetypes.iter().map(|archetype| (archetype.archetype_id, archetype.chunk_capacity)).collect::<Vec<_>>();
+        assert_eq!(archetype_ids, source_manager.archetypes.iter().map(|archetype| (archetype.archetype_id, archetype.chunk_capacity)).collect::<Vec<_>>());
+
+        // повторная загрузка в менеджер с сущностями отклоняется
+        assert!(matches!(ecs_data_manager.load_snapshot(&snapshot), Err(SnapshotError::NotEmpty)));
+    }
+
+    #[test]
+    fn test_invalid_snapshot_hierarchy_is_rejected() {
+        let mut ecs_data_manager = snapshot_test_manager(64);
+        let parent_id = ecs_data_manager.spawn((TestTickComponent(0),)).unwrap();
+        let child_id = ecs_data_manager.spawn((TestTickComponent(1),)).unwrap();
+
+        ecs_data_manager.set_parent(child_id, parent_id).unwrap();
+        ecs_data_manager.remove_component_unchecked::<Children>(parent_id).unwrap();
+
+        // снимок с рассогласованной иерархией не загружается
+        let snapshot = ecs_data_manager.save_snapshot().unwrap();
+        assert!(matches!(snapshot_test_manager(64).load_snapshot(&snapshot), Err(SnapshotError::InvalidData { .. })));
+    }
+}
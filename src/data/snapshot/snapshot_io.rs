@@ -0,0 +1,371 @@
+use crate::{types::{EntityId, SnapshotResult, SnapshotError, StableComponentId}, data::component::storage_type::StorageType};
+
+/// Запись снимка: числа в little-endian, длины и usize - как u64
+#[derive(Debug, Default)]
+pub struct SnapshotWriter {
+    bytes: Vec<u8>,
+}
+
+impl SnapshotWriter {
+    pub fn new() -> Self {
+        Self { bytes: Vec::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    /// Байты без длины, читаются через `SnapshotReader::read_raw`
+    pub fn write_raw(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.write_raw(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.write_raw(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.write_raw(&value.to_le_bytes());
+    }
+
+    pub fn write_usize(&mut self, value: usize) {
+        self.write_u64(value as u64);
+    }
+
+    pub fn write_i8(&mut self, value: i8) {
+        self.write_raw(&value.to_le_bytes());
+    }
+
+    pub fn write_i16(&mut self, value: i16) {
+        self.write_raw(&value.to_le_bytes());
+    }
+
+    pub fn write_i32(&mut self, value: i32) {
+        self.write_raw(&value.to_le_bytes());
+    }
+
+    pub fn write_i64(&mut self, value: i64) {
+        self.write_raw(&value.to_le_bytes());
+    }
+
+    pub fn write_f32(&mut self, value: f32) {
+        self.write_raw(&value.to_le_bytes());
+    }
+
+    pub fn write_f64(&mut self, value: f64) {
+        self.write_raw(&value.to_le_bytes());
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_u8(value as u8);
+    }
+
+    /// Байты с длиной
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_usize(bytes.len());
+        self.write_raw(bytes);
+    }
+
+    pub fn write_str(&mut self, value: &str) {
+        self.write_bytes(value.as_bytes());
+    }
+
+    pub fn write_entity_id(&mut self, entity_id: EntityId) {
+        self.write_usize(entity_id.id);
+        self.write_usize(entity_id.version);
+    }
+}
+
+/// Чтение снимка, ошибки содержат смещение в байтах
+#[derive(Debug)]
+pub struct SnapshotReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> SnapshotReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn is_end(&self) -> bool {
+        self.position == self.bytes.len()
+    }
+
+    /// Ошибка данных в текущей позиции
+    pub fn invalid_data(&self, message: impl Into<String>) -> SnapshotError {
+        SnapshotError::InvalidData { position: self.position, message: message.into() }
+    }
+
+    pub fn read_raw(&mut self, len: usize) -> SnapshotResult<&'a [u8]> {
+        if self.bytes.len() - self.position < len {
+            return Err(SnapshotError::UnexpectedEnd { position: self.position });
+        }
+
+        let bytes = &self.bytes[self.position..self.position + len];
+        self.position += len;
+
+        Ok(bytes)
+    }
+
+    fn read_array<const N: usize>(&mut self) -> SnapshotResult<[u8; N]> {
+        Ok(self.read_raw(N)?.try_into().unwrap())
+    }
+
+    pub fn read_u8(&mut self) -> SnapshotResult<u8> {
+        Ok(self.read_array::<1>()?[0])
+    }
+
+    pub fn read_u16(&mut self) -> SnapshotResult<u16> {
+        Ok(u16::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn read_u32(&mut self) -> SnapshotResult<u32> {
+        Ok(u32::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn read_u64(&mut self) -> SnapshotResult<u64> {
+        Ok(u64::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn read_usize(&mut self) -> SnapshotResult<usize> {
+        let position = self.position;
+
+        usize::try_from(self.read_u64()?).map_err(|_| SnapshotError::InvalidData { position, message: "value does not fit usize".to_string() })
+    }
+
+    /// Длина последовательности. Каждый элемент занимает хотя бы байт, поэтому длина больше остатка снимка - ошибка,
+    /// а не повод выделять память
+    pub fn read_len(&mut self) -> SnapshotResult<usize> {
+        let position = self.position;
+        let len = self.read_usize()?;
+
+        match len <= self.bytes.len() - self.position {
+            true => Ok(len),
+            false => Err(SnapshotError::UnexpectedEnd { position }),
+        }
+    }
+
+    pub fn read_i8(&mut self) -> SnapshotResult<i8> {
+        Ok(i8::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn read_i16(&mut self) -> SnapshotResult<i16> {
+        Ok(i16::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn read_i32(&mut self) -> SnapshotResult<i32> {
+        Ok(i32::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn read_i64(&mut self) -> SnapshotResult<i64> {
+        Ok(i64::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn read_f32(&mut self) -> SnapshotResult<f32> {
+        Ok(f32::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn read_f64(&mut self) -> SnapshotResult<f64> {
+        Ok(f64::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn read_bool(&mut self) -> SnapshotResult<bool> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SnapshotError::InvalidData { position: self.position - 1, message: "invalid bool".to_string() }),
+        }
+    }
+
+    pub fn read_bytes(&mut self) -> SnapshotResult<&'a [u8]> {
+        let len = self.read_len()?;
+        self.read_raw(len)
+    }
+
+    pub fn read_str(&mut self) -> SnapshotResult<&'a str> {
+        let position = self.position;
+
+        std::str::from_utf8(self.read_bytes()?).map_err(|_| SnapshotError::InvalidData { position, message: "invalid utf-8".to_string() })
+    }
+
+    pub fn read_entity_id(&mut self) -> SnapshotResult<EntityId> {
+        Ok(EntityId { id: self.read_usize()?, version: self.read_usize()? })
+    }
+}
+
+/// Компонент, значения которого сохраняются в снимок. Подключается через `ComponentBuilder::snapshot`
+pub trait ISnapshotComponent: Sized {
+    fn write_snapshot(&self, writer: &mut SnapshotWriter);
+    fn read_snapshot(reader: &mut SnapshotReader) -> SnapshotResult<Self>;
+}
+
+macro_rules! primitive_into_snapshot_component {
+    ($($type:ty => $write:ident, $read:ident);* $(;)?) => {
+        $(
+            impl ISnapshotComponent for $type {
+                fn write_snapshot(&self, writer: &mut SnapshotWriter) {
+                    writer.$write(*self);
+                }
+
+                fn read_snapshot(reader: &mut SnapshotReader) -> SnapshotResult<Self> {
+                    reader.$read()
+                }
+            }
+        )*
+    };
+}
+
+primitive_into_snapshot_component! {
+    u8 => write_u8, read_u8;
+    u16 => write_u16, read_u16;
+    u32 => write_u32, read_u32;
+    u64 => write_u64, read_u64;
+    usize => write_usize, read_usize;
+    i8 => write_i8, read_i8;
+    i16 => write_i16, read_i16;
+    i32 => write_i32, read_i32;
+    i64 => write_i64, read_i64;
+    f32 => write_f32, read_f32;
+    f64 => write_f64, read_f64;
+    bool => write_bool, read_bool;
+    EntityId => write_entity_id, read_entity_id;
+}
+
+impl ISnapshotComponent for String {
+    fn write_snapshot(&self, writer: &mut SnapshotWriter) {
+        writer.write_str(self);
+    }
+
+    fn read_snapshot(reader: &mut SnapshotReader) -> SnapshotResult<Self> {
+        Ok(reader.read_str()?.to_string())
+    }
+}
+
+impl<T: ISnapshotComponent> ISnapshotComponent for Vec<T> {
+    fn write_snapshot(&self, writer: &mut SnapshotWriter) {
+        writer.write_usize(self.len());
+        self.iter().for_each(|item| item.write_snapshot(writer));
+    }
+
+    fn read_snapshot(reader: &mut SnapshotReader) -> SnapshotResult<Self> {
+        let len = reader.read_len()?;
+        (0..len).map(|_| T::read_snapshot(reader)).collect()
+    }
+}
+
+impl<T: ISnapshotComponent> ISnapshotComponent for Option<T> {
+    fn write_snapshot(&self, writer: &mut SnapshotWriter) {
+        writer.write_bool(self.is_some());
+
+        if let Some(value) = self {
+            value.write_snapshot(writer);
+        }
+    }
+
+    fn read_snapshot(reader: &mut SnapshotReader) -> SnapshotResult<Self> {
+        match reader.read_bool()? {
+            true => Ok(Some(T::read_snapshot(reader)?)),
+            false => Ok(None),
+        }
+    }
+}
+
+impl<T: ISnapshotComponent, const N: usize> ISnapshotComponent for [T; N] {
+    fn write_snapshot(&self, writer: &mut SnapshotWriter) {
+        self.iter().for_each(|item| item.write_snapshot(writer));
+    }
+
+    fn read_snapshot(reader: &mut SnapshotReader) -> SnapshotResult<Self> {
+        let items = (0..N).map(|_| T::read_snapshot(reader)).collect::<SnapshotResult<Vec<_>>>()?;
+        Ok(items.try_into().unwrap_or_else(|_| unreachable!()))
+    }
+}
+
+impl ISnapshotComponent for StableComponentId {
+    fn write_snapshot(&self, writer: &mut SnapshotWriter) {
+        match self {
+            StableComponentId::Name(name) => {
+                writer.write_u8(0);
+                writer.write_str(name);
+            },
+            StableComponentId::Id(id) => {
+                writer.write_u8(1);
+                writer.write_u64(*id);
+            },
+        }
+    }
+
+    fn read_snapshot(reader: &mut SnapshotReader) -> SnapshotResult<Self> {
+        match reader.read_u8()? {
+            0 => Ok(StableComponentId::Name(reader.read_str()?.to_string())),
+            1 => Ok(StableComponentId::Id(reader.read_u64()?)),
+            _ => Err(SnapshotError::InvalidData { position: reader.position() - 1, message: "invalid stable component id".to_string() }),
+        }
+    }
+}
+
+impl ISnapshotComponent for StorageType {
+    fn write_snapshot(&self, writer: &mut SnapshotWriter) {
+        writer.write_u8(match self {
+            StorageType::Table => 0,
+            StorageType::SparseSet => 1,
+            StorageType::Shared => 2,
+        });
+    }
+
+    fn read_snapshot(reader: &mut SnapshotReader) -> SnapshotResult<Self> {
+        match reader.read_u8()? {
+            0 => Ok(StorageType::Table),
+            1 => Ok(StorageType::SparseSet),
+            2 => Ok(StorageType::Shared),
+            _ => Err(SnapshotError::InvalidData { position: reader.position() - 1, message: "invalid storage type".to_string() }),
+        }
+    }
+}
+
+macro_rules! tuple_into_snapshot_component {
+    ( $( $name:ident ),+ ) => {
+        impl<$($name: ISnapshotComponent),+> ISnapshotComponent for ($($name,)+)
+        {
+            #[allow(non_snake_case)]
+            fn write_snapshot(&self, writer: &mut SnapshotWriter) {
+                let ($($name,)+) = self;
+                $($name.write_snapshot(writer);)+
+            }
+
+            fn read_snapshot(reader: &mut SnapshotReader) -> SnapshotResult<Self> {
+                Ok(($($name::read_snapshot(reader)?,)+))
+            }
+        }
+    };
+}
+
+tuple_into_snapshot_component!(T0);
+tuple_into_snapshot_component!(T0, T1);
+tuple_into_snapshot_component!(T0, T1, T2);
+tuple_into_snapshot_component!(T0, T1, T2, T3);
+tuple_into_snapshot_component!(T0, T1, T2, T3, T4);
+tuple_into_snapshot_component!(T0, T1, T2, T3, T4, T5);
+tuple_into_snapshot_component!(T0, T1, T2, T3, T4, T5, T6);
+tuple_into_snapshot_component!(T0, T1, T2, T3, T4, T5, T6, T7);
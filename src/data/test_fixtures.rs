@@ -0,0 +1,49 @@
+use std::fmt::Debug;
+
+use crate::types::ComponentId;
+
+use super::{EcsDataManager, component::{component_builder::ComponentBuilder, storage_type::StorageType}};
+
+#[derive(Debug)]
+pub (crate) struct TestComponentA {}
+
+#[derive(Debug)]
+pub (crate) struct TestComponentB {}
+
+/// Метка со значением по умолчанию
+#[derive(Debug, Default)]
+pub (crate) struct TestComponentC {}
+
+#[derive(Debug, PartialEq)]
+pub (crate) struct TestTickComponent(pub (crate) u32);
+
+#[derive(Debug, Clone, PartialEq)]
+pub (crate) struct TestSharedComponent(pub (crate) u32);
+
+#[derive(Debug, Clone, PartialEq)]
+pub (crate) struct TestNameComponent(pub (crate) String);
+
+/// Регистрация компонента с настройкой построителя
+pub (crate) fn register_with<TComponent: Debug + Sync + Send + 'static>(ecs_data_manager: &mut EcsDataManager, configure: impl FnOnce(&mut ComponentBuilder<'_, TComponent>)) -> ComponentId {
+    let mut component_builder = ecs_data_manager.get_component_builder::<TComponent>();
+    configure(&mut component_builder);
+    component_builder.build().unwrap()
+}
+
+pub (crate) fn register_sparse<TComponent: Debug + Sync + Send + 'static>(ecs_data_manager: &mut EcsDataManager) -> ComponentId {
+    register_with::<TComponent>(ecs_data_manager, |component_builder| {
+        component_builder.storage_type(StorageType::SparseSet);
+    })
+}
+
+pub (crate) fn register_shared<TComponent: Debug + PartialEq + Clone + Sync + Send + 'static>(ecs_data_manager: &mut EcsDataManager) -> ComponentId {
+    register_with::<TComponent>(ecs_data_manager, |component_builder| {
+        component_builder.shared();
+    })
+}
+
+pub (crate) fn register_tag<TComponent: Debug + Default + Sync + Send + 'static>(ecs_data_manager: &mut EcsDataManager) -> ComponentId {
+    register_with::<TComponent>(ecs_data_manager, |component_builder| {
+        component_builder.tag_default();
+    })
+}
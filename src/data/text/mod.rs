@@ -0,0 +1,385 @@
+pub mod text_io;
+
+use std::{any::Any, collections::{HashMap, HashSet}};
+
+use crate::types::{ComponentId, EntityId, ArchetypeType, StableComponentId, TextResult, TextError};
+
+use self::text_io::{TextValue, TextContext, parse_text};
+
+use super::{EcsDataManager, component::{component_info::ComponentInfo, dynamic_component::DynamicComponent}, hierarchy::{Parent, Children, check_hierarchy_links}};
+
+// значения компонентов сущности по идентификаторам
+type ComponentsMap = HashMap<ComponentId, Box<dyn Any + Send + Sync>>;
+
+/// Parent и Children сущностей документа должны ссылаться друг на друга, ссылки за пределы документа (null) не допускаются
+fn check_text_hierarchy(entity_ids: &[EntityId], entities_components: &[ComponentsMap]) -> TextResult<()> {
+    let parent_component_id = ComponentId::from_type::<Parent>();
+    let children_component_id = ComponentId::from_type::<Children>();
+
+    let parents = entity_ids.iter().zip(entities_components.iter())
+        .filter_map(|(entity_id, components_map)| components_map.get(&parent_component_id).map(|parent| (*entity_id, parent.downcast_ref::<Parent>().unwrap().0)))
+        .collect();
+
+    let children = entity_ids.iter().zip(entities_components.iter())
+        .filter_map(|(entity_id, components_map)| components_map.get(&children_component_id).map(|children| (*entity_id, children.downcast_ref::<Children>().unwrap().0.clone())))
+        .collect();
+
+    check_hierarchy_links(&entity_ids.iter().copied().collect::<HashSet<_>>(), &parents, &children)
+        .map_err(|message| TextError::InvalidHierarchy { message })
+}
+
+/// Компонент с текстовым форматом: метка пишется как null, остальным нужны хуки записи и чтения
+fn is_text_component(component_info: &ComponentInfo) -> bool {
+    component_info.is_tag || component_info.text_write.is_some() && (component_info.text_read.is_some() || component_info.dynamic_descriptor.is_some())
+}
+
+/// Текстовый формат сцены для ручного редактирования уровней:
+///
+/// ```text
+/// {
+///     "entities": [
+///         {
+///             "anthill_ecs::Children": [1],
+///             "game::Position": {
+///                 "x": 1.0,
+///                 "y": 2.0
+///             }
+///         },
+///         {
+///             "anthill_ecs::Parent": 0,
+///             "game::Player": null
+///         }
+///     ]
+/// }
+/// ```
+///
+/// Компоненты сущности - поля объекта с именами из стабильных идентификаторов (`#123` для числовых), метки пишутся как null.
+/// Ссылки на сущности - номера сущностей в документе, связи Parent/Children проверяются при загрузке.
+///
+/// Разбор свой, а не serde_json: крейт не добавляет зависимостей и не требует derive от компонентов, ссылки на сущности
+/// переводятся в номера документа при записи и чтении, значения хранят строку и столбец для сообщений об ошибках,
+/// целые читаются без потери точности, а порядок полей и сущностей детерминирован
+impl EcsDataManager {
+    /// Все живые сущности в порядке идентификаторов, компоненты сущности в порядке стабильных идентификаторов
+    pub fn save_text(&self) -> TextResult<String> {
+        let entity_ids = self.entity_index.keys().map(|id| EntityId { id, version: self.entity_versions[id] }).collect::<Vec<_>>();
+        let context = TextContext::new(entity_ids);
+
+        let mut entities_fields = self.entity_index.keys().map(|_| Vec::new()).collect::<Vec<Vec<(StableComponentId, TextValue)>>>();
+
+        for archetype in self.archetypes.iter().filter(|archetype| !archetype.is_empty()) {
+            for component_id in archetype.archetype_type.iter() {
+                let (stable_id, component_info) = self.text_component(component_id)?;
+
+                for archetype_chunk in archetype.chunks.iter() {
+                    let entity_indexes = archetype_chunk.entity_ids.iter().map(|entity_id| context.entity_index(*entity_id).unwrap()).collect::<Vec<_>>();
+
+                    let shared_value_index = archetype_chunk.shared_components_key.iter()
+                        .find(|(shared_component_id, _)| shared_component_id == component_id)
+                        .map(|(_, value_index)| *value_index);
+
+                    if let Some(value_index) = shared_value_index {
+                        let value = (component_info.text_write.unwrap())(self.shared_value(component_id, value_index), &context);
+                        entity_indexes.iter().for_each(|entity_index| entities_fields[*entity_index].push((stable_id.clone(), value.clone())));
+                    } else if let Some(components_array) = archetype_chunk.get_components_array(component_id) {
+                        let text_write = component_info.text_write.unwrap();
+                        let mut entity_indexes = entity_indexes.iter();
+
+                        components_array.for_each_component(&mut |component| {
+                            entities_fields[*entity_indexes.next().unwrap()].push((stable_id.clone(), text_write(component, &context)));
+                        }).map_err(|_| TextError::ComponentLocked { component_id: *component_id })?;
+                    } else {
+                        entity_indexes.iter().for_each(|entity_index| entities_fields[*entity_index].push((stable_id.clone(), TextValue::null())));
+                    }
+                }
+            }
+        }
+
+        for (component_id, sparse_set) in self.sparse_sets.iter().filter(|(_, sparse_set)| sparse_set.len() != 0) {
+            let (stable_id, component_info) = self.text_component(component_id)?;
+            let text_write = component_info.text_write.unwrap();
+
+            sparse_set.for_each_component(&mut |entity_id, component| {
+                entities_fields[context.entity_index(entity_id).unwrap()].push((stable_id.clone(), text_write(component, &context)));
+            }).map_err(|_| TextError::ComponentLocked { component_id: *component_id })?;
+        }
+
+        let entities = entities_fields.into_iter().map(|mut entity_fields| {
+            entity_fields.sort_by(|(left, _), (right, _)| left.cmp(right));
+            TextValue::object(entity_fields.into_iter().map(|(stable_id, value)| (stable_id.to_string(), value)))
+        });
+
+        Ok(TextValue::object([("entities", TextValue::array(entities))]).to_text())
+    }
+
+    /// Стабильный идентификатор компонента, у которого есть текстовый формат
+    fn text_component(&self, component_id: &ComponentId) -> TextResult<(StableComponentId, &ComponentInfo)> {
+        let component_info = &self.components_info[component_id];
+
+        let Some(stable_id) = &component_info.stable_id else {
+            return Err(TextError::MissingStableId { component_id: *component_id, component_name: component_info.name.clone() });
+        };
+
+        if !is_text_component(component_info) {
+            return Err(TextError::ComponentNotSerializable { component_id: *component_id, component_name: component_info.name.clone() });
+        }
+
+        Ok((stable_id.clone(), component_info))
+    }
+
+    /// Создание сущностей документа, возвращает их идентификаторы в порядке документа.
+    /// Документ проверяется целиком до создания первой сущности, при ошибке менеджер не изменяется.
+    /// Хуки компонентов вызываются после создания всех сущностей, как в spawn_batch
+    pub fn load_text(&mut self, text: &str) -> TextResult<Vec<EntityId>> {
+        let document = parse_text(text)?;
+
+        document.check_fields(&["entities"])?;
+
+        let entities = document.field("entities")?.as_array()?;
+
+        // ссылки на сущности разрешаются до создания сущностей: идентификаторы выдаются так же, как в new_entity_id
+        let entity_ids = self.free_entity_id.iter().rev().copied()
+            .chain((self.index_count..).map(EntityId::new))
+            .take(entities.len())
+            .collect::<Vec<_>>();
+
+        let context = TextContext::new(entity_ids.clone());
+
+        let entities_components = entities.iter().map(|entity| self.read_text_entity(entity, &context)).collect::<TextResult<Vec<_>>>()?;
+
+        let (archetype_types, entities_components): (Vec<_>, Vec<_>) = entities_components.into_iter().unzip();
+
+        check_text_hierarchy(&entity_ids, &entities_components)?;
+
+        // колонки и множества, удерживаемые ChunkDataAccessor, проверяются до создания первой сущности
+        if let Some(component_id) = archetype_types.iter().find_map(|archetype_type| self.locked_component_on_batch(archetype_type)) {
+            return Err(TextError::ComponentLocked { component_id });
+        }
+
+        for (components_map, archetype_type) in entities_components.into_iter().zip(archetype_types.iter()) {
+            let archetype_id = self.get_or_create_archetype(self.table_archetype_type(archetype_type.clone()))?;
+            let entity_id = self.new_entity_id();
+
+            self.insert_boxed_entity(entity_id, archetype_id, components_map);
+        }
+
+        entity_ids.iter().zip(archetype_types.iter()).for_each(|(entity_id, archetype_type)| {
+            self.run_component_hooks(*entity_id, archetype_type, |component_info| component_info.on_add.as_ref());
+            self.run_component_hooks(*entity_id, archetype_type, |component_info| component_info.on_insert.as_ref());
+        });
+
+        self.apply_hook_commands();
+
+        Ok(entity_ids)
+    }
+
+    /// Набор компонентов сущности и значения всех компонентов, кроме меток
+    fn read_text_entity(&self, entity: &TextValue, context: &TextContext) -> TextResult<(ArchetypeType, ComponentsMap)> {
+        let mut component_ids = Vec::new();
+        let mut components_map = HashMap::new();
+
+        for field in entity.as_object()? {
+            let stable_id = field.name.parse::<StableComponentId>().unwrap();
+
+            let Some(component_id) = self.component_id_by_stable_id(&stable_id) else {
+                return Err(TextError::UnknownComponent { line: field.position.line, column: field.position.column, stable_id });
+            };
+
+            let (_, component_info) = self.text_component(&component_id)?;
+
+            // разные записи одного числового идентификатора, например `#5` и `#05`
+            if component_ids.contains(&component_id) {
+                return Err(TextError::InvalidField { line: field.position.line, column: field.position.column, message: format!("component duplicated: {stable_id}") });
+            }
+
+            component_ids.push(component_id);
+
+            if let Some(component) = self.read_text_value(component_info, &field.value, context)? {
+                components_map.insert(component_id, component);
+            }
+        }
+
+        Ok((component_ids.into(), components_map))
+    }
+
+    /// None для метки: ее значение не хранится
+    fn read_text_value(&self, component_info: &ComponentInfo, value: &TextValue, context: &TextContext) -> TextResult<Option<Box<dyn Any + Send + Sync>>> {
+        if component_info.is_tag {
+            return match value.is_null() {
+                true => Ok(None),
+                false => Err(value.error("tag component must be null")),
+            };
+        }
+
+        let Some(dynamic_descriptor) = &component_info.dynamic_descriptor else {
+            return (component_info.text_read.unwrap())(value, context).map(Some);
+        };
+
+        let bytes = value.as_array_of_len(dynamic_descriptor.size)?.iter().map(|byte| byte.as_integer::<u8>()).collect::<TextResult<Vec<_>>>()?;
+
+        // у компонента без drop значение - просто байты
+        Ok(Some(Box::new(unsafe { DynamicComponent::from_bytes(component_info.component_id, dynamic_descriptor, &bytes) })))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::types::{TextResult, TextError};
+
+    use super::{text_io::{ITextComponent, TextValue, TextContext, parse_text}, super::{EcsDataManager, component::storage_type::StorageType, test_fixtures::{TestComponentC, TestTickComponent, TestNameComponent, register_with}}};
+
+    impl ITextComponent for TestTickComponent {
+        fn write_text(&self, context: &TextContext) -> TextValue {
+            self.0.write_text(context)
+        }
+
+        fn read_text(value: &TextValue, context: &TextContext) -> TextResult<Self> {
+            Ok(Self(u32::read_text(value, context)?))
+        }
+    }
+
+    impl ITextComponent for TestNameComponent {
+        fn write_text(&self, context: &TextContext) -> TextValue {
+            self.0.write_text(context)
+        }
+
+        fn read_text(value: &TextValue, context: &TextContext) -> TextResult<Self> {
+            Ok(Self(String::read_text(value, context)?))
+        }
+    }
+
+    fn text_test_manager() -> EcsDataManager {
+        let mut ecs_data_manager = EcsDataManager::new();
+
+        register_with::<TestTickComponent>(&mut ecs_data_manager, |component_builder| {
+            component_builder.stable_id("test::TestTickComponent").text();
+        });
+
+        register_with::<TestNameComponent>(&mut ecs_data_manager, |component_builder| {
+            component_builder.storage_type(StorageType::SparseSet).stable_id("test::TestNameComponent").text();
+        });
+
+        register_with::<TestComponentC>(&mut ecs_data_manager, |component_builder| {
+            component_builder.tag_default().stable_id("test::TestComponentC");
+        });
+
+        ecs_data_manager
+    }
+
+    #[test]
+    fn test_text_round_trip() {
+        let mut ecs_data_manager = text_test_manager();
+
+        let root_id = ecs_data_manager.spawn((TestTickComponent(1), TestNameComponent("root \"quoted\"\n".to_string()))).unwrap();
+        let removed_id = ecs_data_manager.spawn((TestTickComponent(2),)).unwrap();
+        let child_id = ecs_data_manager.spawn((TestTickComponent(u32::MAX), TestComponentC {})).unwrap();
+        let grandchild_id = ecs_data_manager.spawn((TestComponentC {},)).unwrap();
+
+        ecs_data_manager.remove_entity(removed_id).unwrap();
+        ecs_data_manager.set_parent(child_id, root_id).unwrap();
+        ecs_data_manager.set_parent(grandchild_id, child_id).unwrap();
+
+        let text = ecs_data_manager.save_text().unwrap();
+
+        let mut loaded_manager = text_test_manager();
+        let entity_ids = loaded_manager.load_text(&text).unwrap();
+
+        assert_eq!(loaded_manager.save_text().unwrap(), text);
+
+        assert_eq!(entity_ids.len(), 3);
+        assert_eq!(loaded_manager.get::<TestNameComponent>(entity_ids[0]).unwrap().0, "root \"quoted\"\n");
+        assert_eq!(loaded_manager.get::<TestTickComponent>(entity_ids[1]).unwrap().0, u32::MAX);
+        assert_eq!(loaded_manager.parent(entity_ids[2]).unwrap(), Some(entity_ids[1]));
+        assert_eq!(loaded_manager.iter_descendants(entity_ids[0]).unwrap().collect::<Result<Vec<_>, _>>().unwrap(), entity_ids[1..].to_vec());
+    }
+
+    #[test]
+    fn test_invalid_hierarchy_is_rejected() {
+        let invalid_documents = [
+            // родитель вне документа
+            r#"{ "entities": [ { "anthill_ecs::Parent": null } ] }"#,
+            // Children без Parent и Parent без Children
+            r#"{ "entities": [ { "anthill_ecs::Children": [1] }, {} ] }"#,
+            r#"{ "entities": [ {}, { "anthill_ecs::Parent": 0 } ] }"#,
+            // пустой список дочерних сущностей
+            r#"{ "entities": [ { "anthill_ecs::Children": [] } ] }"#,
+            // дочерняя сущность указана дважды
+            r#"{ "entities": [ { "anthill_ecs::Children": [1, 1] }, { "anthill_ecs::Parent": 0 } ] }"#,
+            // циклы
+            r#"{ "entities": [ { "anthill_ecs::Parent": 0, "anthill_ecs::Children": [0] } ] }"#,
+            r#"{ "entities": [ { "anthill_ecs::Parent": 1, "anthill_ecs::Children": [1] }, { "anthill_ecs::Parent": 0, "anthill_ecs::Children": [0] } ] }"#,
+        ];
+
+        let mut ecs_data_manager = text_test_manager();
+        let empty_text = ecs_data_manager.save_text().unwrap();
+
+        for document in invalid_documents {
+            assert!(matches!(ecs_data_manager.load_text(document), Err(TextError::InvalidHierarchy { .. })), "{document}");
+            assert_eq!(ecs_data_manager.save_text().unwrap(), empty_text);
+        }
+
+        ecs_data_manager.load_text(r#"{ "entities": [ { "anthill_ecs::Children": [1] }, { "anthill_ecs::Parent": 0 } ] }"#).unwrap();
+    }
+
+    /// Детерминированный генератор для перебора искаженных документов
+    struct TestRandom(u64);
+
+    impl TestRandom {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, bound: usize) -> usize {
+            (self.next() % bound.max(1) as u64) as usize
+        }
+    }
+
+    #[test]
+    fn test_text_fuzz() {
+        let mut source_manager = text_test_manager();
+
+        let parent_id = source_manager.spawn((TestTickComponent(7), TestNameComponent("name".to_string()))).unwrap();
+        let child_id = source_manager.spawn((TestComponentC {},)).unwrap();
+        source_manager.set_parent(child_id, parent_id).unwrap();
+
+        let source_chars = source_manager.save_text().unwrap().chars().collect::<Vec<_>>();
+        let alphabet = "{}[]:,\"\\/ \n0123456789-+.eEnultrfas#_:ы".chars().collect::<Vec<_>>();
+
+        let mut random = TestRandom(0x9E37_79B9_7F4A_7C15);
+        let mut ecs_data_manager = text_test_manager();
+
+        for _ in 0..2000 {
+            let mut chars = source_chars.clone();
+
+            for _ in 0..1 + random.below(4) {
+                let position = random.below(chars.len());
+
+                match random.below(4) {
+                    0 => chars[position] = alphabet[random.below(alphabet.len())],
+                    1 => chars.insert(position, alphabet[random.below(alphabet.len())]),
+                    2 => { chars.drain(position..(position + random.below(8)).min(chars.len())); },
+                    _ => chars.truncate(position),
+                }
+
+                if chars.is_empty() {
+                    chars.push(alphabet[random.below(alphabet.len())]);
+                }
+            }
+
+            let text = chars.into_iter().collect::<String>();
+
+            // разбор и загрузка не паникуют, при ошибке менеджер не изменяется
+            _ = parse_text(&text);
+
+            let text_before = ecs_data_manager.save_text().unwrap();
+
+            match ecs_data_manager.load_text(&text) {
+                Ok(_) => ecs_data_manager = text_test_manager(),
+                Err(_) => assert_eq!(ecs_data_manager.save_text().unwrap(), text_before),
+            }
+        }
+    }
+}
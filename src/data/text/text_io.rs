@@ -0,0 +1,662 @@
+use std::{collections::HashMap, fmt::Write, iter::Peekable, str::Chars};
+
+use crate::types::{EntityId, TextResult, TextError};
+
+/// Строка и столбец в тексте, с единицы
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TextPosition {
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TextValueKind {
+    Null,
+    Bool(bool),
+    // целые хранятся отдельно от дробных, чтобы u64 и i64 читались без потери точности
+    Integer(i128),
+    Float(f64),
+    String(String),
+    Array(Vec<TextValue>),
+    Object(Vec<TextField>),
+}
+
+/// Значение текстового формата (JSON, допускаются комментарии `//` и запятая после последнего элемента).
+/// Прочитанное значение помнит свое положение в тексте для сообщений об ошибках
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextValue {
+    pub kind: TextValueKind,
+    pub position: TextPosition,
+}
+
+/// Поле объекта, порядок полей сохраняется
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextField {
+    pub name: String,
+    pub position: TextPosition,
+    pub value: TextValue,
+}
+
+impl TextValue {
+    pub fn new(kind: TextValueKind) -> Self {
+        Self { kind, position: Default::default() }
+    }
+
+    pub fn null() -> Self {
+        Self::new(TextValueKind::Null)
+    }
+
+    pub fn bool(value: bool) -> Self {
+        Self::new(TextValueKind::Bool(value))
+    }
+
+    pub fn integer(value: impl Into<i128>) -> Self {
+        Self::new(TextValueKind::Integer(value.into()))
+    }
+
+    /// Бесконечности и NaN в JSON не представимы, они пишутся строками
+    pub fn float(value: f64) -> Self {
+        match value.is_finite() {
+            true => Self::new(TextValueKind::Float(value)),
+            false => Self::string(format!("{value}")),
+        }
+    }
+
+    pub fn string(value: impl Into<String>) -> Self {
+        Self::new(TextValueKind::String(value.into()))
+    }
+
+    pub fn array(values: impl IntoIterator<Item = TextValue>) -> Self {
+        Self::new(TextValueKind::Array(values.into_iter().collect()))
+    }
+
+    pub fn object<TName: Into<String>>(fields: impl IntoIterator<Item = (TName, TextValue)>) -> Self {
+        Self::new(TextValueKind::Object(fields.into_iter().map(|(name, value)| TextField { name: name.into(), position: Default::default(), value }).collect()))
+    }
+
+    /// Ошибка в значении, с его положением в тексте
+    pub fn error(&self, message: impl Into<String>) -> TextError {
+        TextError::InvalidField { line: self.position.line, column: self.position.column, message: message.into() }
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.kind == TextValueKind::Null
+    }
+
+    pub fn as_bool(&self) -> TextResult<bool> {
+        match &self.kind {
+            TextValueKind::Bool(value) => Ok(*value),
+            _ => Err(self.error("expected bool")),
+        }
+    }
+
+    /// Целое с проверкой диапазона типа
+    pub fn as_integer<TInteger: TryFrom<i128>>(&self) -> TextResult<TInteger> {
+        match &self.kind {
+            TextValueKind::Integer(value) => TInteger::try_from(*value).map_err(|_| self.error(format!("integer out of range of {}", std::any::type_name::<TInteger>()))),
+            _ => Err(self.error("expected integer")),
+        }
+    }
+
+    pub fn as_float(&self) -> TextResult<f64> {
+        match &self.kind {
+            TextValueKind::Float(value) => Ok(*value),
+            TextValueKind::Integer(value) => Ok(*value as f64),
+            TextValueKind::String(value) => match value.as_str() {
+                "NaN" => Ok(f64::NAN),
+                "inf" => Ok(f64::INFINITY),
+                "-inf" => Ok(f64::NEG_INFINITY),
+                _ => Err(self.error("expected number")),
+            },
+            _ => Err(self.error("expected number")),
+        }
+    }
+
+    pub fn as_str(&self) -> TextResult<&str> {
+        match &self.kind {
+            TextValueKind::String(value) => Ok(value),
+            _ => Err(self.error("expected string")),
+        }
+    }
+
+    pub fn as_array(&self) -> TextResult<&[TextValue]> {
+        match &self.kind {
+            TextValueKind::Array(values) => Ok(values),
+            _ => Err(self.error("expected array")),
+        }
+    }
+
+    pub fn as_object(&self) -> TextResult<&[TextField]> {
+        match &self.kind {
+            TextValueKind::Object(fields) => Ok(fields),
+            _ => Err(self.error("expected object")),
+        }
+    }
+
+    /// Массив заданной длины
+    pub fn as_array_of_len(&self, len: usize) -> TextResult<&[TextValue]> {
+        let values = self.as_array()?;
+
+        match values.len() == len {
+            true => Ok(values),
+            false => Err(self.error(format!("expected array of {len} items, found {}", values.len()))),
+        }
+    }
+
+    /// Обязательное поле объекта
+    pub fn field(&self, name: &str) -> TextResult<&TextValue> {
+        self.as_object()?.iter()
+            .find(|field| field.name == name)
+            .map(|field| &field.value)
+            .ok_or_else(|| self.error(format!("missing field: {name}")))
+    }
+
+    /// Необязательное поле объекта
+    pub fn optional_field(&self, name: &str) -> TextResult<Option<&TextValue>> {
+        Ok(self.as_object()?.iter().find(|field| field.name == name).map(|field| &field.value))
+    }
+
+    /// Объект содержит только перечисленные поля, опечатка в имени поля - ошибка
+    pub fn check_fields(&self, names: &[&str]) -> TextResult<()> {
+        match self.as_object()?.iter().find(|field| !names.contains(&field.name.as_str())) {
+            Some(field) => Err(TextError::InvalidField { line: field.position.line, column: field.position.column, message: format!("unknown field: {}", field.name) }),
+            None => Ok(()),
+        }
+    }
+
+    /// Текст с отступами в четыре пробела. Массивы без вложенных массивов и объектов пишутся в одну строку
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        self.write_text(&mut text, 0);
+        text.push('\n');
+        text
+    }
+
+    fn write_text(&self, text: &mut String, indent: usize) {
+        match &self.kind {
+            TextValueKind::Null => text.push_str("null"),
+            TextValueKind::Bool(value) => write!(text, "{value}").unwrap(),
+            TextValueKind::Integer(value) => write!(text, "{value}").unwrap(),
+            // Debug печатает кратчайшее представление, которое читается обратно в то же число, и всегда с точкой или экспонентой
+            TextValueKind::Float(value) => write!(text, "{value:?}").unwrap(),
+            TextValueKind::String(value) => write_text_string(text, value),
+            TextValueKind::Array(values) if values.is_empty() => text.push_str("[]"),
+            TextValueKind::Array(values) if values.iter().all(|value| !matches!(value.kind, TextValueKind::Array(_) | TextValueKind::Object(_))) => {
+                text.push('[');
+
+                values.iter().enumerate().for_each(|(index, value)| {
+                    if index != 0 {
+                        text.push_str(", ");
+                    }
+
+                    value.write_text(text, indent);
+                });
+
+                text.push(']');
+            },
+            TextValueKind::Array(values) => {
+                text.push('[');
+
+                values.iter().enumerate().for_each(|(index, value)| {
+                    text.push_str(if index == 0 { "\n" } else { ",\n" });
+                    write_text_indent(text, indent + 1);
+                    value.write_text(text, indent + 1);
+                });
+
+                text.push('\n');
+                write_text_indent(text, indent);
+                text.push(']');
+            },
+            TextValueKind::Object(fields) if fields.is_empty() => text.push_str("{}"),
+            TextValueKind::Object(fields) => {
+                text.push('{');
+
+                fields.iter().enumerate().for_each(|(index, field)| {
+                    text.push_str(if index == 0 { "\n" } else { ",\n" });
+                    write_text_indent(text, indent + 1);
+                    write_text_string(text, &field.name);
+                    text.push_str(": ");
+                    field.value.write_text(text, indent + 1);
+                });
+
+                text.push('\n');
+                write_text_indent(text, indent);
+                text.push('}');
+            },
+        }
+    }
+}
+
+fn write_text_indent(text: &mut String, indent: usize) {
+    (0..indent).for_each(|_| text.push_str("    "));
+}
+
+fn write_text_string(text: &mut String, value: &str) {
+    text.push('"');
+
+    value.chars().for_each(|char| match char {
+        '"' => text.push_str("\\\""),
+        '\\' => text.push_str("\\\\"),
+        '\n' => text.push_str("\\n"),
+        '\r' => text.push_str("\\r"),
+        '\t' => text.push_str("\\t"),
+        char if char.is_control() => write!(text, "\\u{:04x}", char as u32).unwrap(),
+        char => text.push(char),
+    });
+
+    text.push('"');
+}
+
+/// Разбор текста в значение, ошибки синтаксиса содержат строку и столбец
+pub fn parse_text(text: &str) -> TextResult<TextValue> {
+    let mut text_parser = TextParser { chars: text.chars().peekable(), position: TextPosition { line: 1, column: 1 } };
+
+    let value = text_parser.parse_value()?;
+
+    text_parser.skip_whitespace()?;
+
+    match text_parser.chars.peek() {
+        Some(_) => Err(text_parser.error("unexpected text after value")),
+        None => Ok(value),
+    }
+}
+
+struct TextParser<'a> {
+    chars: Peekable<Chars<'a>>,
+    position: TextPosition,
+}
+
+impl<'a> TextParser<'a> {
+    fn error(&self, message: impl Into<String>) -> TextError {
+        self.error_at(self.position, message)
+    }
+
+    fn error_at(&self, position: TextPosition, message: impl Into<String>) -> TextError {
+        TextError::Syntax { line: position.line, column: position.column, message: message.into() }
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let char = self.chars.next()?;
+
+        match char {
+            '\n' => {
+                self.position.line += 1;
+                self.position.column = 1;
+            },
+            _ => self.position.column += 1,
+        }
+
+        Some(char)
+    }
+
+    fn expect(&mut self, expected: char) -> TextResult<()> {
+        match self.chars.peek().copied() {
+            Some(char) if char == expected => {
+                self.next();
+                Ok(())
+            },
+            Some(char) => Err(self.error(format!("expected '{expected}', found '{char}'"))),
+            None => Err(self.error(format!("expected '{expected}', found end of text"))),
+        }
+    }
+
+    fn skip_whitespace(&mut self) -> TextResult<()> {
+        loop {
+            match self.chars.peek() {
+                Some(char) if char.is_whitespace() => {
+                    self.next();
+                },
+                Some('/') => {
+                    let position = self.position;
+                    self.next();
+
+                    if self.chars.peek() != Some(&'/') {
+                        return Err(self.error_at(position, "unexpected '/'"));
+                    }
+
+                    while !matches!(self.next(), Some('\n') | None) {}
+                },
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn parse_value(&mut self) -> TextResult<TextValue> {
+        self.skip_whitespace()?;
+
+        let position = self.position;
+
+        let kind = match self.chars.peek().copied() {
+            Some('{') => self.parse_object()?,
+            Some('[') => self.parse_array()?,
+            Some('"') => TextValueKind::String(self.parse_string()?),
+            Some(char) if char == '-' || char.is_ascii_digit() => self.parse_number()?,
+            Some(char) if char.is_ascii_alphabetic() => match self.parse_word().as_str() {
+                "null" => TextValueKind::Null,
+                "true" => TextValueKind::Bool(true),
+                "false" => TextValueKind::Bool(false),
+                word => return Err(self.error_at(position, format!("unexpected word: {word}"))),
+            },
+            Some(char) => return Err(self.error(format!("unexpected '{char}'"))),
+            None => return Err(self.error("unexpected end of text")),
+        };
+
+        Ok(TextValue { kind, position })
+    }
+
+    fn parse_word(&mut self) -> String {
+        let mut word = String::new();
+
+        while let Some(char) = self.chars.peek().copied().filter(|char| char.is_ascii_alphanumeric() || *char == '_') {
+            word.push(char);
+            self.next();
+        }
+
+        word
+    }
+
+    fn parse_number(&mut self) -> TextResult<TextValueKind> {
+        let position = self.position;
+        let mut number = String::new();
+
+        while let Some(char) = self.chars.peek().copied().filter(|char| char.is_ascii_digit() || matches!(char, '-' | '+' | '.' | 'e' | 'E')) {
+            number.push(char);
+            self.next();
+        }
+
+        if !number.contains(['.', 'e', 'E']) {
+            if let Ok(value) = number.parse::<i128>() {
+                return Ok(TextValueKind::Integer(value));
+            }
+        }
+
+        number.parse::<f64>().map(TextValueKind::Float).map_err(|_| self.error_at(position, format!("invalid number: {number}")))
+    }
+
+    fn parse_string(&mut self) -> TextResult<String> {
+        self.expect('"')?;
+
+        let mut value = String::new();
+
+        loop {
+            let position = self.position;
+
+            match self.next() {
+                Some('"') => return Ok(value),
+                Some('\\') => value.push(self.parse_escape(position)?),
+                Some('\n') | None => return Err(self.error_at(position, "unterminated string")),
+                Some(char) => value.push(char),
+            }
+        }
+    }
+
+    fn parse_escape(&mut self, position: TextPosition) -> TextResult<char> {
+        match self.next() {
+            Some('"') => Ok('"'),
+            Some('\\') => Ok('\\'),
+            Some('/') => Ok('/'),
+            Some('b') => Ok('\u{8}'),
+            Some('f') => Ok('\u{c}'),
+            Some('n') => Ok('\n'),
+            Some('r') => Ok('\r'),
+            Some('t') => Ok('\t'),
+            Some('u') => {
+                let high = self.parse_hex(position)?;
+
+                // символ вне базовой плоскости записывается суррогатной парой
+                let code = match high {
+                    0xd800..=0xdbff => {
+                        self.expect('\\')?;
+                        self.expect('u')?;
+
+                        let low = self.parse_hex(position)?;
+
+                        if !(0xdc00..=0xdfff).contains(&low) {
+                            return Err(self.error_at(position, "invalid surrogate pair"));
+                        }
+
+                        0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+                    },
+                    code => code,
+                };
+
+                char::from_u32(code).ok_or_else(|| self.error_at(position, "invalid unicode escape"))
+            },
+            _ => Err(self.error_at(position, "invalid escape")),
+        }
+    }
+
+    fn parse_hex(&mut self, position: TextPosition) -> TextResult<u32> {
+        (0..4).try_fold(0, |code, _| {
+            let digit = self.next().and_then(|char| char.to_digit(16)).ok_or_else(|| self.error_at(position, "invalid unicode escape"))?;
+            Ok(code * 16 + digit)
+        })
+    }
+
+    fn parse_array(&mut self) -> TextResult<TextValueKind> {
+        self.expect('[')?;
+
+        let mut values = Vec::new();
+
+        loop {
+            self.skip_whitespace()?;
+
+            if self.chars.peek() == Some(&']') {
+                self.next();
+                return Ok(TextValueKind::Array(values));
+            }
+
+            values.push(self.parse_value()?);
+
+            self.skip_whitespace()?;
+
+            if self.chars.peek() != Some(&']') {
+                self.expect(',')?;
+            }
+        }
+    }
+
+    fn parse_object(&mut self) -> TextResult<TextValueKind> {
+        self.expect('{')?;
+
+        let mut fields: Vec<TextField> = Vec::new();
+
+        loop {
+            self.skip_whitespace()?;
+
+            if self.chars.peek() == Some(&'}') {
+                self.next();
+                return Ok(TextValueKind::Object(fields));
+            }
+
+            let position = self.position;
+            let name = self.parse_string()?;
+
+            if fields.iter().any(|field| field.name == name) {
+                return Err(self.error_at(position, format!("duplicated field: {name}")));
+            }
+
+            self.skip_whitespace()?;
+            self.expect(':')?;
+
+            let value = self.parse_value()?;
+
+            fields.push(TextField { name, position, value });
+
+            self.skip_whitespace()?;
+
+            if self.chars.peek() != Some(&'}') {
+                self.expect(',')?;
+            }
+        }
+    }
+}
+
+/// Ссылки на сущности в тексте - номера сущностей в документе, поэтому документ загружается в любую сцену
+#[derive(Debug, Default)]
+pub struct TextContext {
+    entity_ids: Vec<EntityId>,
+    entity_indexes: HashMap<EntityId, usize>,
+}
+
+impl TextContext {
+    pub fn new(entity_ids: Vec<EntityId>) -> Self {
+        let entity_indexes = entity_ids.iter().enumerate().map(|(entity_index, entity_id)| (*entity_id, entity_index)).collect();
+
+        Self { entity_ids, entity_indexes }
+    }
+
+    pub fn entity_index(&self, entity_id: EntityId) -> Option<usize> {
+        self.entity_indexes.get(&entity_id).copied()
+    }
+
+    pub fn entity_id(&self, entity_index: usize) -> Option<EntityId> {
+        self.entity_ids.get(entity_index).copied()
+    }
+}
+
+/// Компонент, который пишется в текстовый формат. Подключается через `ComponentBuilder::text`
+pub trait ITextComponent: Sized {
+    fn write_text(&self, context: &TextContext) -> TextValue;
+    fn read_text(value: &TextValue, context: &TextContext) -> TextResult<Self>;
+}
+
+macro_rules! integer_into_text_component {
+    ( $( $name:ty ),+ ) => {
+        $(
+            impl ITextComponent for $name {
+                fn write_text(&self, _context: &TextContext) -> TextValue {
+                    TextValue::new(TextValueKind::Integer(*self as i128))
+                }
+
+                fn read_text(value: &TextValue, _context: &TextContext) -> TextResult<Self> {
+                    value.as_integer()
+                }
+            }
+        )+
+    };
+}
+
+integer_into_text_component!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+impl ITextComponent for f64 {
+    fn write_text(&self, _context: &TextContext) -> TextValue {
+        TextValue::float(*self)
+    }
+
+    fn read_text(value: &TextValue, _context: &TextContext) -> TextResult<Self> {
+        value.as_float()
+    }
+}
+
+impl ITextComponent for f32 {
+    // кратчайшая запись f32 читается обратно в то же число
+    fn write_text(&self, _context: &TextContext) -> TextValue {
+        TextValue::float(self.to_string().parse().unwrap())
+    }
+
+    fn read_text(value: &TextValue, _context: &TextContext) -> TextResult<Self> {
+        Ok(value.as_float()? as f32)
+    }
+}
+
+impl ITextComponent for bool {
+    fn write_text(&self, _context: &TextContext) -> TextValue {
+        TextValue::bool(*self)
+    }
+
+    fn read_text(value: &TextValue, _context: &TextContext) -> TextResult<Self> {
+        value.as_bool()
+    }
+}
+
+impl ITextComponent for String {
+    fn write_text(&self, _context: &TextContext) -> TextValue {
+        TextValue::string(self.clone())
+    }
+
+    fn read_text(value: &TextValue, _context: &TextContext) -> TextResult<Self> {
+        Ok(value.as_str()?.to_string())
+    }
+}
+
+/// Идентификатор сущности, которой нет в документе. Такая сущность никогда не жива
+const DANGLING_ENTITY_ID: EntityId = EntityId { id: usize::MAX, version: usize::MAX };
+
+/// Ссылка на сущность вне документа пишется как null
+impl ITextComponent for EntityId {
+    fn write_text(&self, context: &TextContext) -> TextValue {
+        context.entity_index(*self).map_or(TextValue::null(), |entity_index| TextValue::integer(entity_index as u64))
+    }
+
+    fn read_text(value: &TextValue, context: &TextContext) -> TextResult<Self> {
+        if value.is_null() {
+            return Ok(DANGLING_ENTITY_ID);
+        }
+
+        let entity_index = value.as_integer::<usize>()?;
+
+        context.entity_id(entity_index).ok_or_else(|| value.error(format!("no entity with index: {entity_index}")))
+    }
+}
+
+impl<T: ITextComponent> ITextComponent for Vec<T> {
+    fn write_text(&self, context: &TextContext) -> TextValue {
+        TextValue::array(self.iter().map(|item| item.write_text(context)))
+    }
+
+    fn read_text(value: &TextValue, context: &TextContext) -> TextResult<Self> {
+        value.as_array()?.iter().map(|item| T::read_text(item, context)).collect()
+    }
+}
+
+impl<T: ITextComponent> ITextComponent for Option<T> {
+    fn write_text(&self, context: &TextContext) -> TextValue {
+        self.as_ref().map_or(TextValue::null(), |value| value.write_text(context))
+    }
+
+    fn read_text(value: &TextValue, context: &TextContext) -> TextResult<Self> {
+        match value.is_null() {
+            true => Ok(None),
+            false => Ok(Some(T::read_text(value, context)?)),
+        }
+    }
+}
+
+impl<T: ITextComponent, const N: usize> ITextComponent for [T; N] {
+    fn write_text(&self, context: &TextContext) -> TextValue {
+        TextValue::array(self.iter().map(|item| item.write_text(context)))
+    }
+
+    fn read_text(value: &TextValue, context: &TextContext) -> TextResult<Self> {
+        let items = value.as_array_of_len(N)?.iter().map(|item| T::read_text(item, context)).collect::<TextResult<Vec<_>>>()?;
+        Ok(items.try_into().unwrap_or_else(|_| unreachable!()))
+    }
+}
+
+macro_rules! tuple_into_text_component {
+    ( $( $name:ident ),+ ) => {
+        impl<$($name: ITextComponent),+> ITextComponent for ($($name,)+)
+        {
+            #[allow(non_snake_case)]
+            fn write_text(&self, context: &TextContext) -> TextValue {
+                let ($($name,)+) = self;
+                TextValue::array([$($name.write_text(context),)+])
+            }
+
+            fn read_text(value: &TextValue, context: &TextContext) -> TextResult<Self> {
+                let mut items = value.as_array_of_len([$(stringify!($name),)+].len())?.iter();
+                Ok(($($name::read_text(items.next().unwrap(), context)?,)+))
+            }
+        }
+    };
+}
+
+tuple_into_text_component!(T0);
+tuple_into_text_component!(T0, T1);
+tuple_into_text_component!(T0, T1, T2);
+tuple_into_text_component!(T0, T1, T2, T3);
+tuple_into_text_component!(T0, T1, T2, T3, T4);
+tuple_into_text_component!(T0, T1, T2, T3, T4, T5);
+tuple_into_text_component!(T0, T1, T2, T3, T4, T5, T6);
+tuple_into_text_component!(T0, T1, T2, T3, T4, T5, T6, T7);
@@ -1,6 +1,4 @@
 #![feature(downcast_unchecked)]
-#![feature(drain_filter)]
-#![feature(hash_drain_filter)]
 #![feature(trait_alias)]
 
 pub mod types;
@@ -0,0 +1,24 @@
+use std::ops::Deref;
+
+#[derive(Debug, Copy, Clone, PartialEq, Hash, Eq)]
+pub struct ArchetypeId {
+    pub (crate) id: usize,
+}
+
+impl ArchetypeId {
+    pub fn new(id: usize) -> Self {
+        Self { id }
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+}
+
+impl Deref for ArchetypeId {
+    type Target = usize;
+
+    fn deref(&self) -> &Self::Target {
+        &self.id
+    }
+}
\ No newline at end of file
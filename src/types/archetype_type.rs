@@ -4,6 +4,7 @@ use crate::data::component::component_info::ComponentInfo;
 
 use super::ComponentId;
 
+/// Сигнатура архетипа. Идентификаторы компонентов всегда отсортированы, поэтому один и тот же набор компонентов дает один и тот же архетип независимо от порядка
 #[derive(Debug, Default, Hash, Clone, PartialEq, Eq)]
 pub struct ArchetypeType {
     pub (crate) component_ids: Vec<ComponentId>
@@ -15,8 +16,7 @@ impl ArchetypeType {
     pub fn contain<TComponent>(&self, component_id: ComponentId) -> bool { self.component_ids.contains(&component_id) }
     pub (crate) fn check(&self, registered_components_map: &HashMap<ComponentId, ComponentInfo>) -> Option<ComponentId> {
         self.component_ids.iter()
-            .find(|x| !registered_components_map.contains_key(*x))
-            .map(|x| *x)
+            .find(|x| !registered_components_map.contains_key(*x)).copied()
     }
 }
 
@@ -29,7 +29,8 @@ impl Deref for ArchetypeType {
 }
 
 impl From<Vec<ComponentId>> for ArchetypeType {
-    fn from(component_ids: Vec<ComponentId>) -> Self {
-        Self { component_ids: component_ids }
+    fn from(mut component_ids: Vec<ComponentId>) -> Self {
+        component_ids.sort();
+        Self { component_ids }
     }
 }
\ No newline at end of file
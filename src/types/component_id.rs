@@ -1,34 +1,50 @@
-use std::{ops::Deref, any::TypeId};
+use std::any::TypeId;
 
-#[derive(Debug, Copy, Clone, PartialEq, Hash, Eq)]
+/// Источник идентификатора: тип Rust или компонент, описанный во время выполнения
+#[derive(Debug, Copy, Clone, PartialEq, Hash, Eq, PartialOrd, Ord)]
+pub enum ComponentKind {
+    Type(TypeId),
+    // порядковый номер, выдается EcsDataManager при регистрации
+    Dynamic(usize),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Hash, Eq, PartialOrd, Ord)]
 pub struct ComponentId{
-    pub(crate) id: TypeId,
+    pub(crate) id: ComponentKind,
 }
 
 impl ComponentId {
     pub fn new(id: TypeId) -> Self {
-        Self { id }
+        Self { id: ComponentKind::Type(id) }
     }
 
     pub fn from_type<TComponent: 'static>() -> Self {
-        Self { id: TypeId::of::<TComponent>() }
+        Self { id: ComponentKind::Type(TypeId::of::<TComponent>()) }
+    }
+
+    pub (crate) fn new_dynamic(index: usize) -> Self {
+        Self { id: ComponentKind::Dynamic(index) }
     }
 
-    pub fn id(&self) -> TypeId {
+    pub fn id(&self) -> ComponentKind {
         self.id
     }
-}
 
-impl Deref for ComponentId {
-    type Target = TypeId;
+    /// None для компонента, описанного во время выполнения
+    pub fn type_id(&self) -> Option<TypeId> {
+        match self.id {
+            ComponentKind::Type(type_id) => Some(type_id),
+            ComponentKind::Dynamic(_) => None,
+        }
+    }
 
-    fn deref(&self) -> &Self::Target {
-        &self.id
+    pub fn is_dynamic(&self) -> bool {
+        matches!(self.id, ComponentKind::Dynamic(_))
     }
 }
 
 impl From<TypeId> for ComponentId {
     fn from(type_id: TypeId) -> Self {
-        Self { id: type_id }
+        Self::new(type_id)
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,56 @@
+/// Битовое множество плотных индексов компонентов (см. `ComponentInfo::index`)
+#[derive(Debug, Default, Hash, Clone, PartialEq, Eq)]
+pub struct ComponentsBitSet {
+    pub (crate) words: Vec<u64>,
+}
+
+impl ComponentsBitSet {
+    const WORD_BITS: usize = u64::BITS as usize;
+
+    pub fn empty() -> Self { Self { words: Default::default() } }
+
+    pub fn insert(&mut self, index: usize) {
+        let word_index = index / Self::WORD_BITS;
+
+        if self.words.len() <= word_index {
+            self.words.resize(word_index + 1, 0);
+        }
+
+        self.words[word_index] |= 1 << (index % Self::WORD_BITS);
+    }
+
+    pub fn remove(&mut self, index: usize) {
+        if let Some(word) = self.words.get_mut(index / Self::WORD_BITS) {
+            *word &= !(1 << (index % Self::WORD_BITS));
+        }
+    }
+
+    pub fn contains(&self, index: usize) -> bool {
+        self.words.get(index / Self::WORD_BITS).is_some_and(|word| word & (1 << (index % Self::WORD_BITS)) != 0)
+    }
+
+    /// Все биты `other` есть в текущем множестве
+    pub fn contains_all(&self, other: &ComponentsBitSet) -> bool {
+        other.words.iter().enumerate().all(|(word_index, other_word)| {
+            let word = self.words.get(word_index).map_or(0, |word| *word);
+            word & other_word == *other_word
+        })
+    }
+
+    /// Множества имеют хотя бы один общий бит
+    pub fn intersects(&self, other: &ComponentsBitSet) -> bool {
+        self.words.iter().zip(other.words.iter()).any(|(word, other_word)| word & other_word != 0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|word| *word == 0)
+    }
+}
+
+impl FromIterator<usize> for ComponentsBitSet {
+    fn from_iter<T: IntoIterator<Item = usize>>(iter: T) -> Self {
+        let mut components_bit_set = Self::empty();
+        iter.into_iter().for_each(|index| components_bit_set.insert(index));
+        components_bit_set
+    }
+}
\ No newline at end of file
@@ -0,0 +1,42 @@
+use super::EntityId;
+
+/// Идентификаторы подряд идущих слотов, выделенных одной пачкой. У новых слотов нулевое поколение
+#[derive(Debug, Clone, Copy, PartialEq, Hash, Eq)]
+pub struct EntityIdRange {
+    pub (crate) start: usize,
+    pub (crate) end: usize,
+}
+
+impl EntityIdRange {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self {
+            start,
+            end,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    pub fn contains(&self, entity_id: EntityId) -> bool {
+        (self.start..self.end).contains(&entity_id.id) && entity_id.version == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = EntityId> {
+        (self.start..self.end).map(EntityId::new)
+    }
+}
+
+impl IntoIterator for EntityIdRange {
+    type Item = EntityId;
+    type IntoIter = std::iter::Map<std::ops::Range<usize>, fn(usize) -> EntityId>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        (self.start..self.end).map(EntityId::new as fn(usize) -> EntityId)
+    }
+}
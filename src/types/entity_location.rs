@@ -0,0 +1,31 @@
+use super::ArchetypeId;
+
+/// Положение сущности: архетип, номер чанка в архетипе и строка в чанке
+#[derive(Debug, Clone, Copy, PartialEq, Hash, Eq)]
+pub struct EntityLocation {
+    pub (crate) archetype_id: ArchetypeId,
+    pub (crate) chunk_index: usize,
+    pub (crate) row: usize,
+}
+
+impl EntityLocation {
+    pub fn new(archetype_id: ArchetypeId, chunk_index: usize, row: usize) -> Self {
+        Self {
+            archetype_id,
+            chunk_index,
+            row,
+        }
+    }
+
+    pub fn archetype_id(&self) -> ArchetypeId {
+        self.archetype_id
+    }
+
+    pub fn chunk_index(&self) -> usize {
+        self.chunk_index
+    }
+
+    pub fn row(&self) -> usize {
+        self.row
+    }
+}
\ No newline at end of file
@@ -4,9 +4,24 @@ pub use component_id::*;
 mod archetype_type;
 pub use archetype_type::*;
 
+mod archetype_id;
+pub use archetype_id::*;
+
+mod components_bit_set;
+pub use components_bit_set::*;
+
 mod entity_id;
 pub use entity_id::*;
 
+mod entity_id_range;
+pub use entity_id_range::*;
+
+mod stable_component_id;
+pub use stable_component_id::*;
+
+mod entity_location;
+pub use entity_location::*;
+
 mod scene_id;
 pub use scene_id::*;
 
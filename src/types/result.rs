@@ -2,7 +2,7 @@ use std::any::TypeId;
 
 use thiserror::Error;
 
-use super::{ArchetypeType, ComponentId};
+use super::{ArchetypeType, ComponentId, EntityId, StableComponentId, SceneId};
 
 
 #[derive(Debug, Error)]
@@ -29,10 +29,156 @@ pub enum BuildSystemError {
 
 pub type BuildSystemResult<T> = Result<T, BuildSystemError>;
 
+#[derive(Debug, Error)]
+pub enum RegisterComponentError {
+    #[error("Stable id [{stable_id}] is already used by component: [{component_id:?}]")]
+    DuplicateStableId { stable_id: StableComponentId, component_id: ComponentId },
+    #[error("Component [{component_id:?}] is already registered with stable id: [{registered:?}], requested: [{requested}]")]
+    StableIdMismatch { component_id: ComponentId, registered: Option<StableComponentId>, requested: StableComponentId },
+    #[error("Shared storage of component [{component_id:?}] must be set with ComponentBuilder::shared")]
+    SharedStorageRequiresShared { component_id: ComponentId },
+    #[error("Invalid layout of dynamic component [{name}]: size {size}, align {align}")]
+    InvalidDynamicLayout { name: String, size: usize, align: usize },
+    #[error("Stable name [{stable_id}] is read back as a numeric stable id")]
+    InvalidStableName { stable_id: StableComponentId },
+}
+
+pub type RegisterComponentResult<T> = Result<T, RegisterComponentError>;
+
 #[derive(Debug, Error)]
 pub enum AddEntityError {
     #[error("Component not registered: [{component_id:?}]")]
-    ComponentNotRegistered { component_id: ComponentId }
+    ComponentNotRegistered { component_id: ComponentId },
+    #[error("Component duplicated: [{component_id:?}]")]
+    ComponentDuplicated { component_id: ComponentId },
+    #[error("Prefab component can't be cloned: [{component_id:?}]")]
+    ComponentNotCloneable { component_id: ComponentId },
+    #[error("Hierarchy component is set only with set_parent: [{component_id:?}]")]
+    HierarchyComponent { component_id: ComponentId },
+    #[error("Component is locked: [{component_id:?}]")]
+    ComponentLocked { component_id: ComponentId },
+    #[error(transparent)]
+    Entity(#[from] EntityError),
+}
+
+pub type AddEntityResult<T> = Result<T, AddEntityError>;
+
+#[derive(Debug, Error)]
+pub enum EntityError {
+    #[error("No such entity: [{entity_id:?}]")]
+    NoSuchEntity { entity_id: EntityId },
+    #[error("Component not registered: [{component_id:?}]")]
+    ComponentNotRegistered { component_id: ComponentId },
+    #[error("Entity can't be a child of itself or its descendant: [{entity_id:?}] parent: [{parent_id:?}]")]
+    HierarchyCycle { entity_id: EntityId, parent_id: EntityId },
+    #[error("Entity [{entity_id:?}] has no component: [{component_id:?}]")]
+    MissingComponent { entity_id: EntityId, component_id: ComponentId },
+    #[error("Component of entity [{entity_id:?}] is locked: [{component_id:?}]")]
+    ComponentLocked { entity_id: EntityId, component_id: ComponentId },
+    #[error("Tag component has no value, use has_component: [{component_id:?}]")]
+    TagComponent { component_id: ComponentId },
+    #[error("Hierarchy component is changed only with set_parent and remove_parent: [{component_id:?}]")]
+    HierarchyComponent { component_id: ComponentId },
+    #[error("Shared component can't be changed in place, insert a new value: [{component_id:?}]")]
+    SharedComponentReadOnly { component_id: ComponentId },
+    #[error("Component requested twice: [{component_id:?}]")]
+    ComponentDuplicated { component_id: ComponentId },
+    #[error("Component is not dynamic: [{component_id:?}]")]
+    NotDynamicComponent { component_id: ComponentId },
+    #[error("Dynamic component [{component_id:?}] expects {expected} bytes, found {actual}")]
+    DynamicComponentSizeMismatch { component_id: ComponentId, expected: usize, actual: usize },
+}
+
+pub type EntityResult<T> = Result<T, EntityError>;
+
+#[derive(Debug, Error)]
+pub enum ResourceError {
+    #[error("No such resource: [{resource_name:?}] [{resource_type_id:?}]")]
+    NoSuchResource { resource_name: String, resource_type_id: TypeId },
+    #[error("Resource is locked: [{resource_name:?}] [{resource_type_id:?}]")]
+    ResourceLocked { resource_name: String, resource_type_id: TypeId },
+    #[error("Event is not registered: [{event_name:?}] [{event_type_id:?}]")]
+    EventNotRegistered { event_name: String, event_type_id: TypeId },
+}
+
+pub type ResourceResult<T> = Result<T, ResourceError>;
+
+#[derive(Debug, Error)]
+pub enum QueryError {
+    #[error("Component [{component_id:?}] is locked by a running system")]
+    ComponentLocked { component_id: ComponentId },
+}
+
+pub type QueryResult<T> = Result<T, QueryError>;
+
+#[derive(Debug, Error)]
+pub enum CommandError {
+    #[error(transparent)]
+    AddEntity(#[from] AddEntityError),
+    #[error(transparent)]
+    Entity(#[from] EntityError),
+}
+
+pub type CommandResult<T> = Result<T, CommandError>;
+
+#[derive(Debug, Error)]
+pub enum UpdateError {
+    #[error("Query of system [{system_type_id:?}] failed, the system is skipped: {source}")]
+    Query { system_type_id: TypeId, source: QueryError },
+    #[error(transparent)]
+    Command(#[from] CommandError),
+}
+
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    #[error("Snapshot io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Not an ecs snapshot")]
+    InvalidHeader,
+    #[error("Unsupported snapshot version: {version}")]
+    UnsupportedVersion { version: u32 },
+    #[error("Unexpected end of snapshot at byte {position}")]
+    UnexpectedEnd { position: usize },
+    #[error("Invalid snapshot data at byte {position}: {message}")]
+    InvalidData { position: usize, message: String },
+    #[error("Component [{component_name}] [{component_id:?}] has no stable id")]
+    MissingStableId { component_id: ComponentId, component_name: String },
+    #[error("Component [{component_name}] [{component_id:?}] is not serializable")]
+    ComponentNotSerializable { component_id: ComponentId, component_name: String },
+    #[error("Unknown component in snapshot: [{stable_id}]")]
+    UnknownComponent { stable_id: StableComponentId },
+    #[error("Component [{stable_id}] is registered with another storage type than in snapshot")]
+    StorageTypeMismatch { stable_id: StableComponentId },
+    #[error("Snapshot can be loaded only into EcsDataManager without entities")]
+    NotEmpty,
+    #[error("Component [{component_id:?}] is locked by a running system")]
+    ComponentLocked { component_id: ComponentId },
+}
+
+pub type SnapshotResult<T> = Result<T, SnapshotError>;
+
+#[derive(Debug, Error)]
+pub enum TextError {
+    #[error("{line}:{column}: {message}")]
+    Syntax { line: usize, column: usize, message: String },
+    #[error("{line}:{column}: invalid field: {message}")]
+    InvalidField { line: usize, column: usize, message: String },
+    #[error("{line}:{column}: unknown component: [{stable_id}]")]
+    UnknownComponent { line: usize, column: usize, stable_id: StableComponentId },
+    #[error("Component [{component_name}] [{component_id:?}] has no stable id")]
+    MissingStableId { component_id: ComponentId, component_name: String },
+    #[error("Component [{component_name}] [{component_id:?}] has no text format")]
+    ComponentNotSerializable { component_id: ComponentId, component_name: String },
+    #[error("No such scene: [{scene_id:?}]")]
+    NoSuchScene { scene_id: SceneId },
+    #[error("Component [{component_id:?}] is locked by a running system")]
+    ComponentLocked { component_id: ComponentId },
+    #[error("Invalid hierarchy: {message}")]
+    InvalidHierarchy { message: String },
+    #[error("Scene [{scene_id:?}] is locked by a running update")]
+    SceneLocked { scene_id: SceneId },
+    #[error(transparent)]
+    AddEntity(#[from] AddEntityError),
 }
 
-pub type AddEntityResult<T> = Result<T, AddEntityError>;
\ No newline at end of file
+pub type TextResult<T> = Result<T, TextError>;
@@ -0,0 +1,48 @@
+use std::{fmt::Display, str::FromStr, convert::Infallible};
+
+/// Идентификатор компонента, не зависящий от сборки: для файлов сохранений и сети.
+/// Задается при регистрации компонента именем или числом
+#[derive(Debug, Clone, PartialEq, Hash, Eq, PartialOrd, Ord)]
+pub enum StableComponentId {
+    Name(String),
+    Id(u64),
+}
+
+impl From<&str> for StableComponentId {
+    fn from(name: &str) -> Self {
+        Self::Name(name.to_string())
+    }
+}
+
+impl From<String> for StableComponentId {
+    fn from(name: String) -> Self {
+        Self::Name(name)
+    }
+}
+
+impl From<u64> for StableComponentId {
+    fn from(id: u64) -> Self {
+        Self::Id(id)
+    }
+}
+
+impl Display for StableComponentId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StableComponentId::Name(name) => write!(f, "{name}"),
+            StableComponentId::Id(id) => write!(f, "#{id}"),
+        }
+    }
+}
+
+/// Обратно Display: `#123` - номер, остальное - имя
+impl FromStr for StableComponentId {
+    type Err = Infallible;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text.strip_prefix('#').and_then(|id| id.parse::<u64>().ok()) {
+            Some(id) => Ok(Self::Id(id)),
+            None => Ok(Self::Name(text.to_string())),
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use std::{collections::HashMap, sync::Arc};
 
-use crate::{types::SceneId, data::EcsDataManager, behavior::EcsBehaviorManager};
+use crate::{types::{SceneId, EntityId, TextResult, TextError}, data::EcsDataManager, behavior::EcsBehaviorManager};
 
 #[derive(Debug, Default)]
 pub (crate) struct Scene {
@@ -16,6 +16,12 @@ pub struct World {
     scene_count: u32,
 }
 
+impl Default for World {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl World {
     pub fn new() -> Self {
         Self {
@@ -32,7 +38,7 @@ impl World {
             new_entity_id
         });
 
-        self.scenes.insert(scene_id.clone(), Default::default());
+        self.scenes.insert(scene_id, Default::default());
 
         scene_id
     }
@@ -44,4 +50,20 @@ impl World {
     pub fn get_scene_behavior(&self, scene_id: &SceneId) -> Option<Arc<std::sync::RwLock<EcsBehaviorManager>>> {
         self.scenes.get(scene_id).map(|scene| scene.ecs_behavior_manager.clone())
     }
-}
\ No newline at end of file
+
+    /// Текстовое описание сущностей сцены, см. `EcsDataManager::save_text`. Во время обновления сцены возвращает `TextError::SceneLocked`
+    pub fn save_scene_text(&self, scene_id: &SceneId) -> TextResult<String> {
+        let ecs_data_manager = self.get_scene_data(scene_id).ok_or(TextError::NoSuchScene { scene_id: *scene_id })?;
+        let ecs_data_manager_read_lock = ecs_data_manager.try_read().map_err(|_| TextError::SceneLocked { scene_id: *scene_id })?;
+
+        ecs_data_manager_read_lock.save_text()
+    }
+
+    /// Создание в сцене сущностей из текстового описания, см. `EcsDataManager::load_text`. Во время обновления сцены возвращает `TextError::SceneLocked`
+    pub fn load_scene_text(&self, scene_id: &SceneId, text: &str) -> TextResult<Vec<EntityId>> {
+        let ecs_data_manager = self.get_scene_data(scene_id).ok_or(TextError::NoSuchScene { scene_id: *scene_id })?;
+        let mut ecs_data_manager_write_lock = ecs_data_manager.try_write().map_err(|_| TextError::SceneLocked { scene_id: *scene_id })?;
+
+        ecs_data_manager_write_lock.load_text(text)
+    }
+}